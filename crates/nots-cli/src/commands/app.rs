@@ -1,13 +1,30 @@
-use crate::State;
+use std::collections::HashMap;
+
+use crate::{
+    utils::{is_interactive, parse_key_value, print_table},
+    State,
+};
 use clap::Subcommand;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use colored::Colorize;
+use inquire::{validator::Validation, Confirm};
+use nots_client::{
+    api::{CreateAppRequest, CreateAppResponse, UpdateAppRequest},
+    models::{
+        App as AppModel, DockerRuntimeOptions, GitSource, HealthCheck, HealthCheckKind, Match, Repo, ResourceLimits,
+        WorkerRuntimeOptions, WorkerSettings,
+    },
+    utils::check_response,
+    Client,
+};
 
 pub async fn run(args: &AppCommand, state: State) -> Result<()> {
     let app = App(state);
     match args {
-        AppCommand::Create => app.create().await,
+        AppCommand::Create(args) => app.create(args).await,
         AppCommand::List => app.list().await,
-        AppCommand::Edit { name } => app.edit(name).await,
+        AppCommand::Edit(args) => app.edit(args).await,
+        AppCommand::Delete { name, yes } => app.delete(name, *yes).await,
     }
 }
 
@@ -15,24 +32,647 @@ struct App(State);
 
 #[derive(Debug, Subcommand, Clone)]
 pub enum AppCommand {
-    Create,
+    /// Create a new app
+    Create(Box<CreateCommand>),
+    /// List all apps
     List,
-    Edit {
+    /// Change the settings of an app
+    Edit(Box<EditCommand>),
+    /// Delete an app
+    Delete {
         #[clap(short, long)]
         name: String,
+
+        #[clap(short, long)]
+        /// Don't ask for confirmation
+        yes: bool,
     },
 }
 
+#[derive(Debug, clap::Args, Clone)]
+pub struct CreateCommand {
+    #[clap(short, long)]
+    /// Name of the app, prompted for if not set
+    name: Option<String>,
+
+    #[clap(flatten)]
+    settings: AppSettingsArgs,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct EditCommand {
+    #[clap(short, long)]
+    name: String,
+
+    #[clap(long)]
+    /// Rename the app
+    rename: Option<String>,
+
+    #[clap(long = "unset-env", value_name = "KEY")]
+    /// Remove an environment variable, can be repeated
+    unset_env: Vec<String>,
+
+    #[clap(flatten)]
+    settings: AppSettingsArgs,
+}
+
+#[derive(Debug, clap::Args, Clone)]
+pub struct AppSettingsArgs {
+    #[clap(long = "hostname")]
+    /// Hostname to route to this app, can be repeated (glob, or `regex:<pattern>`), `-` removes all
+    hostnames: Vec<String>,
+
+    #[clap(long = "route")]
+    /// Route to match, can be repeated (glob, or `regex:<pattern>`), `-` removes all
+    routes: Vec<String>,
+
+    #[clap(long)]
+    /// Higher priority routes are matched first
+    route_priority: Option<i16>,
+
+    #[clap(long)]
+    /// File to pass to the command, e.g. `index.js`
+    main: Option<String>,
+
+    #[clap(long)]
+    /// Command to start the app with
+    command: Option<String>,
+
+    #[clap(long)]
+    /// Command to run before starting the app
+    prepare: Option<String>,
+
+    #[clap(long)]
+    /// Port the app listens on, process workers always use the one in $PORT
+    port: Option<u16>,
+
+    #[clap(long = "env", value_parser = parse_key_value)]
+    /// Environment variable as KEY=VALUE, can be repeated
+    env: Vec<(String, String)>,
+
+    #[clap(long, value_parser = parse_limit::<f64>)]
+    /// CPU time each worker may use in cores, e.g. `0.5`, or `unlimited`
+    cpus: Option<LimitFlag<f64>>,
+
+    #[clap(long, value_parser = parse_limit::<u64>)]
+    /// Relative CPU weight of the workers when CPUs are contended, 1024 by default
+    cpu_shares: Option<LimitFlag<u64>>,
+
+    #[clap(long, value_parser = parse_size_limit)]
+    /// Memory each worker may use, e.g. `512m`, or `unlimited`
+    memory: Option<LimitFlag<u64>>,
+
+    #[clap(long, value_parser = parse_size_limit)]
+    /// Swap each worker may use on top of its memory, `0` disables swap
+    swap: Option<LimitFlag<u64>>,
+
+    #[clap(long, value_parser = parse_limit::<u64>)]
+    /// Number of processes and threads each worker may use
+    pids: Option<LimitFlag<u64>>,
+
+    #[clap(long)]
+    /// Runtime to use, e.g. `bun`, or `process` to run without docker
+    runtime: Option<String>,
+
+    #[clap(long)]
+    /// Version of the runtime
+    runtime_version: Option<String>,
+
+    #[clap(long, conflicts_with_all = ["runtime", "runtime_version"])]
+    /// Custom docker image to use instead of a runtime, e.g. `nginx:latest`
+    image: Option<String>,
+
+    #[clap(long)]
+    /// Stop the app after this many seconds without requests and start it again on the next one, 0 to keep it running
+    idle_timeout: Option<u64>,
+
+    #[clap(long)]
+    /// Pause the app after this many seconds without requests and resume it on the next one, 0 to never pause it
+    pause_timeout: Option<u64>,
+
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    /// Number of workers requests are balanced across
+    replicas: Option<u32>,
+
+    #[clap(long, value_parser = parse_health_check)]
+    /// Path to check with GET requests before routing traffic to a worker, `tcp` to only check the port, `none` to disable
+    health_check: Option<HealthCheckFlag>,
+
+    #[clap(long)]
+    /// Repository to watch, new commits are deployed automatically (https, or ssh with --deploy-key), `none` to stop watching it
+    git: Option<String>,
+
+    #[clap(long)]
+    /// Deploy key to fetch a private repository with, see `nots repo key create`
+    deploy_key: Option<String>,
+
+    #[clap(long)]
+    /// Branch of the repository to deploy, defaults to `main`
+    branch: Option<String>,
+
+    #[clap(long)]
+    /// Directory within the repository to deploy
+    git_path: Option<String>,
+}
+
+impl AppSettingsArgs {
+    fn has_worker_settings(&self) -> bool {
+        self.main.is_some()
+            || self.command.is_some()
+            || self.prepare.is_some()
+            || self.port.is_some()
+            || !self.env.is_empty()
+            || self.cpus.is_some()
+            || self.cpu_shares.is_some()
+            || self.memory.is_some()
+            || self.swap.is_some()
+            || self.pids.is_some()
+    }
+
+    fn has_runtime(&self) -> bool {
+        self.runtime.is_some() || self.runtime_version.is_some() || self.image.is_some()
+    }
+
+    /// Apply the given flags on top of existing worker settings
+    fn worker_settings(&self, mut settings: WorkerSettings) -> WorkerSettings {
+        if let Some(main) = &self.main {
+            settings.main = Some(main.clone());
+        }
+        if let Some(command) = &self.command {
+            settings.command = Some(command.clone());
+        }
+        if let Some(prepare) = &self.prepare {
+            settings.prepare = Some(prepare.clone());
+        }
+        if let Some(port) = self.port {
+            settings.port = Some(port);
+        }
+        settings.env.extend(self.env.iter().cloned());
+
+        let limits = &mut settings.limits;
+        if let Some(LimitFlag(cpus)) = self.cpus {
+            limits.cpus = cpus;
+        }
+        if let Some(LimitFlag(cpu_shares)) = self.cpu_shares {
+            limits.cpu_shares = cpu_shares;
+        }
+        if let Some(LimitFlag(memory)) = self.memory {
+            limits.memory = memory;
+        }
+        if let Some(LimitFlag(swap)) = self.swap {
+            limits.swap = swap;
+        }
+        if let Some(LimitFlag(pids)) = self.pids {
+            limits.pids = pids;
+        }
+        settings
+    }
+
+    fn runtime(&self) -> WorkerRuntimeOptions {
+        if let Some(image) = &self.image {
+            let (image, tag) = image.split_once(':').unwrap_or((image, "latest"));
+            return WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Custom {
+                image: image.to_string(),
+                tag: tag.to_string(),
+            });
+        }
+
+        let runtime = self.runtime.clone().unwrap_or("bun".to_string());
+        runtime_options(&runtime, self.runtime_version.clone())
+    }
+
+    /// Change the kind of the existing health check, keeping its timing
+    fn health_check(&self, existing: Option<&HealthCheck>) -> Option<Option<HealthCheck>> {
+        let HealthCheckFlag(kind) = self.health_check.clone()?;
+        Some(kind.map(|kind| HealthCheck {
+            kind,
+            ..existing.cloned().unwrap_or_default()
+        }))
+    }
+
+    fn has_git(&self) -> bool {
+        self.git.is_some() || self.deploy_key.is_some() || self.branch.is_some() || self.git_path.is_some()
+    }
+
+    /// Apply the given flags on top of the existing git source, `Some(None)` removes it
+    fn git_source(&self, existing: Option<&GitSource>) -> Result<Option<Option<GitSource>>> {
+        if !self.has_git() {
+            return Ok(None);
+        }
+
+        if self.git.as_deref() == Some("none") {
+            if self.deploy_key.is_some() || self.branch.is_some() || self.git_path.is_some() {
+                bail!("--branch, --git-path and --deploy-key can't be combined with --git=none");
+            }
+            return Ok(Some(None));
+        }
+
+        let url = match (&self.git, existing) {
+            (Some(url), _) => url.clone(),
+            (None, Some(existing)) => existing.repo.url().to_string(),
+            (None, None) => bail!("--branch, --git-path and --deploy-key require a repository, set it with --git"),
+        };
+
+        let key = match (&self.deploy_key, existing.map(|e| &e.repo)) {
+            (Some(id), _) => Some(id.clone()),
+            // keep using the existing key unless the repository changes
+            (None, Some(Repo::DeployKey { id, .. })) if self.git.is_none() => Some(id.clone()),
+            _ => None,
+        };
+
+        let repo = match key {
+            Some(id) => Repo::DeployKey { url, id },
+            None => Repo::PublicHttps { url },
+        };
+
+        Ok(Some(Some(GitSource {
+            repo,
+            branch: (self.branch.clone())
+                .or(existing.map(|e| e.branch.clone()))
+                .unwrap_or("main".to_string()),
+            path: self.git_path.clone().or(existing.and_then(|e| e.path.clone())),
+        })))
+    }
+}
+
 impl App {
-    async fn create(&self) -> Result<()> {
+    async fn create(&self, args: &CreateCommand) -> Result<()> {
+        let interactive = is_interactive();
+        let mut settings = args.settings.clone();
+
+        let name = match &args.name {
+            Some(name) => name.clone(),
+            None if interactive => inquire::Text::new("What should your app be called?")
+                .with_help_message("lowercase letters, numbers and dashes")
+                .with_validator(|s: &str| {
+                    if s.is_empty() {
+                        return Ok(Validation::Invalid("The name cannot be empty".into()));
+                    }
+                    if !s
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                    {
+                        return Ok(Validation::Invalid(
+                            "Only lowercase letters, numbers and dashes are allowed".into(),
+                        ));
+                    }
+                    Ok(Validation::Valid)
+                })
+                .prompt()?,
+            None => bail!("--name is required when not running interactively"),
+        };
+
+        if interactive {
+            if settings.hostnames.is_empty() {
+                settings.hostnames = prompt_list(
+                    "Which hostnames should be routed to this app?",
+                    "",
+                    "e.g. example.com, *.example.com",
+                )?;
+            }
+
+            if settings.routes.is_empty() {
+                settings.routes = prompt_list(
+                    "Which routes should be routed to this app?",
+                    "/*",
+                    "globs or regex:<pattern>, separated by commas",
+                )?;
+            }
+
+            if !settings.has_runtime() {
+                let runtime = inquire::Select::new(
+                    "Which runtime should be used?",
+                    vec!["bun", "process", "custom docker image"],
+                )
+                .prompt()?;
+
+                match runtime {
+                    "custom docker image" => {
+                        settings.image = Some(
+                            inquire::Text::new("Which image should be used?")
+                                .with_help_message("e.g. nginx:latest")
+                                .prompt()?,
+                        )
+                    }
+                    runtime => settings.runtime = Some(runtime.to_string()),
+                }
+            }
+
+            if settings.main.is_none() && settings.command.is_none() && settings.image.is_none() {
+                settings.main = Some(
+                    inquire::Text::new("What is the entrypoint of your app?")
+                        .with_default("index.js")
+                        .prompt()?,
+                );
+            }
+        }
+
+        let req = CreateAppRequest {
+            name: name.clone(),
+            project: Some(self.0.project().to_string()),
+            hostnames: list_update(&settings.hostnames).unwrap_or_default(),
+            routes: list_update(&settings.routes).unwrap_or_default(),
+            route_priority: settings.route_priority.unwrap_or_default(),
+            worker_runtime: settings.runtime(),
+            worker_settings: settings.worker_settings(WorkerSettings::default()),
+            git: settings.git_source(None)?.flatten(),
+            idle_timeout: settings.idle_timeout.filter(|t| *t > 0),
+            pause_timeout: settings.pause_timeout.filter(|t| *t > 0),
+            health_check: settings.health_check(None).flatten(),
+            replicas: settings.replicas,
+        };
+
+        let res = self.0.client.req("POST", "/app")?.json(&req).send().await?;
+        let res: CreateAppResponse = check_response(res).await?.json().await?;
+
+        println!("{}", format!("Created app {}", name.bold()).green());
+        println!("{}", format!("  ID: {}", res.id.bright_black()).white());
         Ok(())
     }
 
     async fn list(&self) -> Result<()> {
+        let apps = match &self.0.global_args.project {
+            Some(project) => get_project_apps(&self.0.client, project).await?,
+            None => self.get_apps().await?,
+        };
+        if apps.is_empty() {
+            println!("{}", "No apps found".yellow());
+            println!("{}{}", "$ ".bright_black(), "nots app create".bright_white());
+            return Ok(());
+        }
+
+        let mut apps: Vec<_> = apps.into_iter().collect();
+        apps.sort_by(|a, b| (&a.1.project, &a.1.name).cmp(&(&b.1.project, &b.1.name)));
+
+        let rows = apps
+            .into_iter()
+            .map(|(id, app)| {
+                vec![
+                    app.name,
+                    app.project,
+                    id,
+                    join_matches(&app.hostnames),
+                    join_matches(&app.routes),
+                    runtime_name(&app.worker_runtime),
+                    app.replicas.to_string(),
+                    app.updated_at.map(|t| t.date().to_string()).unwrap_or_default(),
+                ]
+            })
+            .collect();
+
+        print_table(
+            &[
+                "NAME",
+                "PROJECT",
+                "ID",
+                "HOSTNAMES",
+                "ROUTES",
+                "RUNTIME",
+                "REPLICAS",
+                "UPDATED",
+            ],
+            rows,
+        );
         Ok(())
     }
 
-    async fn edit(&self, name: &str) -> Result<()> {
+    async fn edit(&self, args: &EditCommand) -> Result<()> {
+        let (id, app) = self.find_app(&args.name).await?;
+        let mut settings = args.settings.clone();
+        let mut rename = args.rename.clone();
+
+        for key in &args.unset_env {
+            if !app.worker_settings.env.contains_key(key) && !settings.env.iter().any(|(k, _)| k == key) {
+                bail!("Environment variable {} is not set for {}", key, app.name);
+            }
+        }
+
+        let nothing_set = rename.is_none()
+            && args.unset_env.is_empty()
+            && settings.hostnames.is_empty()
+            && settings.routes.is_empty()
+            && settings.route_priority.is_none()
+            && settings.idle_timeout.is_none()
+            && settings.pause_timeout.is_none()
+            && settings.health_check.is_none()
+            && settings.replicas.is_none()
+            && !settings.has_worker_settings()
+            && !settings.has_runtime()
+            && !settings.has_git();
+
+        if nothing_set {
+            if !is_interactive() {
+                bail!("Nothing to change, see `nots app edit --help`");
+            }
+
+            let help = "separated by commas, - to remove all";
+            let hostnames = prompt_list("Hostnames", &join_matches(&app.hostnames), help)?;
+            if hostnames != app.hostnames.iter().map(|m| m.to_string()).collect::<Vec<_>>() {
+                settings.hostnames = hostnames;
+            }
+
+            let routes = prompt_list("Routes", &join_matches(&app.routes), help)?;
+            if routes != app.routes.iter().map(|m| m.to_string()).collect::<Vec<_>>() {
+                settings.routes = routes;
+            }
+
+            let route_priority: i16 = inquire::CustomType::new("Route priority")
+                .with_default(app.route_priority)
+                .prompt()?;
+            if route_priority != app.route_priority {
+                settings.route_priority = Some(route_priority);
+            }
+        }
+
+        let req = UpdateAppRequest {
+            name: rename.take(),
+            hostnames: list_update(&settings.hostnames),
+            routes: list_update(&settings.routes),
+            route_priority: settings.route_priority,
+            worker_settings: (settings.has_worker_settings() || !args.unset_env.is_empty()).then(|| {
+                let mut worker_settings = settings.worker_settings(app.worker_settings.clone());
+                for key in &args.unset_env {
+                    worker_settings.env.remove(key);
+                }
+                worker_settings
+            }),
+            worker_runtime: settings.has_runtime().then(|| settings.runtime()),
+            git: settings.git_source(app.git.as_ref())?,
+            idle_timeout: settings.idle_timeout,
+            pause_timeout: settings.pause_timeout,
+            health_check: settings.health_check(app.health_check.as_ref()),
+            replicas: settings.replicas,
+        };
+
+        let res = self
+            .0
+            .client
+            .req("POST", &format!("/app/{}", id))?
+            .json(&req)
+            .send()
+            .await?;
+        let app: AppModel = check_response(res).await?.json().await?;
+
+        println!("{}", format!("Updated app {}", app.name.bold()).green());
         Ok(())
     }
+
+    async fn delete(&self, name: &str, yes: bool) -> Result<()> {
+        let (id, app) = self.find_app(name).await?;
+
+        if !yes {
+            if !is_interactive() {
+                bail!("Refusing to delete {} without --yes", app.name);
+            }
+
+            let ans = Confirm::new(&format!("Do you really want to delete {}?", app.name))
+                .with_help_message("All workers of this app will be stopped")
+                .with_default(false)
+                .prompt()?;
+
+            if !ans {
+                println!("{}", "Aborting".red().bold());
+                return Ok(());
+            }
+        }
+
+        let res = self.0.client.req("DELETE", &format!("/app/{}", id))?.send().await?;
+        check_response(res).await?;
+
+        println!("{}", format!("Deleted app {}", app.name.bold()).green());
+        Ok(())
+    }
+
+    async fn get_apps(&self) -> Result<HashMap<String, AppModel>> {
+        get_apps(&self.0.client).await
+    }
+
+    async fn find_app(&self, name: &str) -> Result<(String, AppModel)> {
+        let project = self.0.project();
+        find_app(&self.0.client, project, name)
+            .await?
+            .with_context(|| format!("App {} not found in project {}", name, project))
+    }
+}
+
+pub async fn get_apps(client: &Client) -> Result<HashMap<String, AppModel>> {
+    let res = client.req("GET", "/apps")?.send().await?;
+    Ok(check_response(res).await?.json().await?)
+}
+
+pub async fn get_project_apps(client: &Client, project: &str) -> Result<HashMap<String, AppModel>> {
+    let res = client.req("GET", &format!("/project/{}/apps", project))?.send().await?;
+    Ok(check_response(res).await?.json().await?)
+}
+
+pub async fn find_app(client: &Client, project: &str, name: &str) -> Result<Option<(String, AppModel)>> {
+    Ok(get_project_apps(client, project)
+        .await?
+        .into_iter()
+        .find(|(_, app)| app.name == name))
+}
+
+fn runtime_options(runtime: &str, version: Option<String>) -> WorkerRuntimeOptions {
+    match runtime {
+        "process" => WorkerRuntimeOptions::Process {},
+        runtime => WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Runtime {
+            runtime: runtime.to_string(),
+            version: version.unwrap_or("latest".to_string()),
+            opts: HashMap::new(),
+        }),
+    }
+}
+
+pub fn runtime_name(runtime: &WorkerRuntimeOptions) -> String {
+    match runtime {
+        WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Custom { image, tag }) => format!("{}:{}", image, tag),
+        WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Runtime { runtime, version, .. }) => {
+            format!("{}@{}", runtime, version)
+        }
+        WorkerRuntimeOptions::Process {} => "process".to_string(),
+    }
+}
+
+fn prompt_list(message: &str, default: &str, help: &str) -> Result<Vec<String>> {
+    let res = inquire::Text::new(message)
+        .with_default(default)
+        .with_help_message(help)
+        .prompt()?;
+
+    Ok(res
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect())
+}
+
+fn parse_matches(matches: &[String]) -> Vec<Match> {
+    matches.iter().map(|m| m.parse().expect("infallible")).collect()
+}
+
+/// New hostnames or routes, `None` leaves them unchanged and `-` removes all of them
+fn list_update(values: &[String]) -> Option<Vec<Match>> {
+    match values {
+        [] => None,
+        [clear] if clear == "-" => Some(Vec::new()),
+        values => Some(parse_matches(values)),
+    }
+}
+
+fn join_matches(matches: &[Match]) -> String {
+    matches.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// `--health-check`, `None` removes the health check
+#[derive(Debug, Clone)]
+pub struct HealthCheckFlag(Option<HealthCheckKind>);
+
+fn parse_health_check(s: &str) -> Result<HealthCheckFlag, String> {
+    match s {
+        "none" => Ok(HealthCheckFlag(None)),
+        "tcp" => Ok(HealthCheckFlag(Some(HealthCheckKind::Tcp))),
+        path if path.starts_with('/') => Ok(HealthCheckFlag(Some(HealthCheckKind::Http { path: path.to_string() }))),
+        _ => Err("expected a path like `/healthz`, `tcp` or `none`".to_string()),
+    }
+}
+
+/// A resource limit flag, `None` removes the limit
+#[derive(Debug, Clone, Copy)]
+pub struct LimitFlag<T>(Option<T>);
+
+fn parse_limit<T: std::str::FromStr>(s: &str) -> Result<LimitFlag<T>, String> {
+    match s {
+        "unlimited" => Ok(LimitFlag(None)),
+        s => s
+            .parse()
+            .map(|limit| LimitFlag(Some(limit)))
+            .map_err(|_| format!("invalid limit `{}`, expected a number or `unlimited`", s)),
+    }
+}
+
+fn parse_size_limit(s: &str) -> Result<LimitFlag<u64>, String> {
+    match s {
+        "unlimited" => Ok(LimitFlag(None)),
+        s => ResourceLimits::parse_bytes(s).map(|bytes| LimitFlag(Some(bytes))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_updates() {
+        let update = |values: &[&str]| {
+            let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+            list_update(&values).map(|matches| join_matches(&matches))
+        };
+
+        assert_eq!(update(&[]), None);
+        assert_eq!(update(&["-"]), Some(String::new()));
+        assert_eq!(
+            update(&["example.com", "regex:^api\\."]),
+            Some("example.com, regex:^api\\.".to_string())
+        );
+    }
 }
@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use colored::Colorize;
+use nots_client::{
+    api::{CreateAppRequest, CreateAppResponse, DeployRequest, UpdateAppRequest, UploadArtifactQuery},
+    manifest::{Manifest, MANIFEST_FILE},
+    models::{App, Artifact},
+    utils::check_response,
+};
+use spinoff::{spinners, Spinner};
+
+use super::app::find_app;
+use crate::State;
+
+#[derive(Debug, Clone, Args)]
+pub struct DeployCommand {
+    #[clap()]
+    /// File, `.tar.gz` archive or directory to deploy
+    pub path: PathBuf,
+
+    #[clap(long, short)]
+    /// Name of the app, defaults to the name in nots.toml
+    pub app: Option<String>,
+
+    #[clap(long, short)]
+    /// Apply the `[env.<stage>]` overrides from nots.toml
+    pub stage: Option<String>,
+}
+
+pub async fn run(args: &DeployCommand, state: State) -> Result<()> {
+    let client = &state.client;
+    if !args.path.exists() {
+        bail!("{} does not exist", args.path.display());
+    }
+
+    // settings from nots.toml take precedence over the ones stored in notsd
+    let manifest = match find_manifest(&args.path) {
+        Some(path) => Some(Manifest::from_path(&path)?.app(args.stage.as_deref())?),
+        None if args.stage.is_some() => bail!("--stage requires a {}", MANIFEST_FILE),
+        None => None,
+    };
+
+    let name = args
+        .app
+        .clone()
+        .or(manifest.as_ref().map(|m| m.name.clone()))
+        .context(format!("--app is required when there is no {}", MANIFEST_FILE))?;
+
+    // --project overrides the project from nots.toml
+    let project = match (&state.global_args.project, &manifest) {
+        (None, Some(manifest)) => manifest.project.clone(),
+        _ => state.project().to_string(),
+    };
+
+    // --app overrides the name from nots.toml
+    let manifest = manifest.map(|m| App {
+        name: name.clone(),
+        project: project.clone(),
+        ..m
+    });
+
+    // settings that restart workers are only sent with the new version, see `update_request`
+    let settings = manifest
+        .as_ref()
+        .map(|m| (m.worker_settings.clone(), m.worker_runtime.clone()));
+
+    let app_id = match (find_app(client, &project, &name).await?, manifest) {
+        (Some((id, _)), Some(manifest)) => {
+            let res = client
+                .req("POST", &format!("/app/{}", id))?
+                .json(&update_request(manifest))
+                .send()
+                .await?;
+            check_response(res).await?;
+            id
+        }
+        (Some((id, _)), None) => id,
+        (None, Some(manifest)) => {
+            let res = client
+                .req("POST", "/app")?
+                .json(&create_request(manifest))
+                .send()
+                .await?;
+            let res: CreateAppResponse = check_response(res).await?.json().await?;
+            println!("{}", format!("Created app {}", name.bold()).green());
+            res.id
+        }
+        (None, None) => bail!(
+            "App {} not found in project {}, create it with `nots app create`",
+            name,
+            project
+        ),
+    };
+
+    let temp_dir = tempfile::tempdir()?;
+    let (file, filename) = package(&args.path, temp_dir.path()).await?;
+
+    let mut spinner = Spinner::new(spinners::Dots, "Uploading...", spinoff::Color::Green);
+    let res = client
+        .req("POST", &format!("/app/{}/artifacts", app_id))?
+        .query(&UploadArtifactQuery { filename })
+        .body(tokio::fs::File::open(&file).await?)
+        .send()
+        .await;
+
+    let artifact: Artifact = match res {
+        Ok(res) => check_response(res).await?.json().await?,
+        Err(e) => {
+            spinner.fail("Upload failed");
+            return Err(e.into());
+        }
+    };
+    spinner.clear();
+
+    let res = client
+        .req("POST", &format!("/app/{}/deploy", app_id))?
+        .json(&DeployRequest {
+            artifact_id: artifact.id.clone(),
+            worker_settings: settings.as_ref().map(|(settings, _)| settings.clone()),
+            worker_runtime: settings.map(|(_, runtime)| runtime),
+        })
+        .send()
+        .await?;
+    check_response(res).await?;
+
+    println!(
+        "{} {}",
+        format!("Deployed {} to", args.path.display()).green(),
+        name.bold().green()
+    );
+    println!("{}", format!("  Version: {}", artifact.id.bright_black()).white());
+    println!(
+        "{}",
+        format!("  Size:    {} bytes", artifact.size.to_string().bright_black()).white()
+    );
+    Ok(())
+}
+
+/// nots.toml is looked up in the deployed directory, or the current directory for files
+fn find_manifest(path: &Path) -> Option<PathBuf> {
+    let dir = match path.is_dir() {
+        true => path.to_path_buf(),
+        false => std::env::current_dir().ok()?,
+    };
+
+    let manifest = dir.join(MANIFEST_FILE);
+    manifest.is_file().then_some(manifest)
+}
+
+/// Directories are packed into a `.tar.gz`, files and archives are uploaded as is
+async fn package(path: &Path, temp_dir: &Path) -> Result<(PathBuf, String)> {
+    if path.is_file() {
+        let filename = path
+            .file_name()
+            .context("Invalid file name")?
+            .to_string_lossy()
+            .to_string();
+        return Ok((path.to_path_buf(), filename));
+    }
+
+    let mut spinner = Spinner::new(spinners::Dots, "Packaging...", spinoff::Color::Green);
+    let archive = temp_dir.join("artifact.tar.gz");
+    let (source, target) = (path.to_path_buf(), archive.clone());
+    let res = tokio::task::spawn_blocking(move || -> Result<()> {
+        let file = std::fs::File::create(target)?;
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(file, flate2::Compression::default()));
+        // links are unpacked as links, as long as they point inside of the artifact
+        builder.follow_symlinks(false);
+        builder.append_dir_all(".", source)?;
+        builder.into_inner()?.finish()?;
+        Ok(())
+    })
+    .await?;
+
+    if let Err(e) = res {
+        spinner.fail("Packaging failed");
+        return Err(e.wrap_err(format!("Could not package {}", path.display())));
+    }
+
+    spinner.clear();
+    Ok((archive, "artifact.tar.gz".to_string()))
+}
+
+fn create_request(app: App) -> CreateAppRequest {
+    CreateAppRequest {
+        name: app.name,
+        project: Some(app.project),
+        hostnames: app.hostnames,
+        routes: app.routes,
+        route_priority: app.route_priority,
+        worker_settings: app.worker_settings,
+        worker_runtime: app.worker_runtime,
+        git: None,
+        idle_timeout: app.idle_timeout,
+        pause_timeout: app.pause_timeout,
+        health_check: app.health_check,
+        replicas: Some(app.replicas),
+    }
+}
+
+/// Leaves out the settings workers are started with, changing them would restart the workers with the old version
+fn update_request(app: App) -> UpdateAppRequest {
+    UpdateAppRequest {
+        name: None,
+        hostnames: Some(app.hostnames),
+        routes: Some(app.routes),
+        route_priority: Some(app.route_priority),
+        worker_settings: None,
+        worker_runtime: None,
+        git: None,
+        idle_timeout: Some(app.idle_timeout.unwrap_or(0)),
+        pause_timeout: Some(app.pause_timeout.unwrap_or(0)),
+        health_check: Some(app.health_check),
+        replicas: Some(app.replicas),
+    }
+}
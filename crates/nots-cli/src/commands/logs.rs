@@ -0,0 +1,117 @@
+use clap::Args;
+use color_eyre::eyre::{ContextCompat, Result};
+use colored::Colorize;
+use nots_client::{
+    api::LogsQuery,
+    models::{LogEntry, LogStream},
+    utils::check_response,
+};
+
+use super::app::find_app;
+use crate::State;
+
+#[derive(Debug, Clone, Args)]
+pub struct LogsCommand {
+    #[clap(long, short)]
+    /// Name of the app
+    pub app: String,
+
+    #[clap(long, short)]
+    /// Keep printing new output as it arrives
+    pub follow: bool,
+
+    #[clap(long, value_parser = parse_since)]
+    /// Only show output of the last e.g. `30s`, `10m`, `2h` or `1d`
+    pub since: Option<u64>,
+}
+
+pub async fn run(args: &LogsCommand, state: State) -> Result<()> {
+    let project = state.project();
+    let (id, _) = find_app(&state.client, project, &args.app)
+        .await?
+        .with_context(|| format!("App {} not found in project {}", args.app, project))?;
+
+    let query = LogsQuery {
+        since: args.since,
+        follow: args.follow,
+    };
+    let res = state
+        .client
+        .req("GET", &format!("/app/{}/logs", id))?
+        .query(&query)
+        .send()
+        .await?;
+    let mut res = check_response(res).await?;
+
+    // entries are newline delimited, but chunks can end in the middle of one
+    let mut buf = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        buf.extend_from_slice(&chunk);
+        while let Some(end) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=end).collect();
+            print_entry(&serde_json::from_slice(&line)?);
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(entry: &LogEntry) {
+    let t = entry.timestamp;
+    let timestamp = format!("{} {:02}:{:02}:{:02}", t.date(), t.hour(), t.minute(), t.second());
+    let worker = entry.worker_id.get(..8).unwrap_or(&entry.worker_id);
+    let line = match entry.stream {
+        LogStream::Stdout => entry.line.normal(),
+        LogStream::Stderr => entry.line.red(),
+    };
+
+    println!("{} {} {}", timestamp.bright_black(), worker.cyan(), line);
+}
+
+/// Parse a duration like `90`, `90s`, `10m`, `2h` or `1d` into seconds
+fn parse_since(value: &str) -> Result<u64, String> {
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{}`, expected e.g. 30s, 10m or 2h", value))?;
+
+    let factor = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit `{}`, expected s, m, h or d", unit)),
+    };
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| format!("duration `{}` is too long", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_since_units() {
+        assert_eq!(parse_since("90"), Ok(90));
+        assert_eq!(parse_since("30s"), Ok(30));
+        assert_eq!(parse_since("10m"), Ok(600));
+        assert_eq!(parse_since("2h"), Ok(7200));
+        assert_eq!(parse_since("1d"), Ok(86400));
+    }
+
+    #[test]
+    fn parse_since_invalid() {
+        assert!(parse_since("").is_err());
+        assert!(parse_since("m").is_err());
+        assert!(parse_since("-5m").is_err());
+        assert!(parse_since("1.5h").is_err());
+        assert_eq!(
+            parse_since("5w"),
+            Err("unknown unit `w`, expected s, m, h or d".to_string())
+        );
+        assert_eq!(
+            parse_since("18446744073709551615d"),
+            Err("duration `18446744073709551615d` is too long".to_string())
+        );
+    }
+}
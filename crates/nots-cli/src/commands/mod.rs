@@ -4,7 +4,14 @@ use clap::{
 };
 
 pub mod app;
+pub mod deploy;
+pub mod logs;
+pub mod project;
+pub mod release;
+pub mod repo;
+pub mod secret;
 pub mod server;
+pub mod token;
 pub mod upgrade;
 
 #[derive(Debug, Parser)]
@@ -17,6 +24,10 @@ pub mod upgrade;
   long_about = None
 )]
 pub struct Cli {
+    #[arg(long, global = true)]
+    /// Project the app belongs to, defaults to the `default` project
+    pub project: Option<String>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -34,6 +45,49 @@ pub enum Commands {
         command: app::AppCommand,
     },
 
+    #[command(arg_required_else_help(true))]
+    Project {
+        #[command(subcommand)]
+        command: project::ProjectCommand,
+    },
+
+    #[command(arg_required_else_help(true))]
+    Repo {
+        #[command(subcommand)]
+        command: repo::RepoCommand,
+    },
+
+    #[command(arg_required_else_help(true))]
+    Secret {
+        #[command(subcommand)]
+        command: secret::SecretCommand,
+    },
+
+    #[command(arg_required_else_help(true))]
+    Token {
+        #[command(subcommand)]
+        command: token::TokenCommand,
+    },
+
+    /// Save the API token used to authenticate with notsd
+    Login {
+        #[clap(long)]
+        /// API token, prompted for if not set
+        token: Option<String>,
+    },
+
+    /// Deploy a file, `.tar.gz` archive or directory to an app
+    Deploy(deploy::DeployCommand),
+
+    /// Show the output of an app's workers
+    Logs(logs::LogsCommand),
+
+    /// List the past deploys of an app
+    Releases(release::ReleasesCommand),
+
+    /// Deploy an earlier release of an app again, without uploading it
+    Rollback(release::RollbackCommand),
+
     Upgrade(upgrade::UpgradeCommand),
 }
 
@@ -0,0 +1,137 @@
+use crate::{
+    utils::{is_interactive, print_table},
+    State,
+};
+use clap::Subcommand;
+use color_eyre::eyre::{bail, Result};
+use colored::Colorize;
+use inquire::Confirm;
+use nots_client::{
+    api::{CreateProjectRequest, UpdateProjectRequest},
+    models::Project,
+    utils::check_response,
+};
+
+use super::app::get_project_apps;
+
+pub async fn run(args: &ProjectCommand, state: State) -> Result<()> {
+    let projects = Projects(state);
+    match args {
+        ProjectCommand::Create { name, hostnames } => projects.create(name, hostnames).await,
+        ProjectCommand::List => projects.list().await,
+        ProjectCommand::Edit { name, hostnames } => projects.edit(name, hostnames).await,
+        ProjectCommand::Delete { name, yes } => projects.delete(name, *yes).await,
+    }
+}
+
+struct Projects(State);
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum ProjectCommand {
+    /// Create a new project
+    Create {
+        name: String,
+
+        #[clap(long = "hostname")]
+        /// Domain owned by this project, can be repeated. Apps of the project can only use it and its subdomains
+        hostnames: Vec<String>,
+    },
+    /// List all projects
+    List,
+    /// Replace the domains of a project
+    Edit {
+        name: String,
+
+        #[clap(long = "hostname")]
+        /// Domain owned by this project, can be repeated. Pass no hostnames to allow any hostname
+        hostnames: Vec<String>,
+    },
+    /// Delete a project, it must not have any apps
+    Delete {
+        name: String,
+
+        #[clap(short, long)]
+        /// Don't ask for confirmation
+        yes: bool,
+    },
+}
+
+impl Projects {
+    async fn create(&self, name: &str, hostnames: &[String]) -> Result<()> {
+        let req = CreateProjectRequest {
+            name: name.to_string(),
+            hostnames: hostnames.to_vec(),
+        };
+
+        let res = self.0.client.req("POST", "/project")?.json(&req).send().await?;
+        check_response(res).await?;
+
+        println!("{}", format!("Created project {}", name.bold()).green());
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<()> {
+        let res = self.0.client.req("GET", "/projects")?.send().await?;
+        let projects: Vec<Project> = check_response(res).await?.json().await?;
+
+        let mut rows = Vec::with_capacity(projects.len());
+        for project in projects {
+            let apps = get_project_apps(&self.0.client, &project.name).await?;
+            rows.push(vec![
+                project.name,
+                project.hostnames.join(", "),
+                apps.len().to_string(),
+                project.created_at.date().to_string(),
+            ]);
+        }
+
+        print_table(&["NAME", "HOSTNAMES", "APPS", "CREATED"], rows);
+        Ok(())
+    }
+
+    async fn edit(&self, name: &str, hostnames: &[String]) -> Result<()> {
+        let req = UpdateProjectRequest {
+            hostnames: Some(hostnames.to_vec()),
+        };
+
+        let res = self
+            .0
+            .client
+            .req("POST", &format!("/project/{}", name))?
+            .json(&req)
+            .send()
+            .await?;
+        check_response(res).await?;
+
+        println!("{}", format!("Updated project {}", name.bold()).green());
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str, yes: bool) -> Result<()> {
+        if !yes {
+            if !is_interactive() {
+                bail!("Refusing to delete {} without --yes", name);
+            }
+
+            let ans = Confirm::new(&format!("Do you really want to delete {}?", name))
+                .with_default(false)
+                .prompt()?;
+
+            if !ans {
+                println!("{}", "Aborting".red().bold());
+                return Ok(());
+            }
+        }
+
+        let res = self
+            .0
+            .client
+            .req("DELETE", &format!("/project/{}", name))?
+            .send()
+            .await?;
+        check_response(res).await?;
+
+        println!("{}", format!("Deleted project {}", name.bold()).green());
+        Ok(())
+    }
+}
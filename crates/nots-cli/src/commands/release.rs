@@ -0,0 +1,140 @@
+use clap::Args;
+use color_eyre::eyre::{ContextCompat, Result};
+use colored::Colorize;
+use nots_client::{api::RollbackRequest, models::Release, utils::check_response};
+
+use super::app::{find_app, runtime_name};
+use crate::{utils::print_table, State};
+
+#[derive(Debug, Clone, Args)]
+pub struct ReleasesCommand {
+    #[clap(long, short)]
+    /// Name of the app
+    pub app: String,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RollbackCommand {
+    #[clap(long, short)]
+    /// Name of the app
+    pub app: String,
+
+    #[clap(long, value_parser = parse_release)]
+    /// Release to deploy again, e.g. `v3`, defaults to the release before the current one
+    pub to: Option<u64>,
+
+    #[clap(long, conflicts_with = "to")]
+    /// Go back to the last release with a different version, skipping releases that only changed settings
+    pub previous_version: bool,
+}
+
+pub async fn list(args: &ReleasesCommand, state: State) -> Result<()> {
+    let project = state.project();
+    let (id, _) = find_app(&state.client, project, &args.app)
+        .await?
+        .with_context(|| format!("App {} not found in project {}", args.app, project))?;
+
+    let res = state
+        .client
+        .req("GET", &format!("/app/{}/releases", id))?
+        .send()
+        .await?;
+    let releases: Vec<Release> = check_response(res).await?.json().await?;
+    if releases.is_empty() {
+        println!("{}", format!("App {} has no releases yet", args.app).yellow());
+        return Ok(());
+    }
+
+    let current = releases.last().map(|r| r.number);
+    let rows = releases
+        .into_iter()
+        .rev()
+        .map(|r| {
+            let t = r.created_at;
+            let mut description = match r.rollback_of {
+                Some(number) => format!("Rollback to v{}", number),
+                None => "Deploy".to_string(),
+            };
+            if Some(r.number) == current {
+                description.push_str(" (current)");
+            }
+
+            vec![
+                format!("v{}", r.number),
+                r.artifact_id,
+                r.commit.map(|c| c.chars().take(8).collect()).unwrap_or_default(),
+                runtime_name(&r.worker_runtime),
+                r.deployed_by,
+                format!("{} {:02}:{:02}", t.date(), t.hour(), t.minute()),
+                description,
+            ]
+        })
+        .collect();
+
+    print_table(
+        &[
+            "RELEASE",
+            "VERSION",
+            "COMMIT",
+            "RUNTIME",
+            "DEPLOYED BY",
+            "CREATED",
+            "DESCRIPTION",
+        ],
+        rows,
+    );
+    Ok(())
+}
+
+pub async fn rollback(args: &RollbackCommand, state: State) -> Result<()> {
+    let project = state.project();
+    let (id, _) = find_app(&state.client, project, &args.app)
+        .await?
+        .with_context(|| format!("App {} not found in project {}", args.app, project))?;
+
+    let res = state
+        .client
+        .req("POST", &format!("/app/{}/rollback", id))?
+        .json(&RollbackRequest {
+            release: args.to,
+            previous_version: args.previous_version,
+        })
+        .send()
+        .await?;
+    let release: Release = check_response(res).await?.json().await?;
+
+    let target = release.rollback_of.unwrap_or_default();
+    println!("{}", format!("Rolled back {} to v{}", args.app.bold(), target).green());
+    println!(
+        "{}",
+        format!("  Release: v{}", release.number.to_string().bright_black()).white()
+    );
+    println!(
+        "{}",
+        format!("  Version: {}", release.artifact_id.bright_black()).white()
+    );
+    Ok(())
+}
+
+/// Parse a release number like `3` or `v3`
+fn parse_release(value: &str) -> Result<u64, String> {
+    value
+        .strip_prefix('v')
+        .unwrap_or(value)
+        .parse()
+        .map_err(|_| format!("invalid release `{}`, expected e.g. v3", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_release_number() {
+        assert_eq!(parse_release("3"), Ok(3));
+        assert_eq!(parse_release("v12"), Ok(12));
+        assert!(parse_release("version3").is_err());
+        assert!(parse_release("v-1").is_err());
+        assert!(parse_release("").is_err());
+    }
+}
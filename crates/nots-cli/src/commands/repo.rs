@@ -0,0 +1,102 @@
+use crate::{utils::print_table, State};
+use clap::Subcommand;
+use color_eyre::eyre::Result;
+use colored::Colorize;
+use nots_client::{api::CreateDeployKeyRequest, models::DeployKeyInfo, utils::check_response};
+
+pub async fn run(args: &RepoCommand, state: State) -> Result<()> {
+    let repos = Repos(state);
+    match args {
+        RepoCommand::Key { command } => match command {
+            KeyCommand::Create { name } => repos.create_key(name).await,
+            KeyCommand::List => repos.list_keys().await,
+            KeyCommand::Delete { id } => repos.delete_key(id).await,
+        },
+    }
+}
+
+struct Repos(State);
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum RepoCommand {
+    /// Manage the SSH keys used to fetch private repositories
+    #[command(arg_required_else_help(true))]
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum KeyCommand {
+    /// Generate a new deploy key and print its public key
+    Create {
+        #[clap(default_value = "deploy")]
+        name: String,
+    },
+    /// List the deploy keys of a project
+    List,
+    /// Delete a deploy key
+    Delete { id: String },
+}
+
+impl Repos {
+    async fn create_key(&self, name: &str) -> Result<()> {
+        let req = CreateDeployKeyRequest { name: name.to_string() };
+        let res = self
+            .0
+            .client
+            .req("POST", &format!("/project/{}/key", self.0.project()))?
+            .json(&req)
+            .send()
+            .await?;
+        let key: DeployKeyInfo = check_response(res).await?.json().await?;
+
+        println!("{}", format!("Created deploy key {}", key.id.bold()).green());
+        println!("{}", "Add this public key to your repository's deploy keys:".yellow());
+        println!("{}", key.public_key);
+        println!(
+            "{}{}",
+            "$ ".bright_black(),
+            format!("nots app edit --name <app> --git <ssh url> --deploy-key {}", key.id).bright_white()
+        );
+        Ok(())
+    }
+
+    async fn list_keys(&self) -> Result<()> {
+        let res = self
+            .0
+            .client
+            .req("GET", &format!("/project/{}/keys", self.0.project()))?
+            .send()
+            .await?;
+        let keys: Vec<DeployKeyInfo> = check_response(res).await?.json().await?;
+
+        if keys.is_empty() {
+            println!("{}", "No deploy keys found".yellow());
+            println!("{}{}", "$ ".bright_black(), "nots repo key create".bright_white());
+            return Ok(());
+        }
+
+        let rows = keys
+            .into_iter()
+            .map(|k| vec![k.name, k.id, k.created_at.date().to_string(), k.public_key])
+            .collect();
+
+        print_table(&["NAME", "ID", "CREATED", "PUBLIC KEY"], rows);
+        Ok(())
+    }
+
+    async fn delete_key(&self, id: &str) -> Result<()> {
+        let res = self
+            .0
+            .client
+            .req("DELETE", &format!("/project/{}/key/{}", self.0.project(), id))?
+            .send()
+            .await?;
+        check_response(res).await?;
+
+        println!("{}", format!("Deleted deploy key {}", id.bold()).green());
+        Ok(())
+    }
+}
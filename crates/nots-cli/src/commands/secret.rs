@@ -0,0 +1,179 @@
+use std::io::Read;
+
+use crate::{
+    utils::{is_interactive, print_table},
+    State,
+};
+use clap::Subcommand;
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use colored::Colorize;
+use inquire::{Confirm, Password, PasswordDisplayMode};
+use nots_client::{
+    api::{SecretValue, SetSecretRequest},
+    models::SecretInfo,
+    utils::check_response,
+};
+
+use super::app::find_app;
+
+pub async fn run(args: &SecretCommand, state: State) -> Result<()> {
+    let secrets = Secrets(state);
+    match args {
+        SecretCommand::Set { app, name, value } => secrets.set(app, name, value.clone()).await,
+        SecretCommand::Get { app, name } => secrets.get(app, name).await,
+        SecretCommand::List { app } => secrets.list(app).await,
+        SecretCommand::Rm { app, name, yes } => secrets.rm(app, name, *yes).await,
+    }
+}
+
+struct Secrets(State);
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum SecretCommand {
+    /// Create or replace a secret
+    Set {
+        #[clap(short, long)]
+        app: String,
+
+        name: String,
+
+        /// Value of the secret, prompted for or read from stdin if not set
+        value: Option<String>,
+    },
+    /// Print the value of a secret
+    Get {
+        #[clap(short, long)]
+        app: String,
+
+        name: String,
+    },
+    /// List the secrets of an app, without their values
+    List {
+        #[clap(short, long)]
+        app: String,
+    },
+    /// Delete a secret
+    Rm {
+        #[clap(short, long)]
+        app: String,
+
+        name: String,
+
+        #[clap(short, long)]
+        /// Don't ask for confirmation
+        yes: bool,
+    },
+}
+
+impl Secrets {
+    async fn set(&self, app: &str, name: &str, value: Option<String>) -> Result<()> {
+        let app_id = self.find_app(app).await?;
+
+        let value = match value {
+            Some(value) => value,
+            None if is_interactive() => Password::new(&format!("Value of {}:", name))
+                .with_display_mode(PasswordDisplayMode::Masked)
+                .without_confirmation()
+                .prompt()?,
+            None => {
+                let mut value = String::new();
+                std::io::stdin().read_to_string(&mut value)?;
+                value.trim_end_matches(['\r', '\n']).to_string()
+            }
+        };
+
+        let res = self
+            .0
+            .client
+            .req("POST", &format!("/app/{}/secret/{}", app_id, name))?
+            .json(&SetSecretRequest { value })
+            .send()
+            .await?;
+        check_response(res).await?;
+
+        println!("{}", format!("Stored secret {}", name.bold()).green());
+        Ok(())
+    }
+
+    async fn get(&self, app: &str, name: &str) -> Result<()> {
+        let app_id = self.find_app(app).await?;
+        let res = self
+            .0
+            .client
+            .req("GET", &format!("/app/{}/secret/{}", app_id, name))?
+            .send()
+            .await?;
+
+        let secret: SecretValue = check_response(res).await?.json().await?;
+        println!("{}", secret.value);
+        Ok(())
+    }
+
+    async fn list(&self, app: &str) -> Result<()> {
+        let app_id = self.find_app(app).await?;
+        let res = self
+            .0
+            .client
+            .req("GET", &format!("/app/{}/secrets", app_id))?
+            .send()
+            .await?;
+
+        let secrets: Vec<SecretInfo> = check_response(res).await?.json().await?;
+        if secrets.is_empty() {
+            println!("{}", "No secrets found".yellow());
+            println!(
+                "{}{}",
+                "$ ".bright_black(),
+                format!("nots secret set --app {} <name>", app).bright_white()
+            );
+            return Ok(());
+        }
+
+        let rows = secrets
+            .into_iter()
+            .map(|s| vec![s.name, s.created_at.date().to_string(), s.updated_at.date().to_string()])
+            .collect();
+
+        print_table(&["NAME", "CREATED", "UPDATED"], rows);
+        Ok(())
+    }
+
+    async fn rm(&self, app: &str, name: &str, yes: bool) -> Result<()> {
+        let app_id = self.find_app(app).await?;
+
+        if !yes {
+            if !is_interactive() {
+                bail!("Refusing to delete {} without --yes", name);
+            }
+
+            let ans = Confirm::new(&format!("Do you really want to delete {}?", name))
+                .with_help_message("Workers referencing this secret will fail to start")
+                .with_default(false)
+                .prompt()?;
+
+            if !ans {
+                println!("{}", "Aborting".red().bold());
+                return Ok(());
+            }
+        }
+
+        let res = self
+            .0
+            .client
+            .req("DELETE", &format!("/app/{}/secret/{}", app_id, name))?
+            .send()
+            .await?;
+        check_response(res).await?;
+
+        println!("{}", format!("Deleted secret {}", name.bold()).green());
+        Ok(())
+    }
+
+    async fn find_app(&self, name: &str) -> Result<String> {
+        let project = self.0.project();
+        let (id, _) = find_app(&self.0.client, project, name)
+            .await?
+            .with_context(|| format!("App {} not found in project {}", name, project))?;
+        Ok(id)
+    }
+}
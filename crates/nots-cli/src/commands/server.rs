@@ -1,8 +1,8 @@
 use std::{process::Command, time::Duration};
 
 use crate::{
+    config::Config,
     server::{DockerBackend, ServerBackend},
-    State,
 };
 use clap::Subcommand;
 use color_eyre::{
@@ -15,8 +15,8 @@ use nots_client::api::ServerStatus;
 
 static NOTSD_VERSION: &str = "0.1.7";
 
-pub async fn run(args: &ServerCommand, state: State) -> Result<()> {
-    let server = Server { state };
+pub async fn run(args: &ServerCommand, config: &Config) -> Result<()> {
+    let server = Server { config };
 
     match args {
         ServerCommand::Init(args) => server.init(args).await,
@@ -27,11 +27,11 @@ pub async fn run(args: &ServerCommand, state: State) -> Result<()> {
     }
 }
 
-struct Server {
-    state: State,
+struct Server<'a> {
+    config: &'a Config,
 }
 
-impl Server {
+impl Server<'_> {
     async fn init(&self, args: &InitCommand) -> Result<()> {
         let backend = self.get_backend()?;
         if !backend.is_supported().await {
@@ -100,7 +100,7 @@ impl Server {
                     runas::Command::new("usermod")
                         .arg("-aG")
                         .arg("nots")
-                        .arg(&whoami::username())
+                        .arg(whoami::username())
                         .status()?;
 
                     println!(
@@ -201,7 +201,7 @@ impl Server {
     }
 
     async fn status(&self) -> Result<()> {
-        let client = &self.state.client;
+        let client = self.config.connect().await?;
         let res = client.req("GET", "/status")?.send().await?;
         let headers = res.headers().clone();
         let status: ServerStatus = res.json().await?;
@@ -216,8 +216,8 @@ impl Server {
             .context("Could not get server version")?;
 
         println!("{}", format!("Connected to Notsd v{}", version).bright_white().bold());
-        let uri = self.state.client.printable_client_uri();
-        println!("  Client URI: {}", uri.bright_black().bold()).bright_white();
+        let uri = client.printable_client_uri();
+        println!("  Client URI: {}", uri.bright_black().bold());
         println!(
             "  Uptime:     {:?}",
             Duration::from_secs(status.uptime_secs).bright_black().bold()
@@ -0,0 +1,114 @@
+use crate::{
+    config::Config,
+    utils::{is_interactive, print_table},
+    State,
+};
+use clap::Subcommand;
+use color_eyre::eyre::{bail, Result};
+use colored::Colorize;
+use inquire::{Password, PasswordDisplayMode};
+use nots_client::{
+    api::{CreateTokenRequest, CreateTokenResponse},
+    models::{TokenInfo, TokenScope},
+    utils::check_response,
+};
+
+pub async fn run(args: &TokenCommand, state: State) -> Result<()> {
+    let tokens = Tokens(state);
+    match args {
+        TokenCommand::Create { name, scope, project } => tokens.create(name, *scope, project.clone()).await,
+        TokenCommand::List => tokens.list().await,
+        TokenCommand::Revoke { id } => tokens.revoke(id).await,
+    }
+}
+
+/// Store the API token used by all other commands
+pub async fn login(token: Option<String>) -> Result<()> {
+    let token = match token {
+        Some(token) => token,
+        None if is_interactive() => Password::new("API token:")
+            .with_display_mode(PasswordDisplayMode::Masked)
+            .without_confirmation()
+            .prompt()?,
+        None => bail!("--token is required when not running interactively"),
+    };
+
+    let mut config = Config::load()?;
+    config.token = Some(token);
+    config.save()?;
+
+    println!(
+        "{}",
+        format!("Saved the token to {}", Config::path()?.display()).green()
+    );
+    Ok(())
+}
+
+struct Tokens(State);
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum TokenCommand {
+    /// Create a new API token, it is only shown once
+    Create {
+        name: String,
+
+        #[clap(long, default_value = "read-only")]
+        /// read-only, deploy or admin
+        scope: TokenScope,
+
+        #[clap(long)]
+        /// Only allow access to this project
+        project: Option<String>,
+    },
+    /// List all API tokens
+    List,
+    /// Revoke an API token
+    Revoke { id: String },
+}
+
+impl Tokens {
+    async fn create(&self, name: &str, scope: TokenScope, project: Option<String>) -> Result<()> {
+        let req = CreateTokenRequest {
+            name: name.to_string(),
+            scope,
+            project,
+        };
+
+        let res = self.0.client.req("POST", "/token")?.json(&req).send().await?;
+        let res: CreateTokenResponse = check_response(res).await?.json().await?;
+
+        println!("{}", format!("Created token {}", name.bold()).green());
+        println!("{}", "Store it somewhere safe, it will not be shown again:".yellow());
+        println!("{}", res.token);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<()> {
+        let res = self.0.client.req("GET", "/tokens")?.send().await?;
+        let tokens: Vec<TokenInfo> = check_response(res).await?.json().await?;
+
+        let rows = tokens
+            .into_iter()
+            .map(|t| {
+                vec![
+                    t.name,
+                    t.id,
+                    t.scope.to_string(),
+                    t.project.unwrap_or("*".to_string()),
+                    t.created_at.date().to_string(),
+                ]
+            })
+            .collect();
+
+        print_table(&["NAME", "ID", "SCOPE", "PROJECT", "CREATED"], rows);
+        Ok(())
+    }
+
+    async fn revoke(&self, id: &str) -> Result<()> {
+        let res = self.0.client.req("DELETE", &format!("/token/{}", id))?.send().await?;
+        check_response(res).await?;
+
+        println!("{}", format!("Revoked token {}", id.bold()).green());
+        Ok(())
+    }
+}
@@ -7,8 +7,6 @@ use nots_client::utils::{get_github_version_by_prefix, Version};
 use spinoff::{spinners, Spinner};
 use tokio::process::Command;
 
-use crate::State;
-
 #[derive(Debug, Clone, Args)]
 pub struct UpgradeCommand {
     #[clap()]
@@ -26,7 +24,7 @@ pub struct UpgradeCommand {
 
 static REPO: &str = "explodingcamera/nots";
 
-pub async fn run(args: &UpgradeCommand, state: State) -> Result<()> {
+pub async fn run(args: &UpgradeCommand) -> Result<()> {
     if cfg!(windows) {
         bail!("Upgrade is not supported on Windows yet");
     }
@@ -139,7 +137,7 @@ async fn install_version(version: &str) -> Result<()> {
 
     let mut cmd = Command::new("chmod")
         .arg("+x")
-        .arg(&file.parent().unwrap().join("nots-cli"))
+        .arg(file.parent().unwrap().join("nots-cli"))
         .spawn()?;
 
     if !cmd.wait().await?.success() {
@@ -147,8 +145,8 @@ async fn install_version(version: &str) -> Result<()> {
     }
 
     let mut cmd = Command::new("mv")
-        .arg(&file.parent().unwrap().join("nots-cli"))
-        .arg(&nots_location.join("nots"))
+        .arg(file.parent().unwrap().join("nots-cli"))
+        .arg(nots_location.join("nots"))
         .spawn()?;
     if !cmd.wait().await?.success() {
         bail!("Could not move nots-cli to {}", nots_location.display());
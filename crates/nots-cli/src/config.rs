@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::{Context, Result};
+use nots_client::Client;
+use serde::{Deserialize, Serialize};
+
+/// The socket notsd listens on by default, see `nots server init`
+pub const DEFAULT_SERVER: &str = "unix:///tmp/nots/api.sock";
+
+/// Settings of the CLI, stored in `~/.config/nots/config.toml`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    pub server: Option<String>, // address of notsd, `NOTS_SERVER` takes precedence
+    pub token: Option<String>,  // API token, `NOTS_TOKEN` takes precedence
+}
+
+impl Config {
+    pub fn path() -> Result<PathBuf> {
+        if let Ok(path) = std::env::var("NOTS_CONFIG") {
+            return Ok(PathBuf::from(path));
+        }
+
+        let config_dir = match std::env::var("XDG_CONFIG_HOME") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var("HOME").context("Could not find the home directory")?).join(".config"),
+        };
+        Ok(config_dir.join("nots").join("config.toml"))
+    }
+
+    /// A missing config file is the same as an empty one
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        let mut config: Config = match std::fs::read_to_string(&path) {
+            Ok(config) => toml::from_str(&config).wrap_err_with(|| format!("Invalid config in {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(e).wrap_err_with(|| format!("Could not read {}", path.display())),
+        };
+
+        if let Ok(server) = std::env::var("NOTS_SERVER") {
+            config.server = Some(server);
+        }
+        if let Ok(token) = std::env::var("NOTS_TOKEN") {
+            config.token = Some(token);
+        }
+        Ok(config)
+    }
+
+    pub fn server(&self) -> &str {
+        self.server.as_deref().unwrap_or(DEFAULT_SERVER)
+    }
+
+    /// Client for the configured server, authenticated with the configured token
+    pub async fn connect(&self) -> Result<Client> {
+        let client = Client::try_new(self.server().parse()?).await?;
+        Ok(client.with_token(self.token.clone()))
+    }
+
+    /// The config contains the API token, so it is only readable by the current user
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options
+            .open(&path)
+            .wrap_err_with(|| format!("Could not write {}", path.display()))?;
+        std::io::Write::write_all(&mut file, toml::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+}
@@ -5,9 +5,10 @@
 use clap::Parser;
 use color_eyre::eyre::Result;
 use commands::{Cli, Commands};
-use nots_client::Client;
+use nots_client::{models::DEFAULT_PROJECT, Client};
 
 mod commands;
+mod config;
 mod server;
 mod utils;
 
@@ -16,21 +17,43 @@ pub struct State {
     pub global_args: Cli,
 }
 
+impl State {
+    /// Project selected with `--project`, or the default project
+    pub fn project(&self) -> &str {
+        self.global_args.project.as_deref().unwrap_or(DEFAULT_PROJECT)
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     nots_client::install_tracing(None);
     color_eyre::install()?;
 
     let args = Cli::parse();
-    let state = State {
-        client: Client::http("localhost", 26543)?,
-        global_args: args,
+    let config = config::Config::load()?;
+
+    // only commands that talk to the API connect to notsd, e.g. `login` works without reaching the server
+    let command = args.command.clone();
+    let connect = || async {
+        Ok::<_, color_eyre::Report>(State {
+            client: config.connect().await?,
+            global_args: args,
+        })
     };
 
-    match state.global_args.command.clone() {
-        Commands::Server { command } => commands::server::run(&command, state).await?,
-        Commands::App { command } => commands::app::run(&command, state).await?,
-        Commands::Upgrade(args) => commands::upgrade::run(&args, state).await?,
+    match command {
+        Commands::Server { command } => commands::server::run(&command, &config).await?,
+        Commands::App { command } => commands::app::run(&command, connect().await?).await?,
+        Commands::Project { command } => commands::project::run(&command, connect().await?).await?,
+        Commands::Repo { command } => commands::repo::run(&command, connect().await?).await?,
+        Commands::Secret { command } => commands::secret::run(&command, connect().await?).await?,
+        Commands::Token { command } => commands::token::run(&command, connect().await?).await?,
+        Commands::Login { token } => commands::token::login(token).await?,
+        Commands::Deploy(args) => commands::deploy::run(&args, connect().await?).await?,
+        Commands::Logs(args) => commands::logs::run(&args, connect().await?).await?,
+        Commands::Releases(args) => commands::release::list(&args, connect().await?).await?,
+        Commands::Rollback(args) => commands::release::rollback(&args, connect().await?).await?,
+        Commands::Upgrade(args) => commands::upgrade::run(&args).await?,
     };
 
     Ok(())
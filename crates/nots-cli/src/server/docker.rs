@@ -74,9 +74,8 @@ impl DockerBackend {
                 ..Default::default()
             })
             .await
-            .map_err(|err| {
+            .inspect_err(|_| {
                 voulmes_spinner.fail("Failed to create worker-api volume");
-                err
             })?;
 
         let db_volume = self
@@ -88,9 +87,8 @@ impl DockerBackend {
                 ..Default::default()
             })
             .await
-            .map_err(|err| {
+            .inspect_err(|_| {
                 voulmes_spinner.fail("Failed to create db volume");
-                err
             })?;
 
         let code_volume = self
@@ -102,9 +100,8 @@ impl DockerBackend {
                 ..Default::default()
             })
             .await
-            .map_err(|err| {
+            .inspect_err(|_| {
                 voulmes_spinner.fail("Failed to create code volume");
-                err
             })?;
 
         voulmes_spinner.stop();
@@ -205,7 +202,8 @@ impl DockerBackend {
                             format!("{}:/worker-api", worker_api_volume.name),
                             format!("{}:/db", db_volume.name),
                             format!("{}:/code", code_volume.name),
-                            "/tmp/nots/api.sock:/tmp/nots/api.sock".to_string(),
+                            // notsd creates the socket on startup, so the whole directory is shared
+                            "/tmp/nots:/tmp/nots".to_string(),
                             "/var/run/docker.sock:/var/run/docker.sock".to_string(),
                         ]),
                         ..Default::default()
@@ -214,18 +212,16 @@ impl DockerBackend {
                 },
             )
             .await
-            .map_err(|err| {
+            .inspect_err(|_| {
                 container_spinner.stop();
-                err
             })?;
 
         // wait for container to start
         self.client
             .start_container(&container.id, None::<StartContainerOptions<String>>)
             .await
-            .map_err(|err| {
+            .inspect_err(|_| {
                 container_spinner.stop();
-                err
             })?;
 
         tokio::time::sleep(std::time::Duration::from_secs(5)).await;
@@ -1 +1,42 @@
+use std::io::IsTerminal;
 
+/// Whether we can prompt the user for input
+pub fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+/// Parse a `KEY=VALUE` pair
+pub fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s.split_once('=').ok_or(format!("expected KEY=VALUE, got `{}`", s))?;
+    if key.is_empty() {
+        return Err(format!("key cannot be empty in `{}`", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Print rows as a table with aligned columns
+pub fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    use colored::Colorize;
+
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (i, col) in row.iter().enumerate() {
+            widths[i] = widths[i].max(col.chars().count());
+        }
+    }
+
+    let line = |cols: Vec<String>| {
+        cols.iter()
+            .enumerate()
+            .map(|(i, c)| format!("{:width$}", c, width = widths[i]))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", line(header.iter().map(|h| h.to_string()).collect()).bold());
+    for row in rows {
+        println!("{}", line(row));
+    }
+}
@@ -1,7 +1,136 @@
 use serde::{Deserialize, Serialize};
 
+use crate::models::{GitSource, HealthCheck, Match, TokenInfo, TokenScope, WorkerRuntimeOptions, WorkerSettings};
+
 #[derive(Serialize, Deserialize)]
-pub struct CreateAppRequest {}
+pub struct CreateAppRequest {
+    pub name: String,
+    pub project: Option<String>, // defaults to the default project
+    pub hostnames: Vec<Match>,
+    pub routes: Vec<Match>,
+    #[serde(default)]
+    pub route_priority: i16,
+
+    pub worker_settings: WorkerSettings,
+    pub worker_runtime: WorkerRuntimeOptions,
+
+    #[serde(default)]
+    pub git: Option<GitSource>, // deploy new commits of this repo automatically
+    #[serde(default)]
+    pub idle_timeout: Option<u64>, // scale to zero after this many seconds without requests
+    #[serde(default)]
+    pub pause_timeout: Option<u64>, // freeze the workers after this many seconds without requests
+    #[serde(default)]
+    pub health_check: Option<HealthCheck>,
+    #[serde(default)]
+    pub replicas: Option<u32>, // defaults to 1
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateAppResponse {
+    pub id: String,
+}
+
+/// Fields that are `None` are left unchanged
+#[derive(Serialize, Deserialize, Default)]
+pub struct UpdateAppRequest {
+    pub name: Option<String>,
+    pub hostnames: Option<Vec<Match>>,
+    pub routes: Option<Vec<Match>>,
+    pub route_priority: Option<i16>,
+
+    pub worker_settings: Option<WorkerSettings>,
+    pub worker_runtime: Option<WorkerRuntimeOptions>,
+
+    #[serde(default, deserialize_with = "double_option", skip_serializing_if = "Option::is_none")]
+    pub git: Option<Option<GitSource>>, // `Some(None)` stops watching the repository
+    #[serde(default)]
+    pub idle_timeout: Option<u64>, // 0 keeps the app running
+    #[serde(default)]
+    pub pause_timeout: Option<u64>, // 0 never pauses the app
+    #[serde(default, deserialize_with = "double_option", skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<Option<HealthCheck>>, // `Some(None)` removes the health check
+    #[serde(default)]
+    pub replicas: Option<u32>,
+}
+
+/// Tells a missing field (`None`) apart from `null` (`Some(None)`)
+fn double_option<'de, T: Deserialize<'de>, D: serde::Deserializer<'de>>(de: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(de).map(Some)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateProjectRequest {
+    pub name: String,
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+}
+
+/// Fields that are `None` are left unchanged
+#[derive(Serialize, Deserialize, Default)]
+pub struct UpdateProjectRequest {
+    pub hostnames: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub scope: TokenScope,
+    pub project: Option<String>, // restrict the token to a single project
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateTokenResponse {
+    pub token: String, // only returned once, notsd just stores a hash of it
+    pub info: TokenInfo,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateDeployKeyRequest {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadArtifactQuery {
+    pub filename: String,
+}
+
+/// Logs are returned as newline delimited JSON `LogEntry`s
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct LogsQuery {
+    pub since: Option<u64>, // only entries of the last `since` seconds
+    #[serde(default)]
+    pub follow: bool, // keep the response open and stream new entries
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeployRequest {
+    pub artifact_id: String,
+
+    // replace the app's settings in the same release, so workers never run the old code with them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_settings: Option<WorkerSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_runtime: Option<WorkerRuntimeOptions>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RollbackRequest {
+    pub release: Option<u64>, // number of the release to deploy again, defaults to the one before the current
+    #[serde(default)]
+    pub previous_version: bool, // without `release`, go back to the last release with a different version
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetSecretRequest {
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SecretValue {
+    pub name: String,
+    pub value: String,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ServerStatus {
@@ -34,21 +163,17 @@ pub struct ServerStatus {
 //     },
 // }
 
-#[derive(Serialize, Deserialize)]
-pub enum Repo {
-    PublicHttps { url: String },
-    DeployKey { url: String, id: String },
-    // MachineUser { url: String, id: String },
-}
+pub use crate::models::Repo;
 
-#[derive(Serialize, Deserialize, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
 #[non_exhaustive]
 pub enum SSHKeyType {
     Ed25519,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Private half of a deploy key as stored by notsd
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DeployKey {
     pub kind: SSHKeyType,
-    pub key: crate::EncryptedBytes,
+    pub key: crate::EncryptedBytes, // OpenSSH private key, encrypted with `kw_secret`
 }
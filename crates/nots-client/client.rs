@@ -1,15 +1,110 @@
 use std::{path::PathBuf, str::FromStr};
 
-use color_eyre::eyre::{eyre, Result};
+use color_eyre::eyre::{bail, eyre, Result};
+
+/// Port of the notsd API if it listens on TCP
+pub const DEFAULT_API_PORT: u16 = 26543;
+
+/// Path of the API socket notsd listens on by default
+pub const DEFAULT_API_SOCKET: &str = "/tmp/nots/api.sock";
+
+/// Base URL of requests over a Unix socket, the host is only used for the `Host` header
+#[cfg(unix)]
+const UNIX_SOCKET_URL: &str = "http://localhost";
 
 pub enum TransportSettings {
-    #[cfg(feature = "ssh")]
+    #[cfg(all(feature = "ssh", unix))]
     Ssh(SshSettings),
 
     Http(HttpSettings),
 
     #[cfg(feature = "tls")]
     Https(HttpSettings),
+
+    #[cfg(unix)]
+    Unix(UnixSettings),
+}
+
+impl FromStr for TransportSettings {
+    type Err = color_eyre::Report;
+
+    /// Parses `unix:///path/to/api.sock`, `ssh://[user@]host[:port][/path/to/api.sock]`,
+    /// `http://host[:port]` or `https://host[:port]`
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((scheme, rest)) = s.split_once("://") else {
+            bail!("Invalid server `{}`, expected e.g. unix:///tmp/nots/api.sock", s);
+        };
+
+        let http = || -> Result<HttpSettings> {
+            let (host, port) = match rest.trim_end_matches('/').rsplit_once(':') {
+                Some((host, port)) => (host, port.parse()?),
+                None => (rest.trim_end_matches('/'), DEFAULT_API_PORT),
+            };
+            Ok(HttpSettings {
+                host: host.to_string(),
+                port,
+            })
+        };
+
+        match scheme {
+            #[cfg(unix)]
+            "unix" => Ok(TransportSettings::Unix(UnixSettings { path: rest.into() })),
+            "http" => Ok(TransportSettings::Http(http()?)),
+            #[cfg(feature = "tls")]
+            "https" => Ok(TransportSettings::Https(http()?)),
+            #[cfg(all(feature = "ssh", unix))]
+            "ssh" => {
+                let (destination, socket) = match rest.split_once('/') {
+                    Some((destination, socket)) => (destination, PathBuf::from(format!("/{}", socket))),
+                    None => (rest, PathBuf::from(DEFAULT_API_SOCKET)),
+                };
+                let (username, host) = match destination.split_once('@') {
+                    Some((username, host)) => (Some(username.to_string()), host),
+                    None => (None, destination),
+                };
+                let (host, port) = parse_ssh_host(host)?;
+                Ok(TransportSettings::Ssh(SshSettings {
+                    username,
+                    host: host.to_string(),
+                    port,
+                    socket,
+                }))
+            }
+            _ => bail!("Unsupported server scheme `{}`", scheme),
+        }
+    }
+}
+
+/// Splits `host[:port]` or `[ipv6]:port`, hosts that could be read as an ssh option are rejected
+#[cfg(all(feature = "ssh", unix))]
+fn parse_ssh_host(host: &str) -> Result<(&str, u16)> {
+    let (host, port) = match host.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => match port.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => bail!("Invalid ssh host `[{}`, expected [address]:port", rest),
+            },
+            None => bail!("Invalid ssh host `[{}`, missing `]`", rest),
+        },
+        None if host.matches(':').count() > 1 => {
+            bail!("Invalid ssh host `{}`, IPv6 addresses have to be written as [address]", host)
+        }
+        None => match host.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host, None),
+        },
+    };
+
+    if host.is_empty() || host.starts_with('-') {
+        bail!("Invalid ssh host `{}`", host);
+    }
+
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| eyre!("Invalid ssh port `{}`", port))?,
+        None => 22,
+    };
+    Ok((host, port))
 }
 
 pub struct HttpSettings {
@@ -17,13 +112,35 @@ pub struct HttpSettings {
     pub port: u16,
 }
 
-#[cfg(feature = "ssh")]
+#[cfg(all(feature = "ssh", unix))]
 pub struct SshSettings {
-    pub username: String,
+    pub username: Option<String>, // defaults to the ssh config
     pub host: String,
     pub port: u16,
 
-    pub local_port: u16,
+    pub socket: PathBuf, // API socket on the remote server
+}
+
+#[cfg(all(feature = "ssh", unix))]
+impl SshSettings {
+    fn destination(&self) -> String {
+        match &self.username {
+            Some(username) => format!("{}@{}", username, self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// The host as it is written in an `ssh://` url, with brackets around IPv6 addresses
+    fn url_host(&self) -> String {
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        };
+        match &self.username {
+            Some(username) => format!("{}@{}", username, host),
+            None => host,
+        }
+    }
 }
 
 pub struct UnixSettings {
@@ -31,10 +148,11 @@ pub struct UnixSettings {
 }
 
 enum ClientTransport {
-    #[cfg(feature = "ssh")]
+    #[cfg(all(feature = "ssh", unix))]
     Ssh {
-        client: hyper::Client<HttpConnector>,
+        client: reqwest::Client,
         settings: SshSettings,
+        tunnel: crate::utils::SshTunnel, // requests are sent to the local end of the tunnel
     },
     #[cfg(feature = "tls")]
     Https {
@@ -45,18 +163,29 @@ enum ClientTransport {
         client: reqwest::Client,
         settings: HttpSettings,
     },
+    #[cfg(unix)]
+    Unix {
+        client: reqwest::Client,
+        settings: UnixSettings,
+    },
 }
 
 pub struct Client {
     transport: ClientTransport,
+    token: Option<String>, // API token sent as a bearer token with every request
 }
 
 impl Client {
     pub fn printable_client_uri(&self) -> String {
         match &self.transport {
-            #[cfg(feature = "ssh")]
+            #[cfg(all(feature = "ssh", unix))]
             ClientTransport::Ssh { settings, .. } => {
-                format!("ssh://{}@{}", settings.username, settings)
+                format!(
+                    "ssh://{}:{}{}",
+                    settings.url_host(),
+                    settings.port,
+                    settings.socket.display()
+                )
             }
             #[cfg(feature = "tls")]
             ClientTransport::Https { settings, .. } => {
@@ -65,27 +194,41 @@ impl Client {
             ClientTransport::Http { settings, .. } => {
                 format!("http://{}:{}", settings.host, settings.port)
             }
+            #[cfg(unix)]
+            ClientTransport::Unix { settings, .. } => format!("unix://{}", settings.path.display()),
         }
     }
 
     fn real_client_url(&self) -> String {
         match &self.transport {
-            #[cfg(feature = "ssh")]
-            ClientTransport::Ssh { settings, .. } => {
-                format!("{}:{}", "localhost:", settings.port)
-            }
+            #[cfg(all(feature = "ssh", unix))]
+            ClientTransport::Ssh { .. } => UNIX_SOCKET_URL.to_string(),
             #[cfg(feature = "tls")]
             ClientTransport::Https { settings, .. } => {
-                format!("{}:{}", settings.host, settings.port)
+                format!("https://{}:{}", settings.host, settings.port)
             }
             ClientTransport::Http { settings, .. } => {
-                format!("{}:{}", settings.host, settings.port)
+                format!("http://{}:{}", settings.host, settings.port)
             }
+            #[cfg(unix)]
+            ClientTransport::Unix { .. } => UNIX_SOCKET_URL.to_string(),
         }
     }
 
-    pub fn try_new(settings: TransportSettings) -> Result<Self> {
+    /// Connect to notsd, which opens the SSH tunnel for `ssh://` servers
+    pub async fn try_new(settings: TransportSettings) -> Result<Self> {
         let transport = match settings {
+            #[cfg(all(feature = "ssh", unix))]
+            TransportSettings::Ssh(settings) => {
+                let tunnel =
+                    crate::utils::SshTunnel::open(&settings.destination(), settings.port, &settings.socket).await?;
+                ClientTransport::Ssh {
+                    client: crate::utils::create_unix_client(tunnel.socket())?,
+                    settings,
+                    tunnel,
+                }
+            }
+
             #[cfg(feature = "tls")]
             TransportSettings::Https(settings) => ClientTransport::Https {
                 client: crate::utils::create_https_client(true)?,
@@ -96,18 +239,32 @@ impl Client {
                 client: crate::utils::create_http_only_client()?,
                 settings,
             },
+
+            #[cfg(unix)]
+            TransportSettings::Unix(settings) => ClientTransport::Unix {
+                client: crate::utils::create_unix_client(&settings.path)?,
+                settings,
+            },
         };
 
-        Ok(Self { transport })
+        Ok(Self { transport, token: None })
+    }
+
+    /// Authenticate all requests with an API token
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
     }
 
     fn get_client(&self) -> &reqwest::Client {
         match &self.transport {
-            #[cfg(feature = "ssh")]
+            #[cfg(all(feature = "ssh", unix))]
             ClientTransport::Ssh { client, .. } => client,
             #[cfg(feature = "tls")]
             ClientTransport::Https { client, .. } => client,
             ClientTransport::Http { client, .. } => client,
+            #[cfg(unix)]
+            ClientTransport::Unix { client, .. } => client,
         }
     }
 
@@ -119,24 +276,87 @@ impl Client {
 
         let method = reqwest::Method::from_str(method)?;
         let uri = format!("{}{}", self.real_client_url(), path);
-        let req = client
+        let mut req = client
             .request(method, uri)
             .header("x-nots-client-version", env!("CARGO_PKG_VERSION"));
+
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token);
+        }
         Ok(req)
     }
 
-    pub fn http(host: &str, port: u16) -> Result<Self> {
+    pub async fn http(host: &str, port: u16) -> Result<Self> {
         Self::try_new(TransportSettings::Http(HttpSettings {
             host: host.to_string(),
             port,
         }))
+        .await
+    }
+
+    #[cfg(unix)]
+    pub async fn unix(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::try_new(TransportSettings::Unix(UnixSettings { path: path.into() })).await
     }
 
     #[cfg(feature = "tls")]
-    pub fn https(host: &str, port: u16) -> Result<Self> {
+    pub async fn https(host: &str, port: u16) -> Result<Self> {
         Self::try_new(TransportSettings::Https(HttpSettings {
             host: host.to_string(),
             port,
         }))
+        .await
+    }
+}
+
+#[cfg(all(test, feature = "ssh", unix))]
+mod tests {
+    use super::*;
+
+    fn ssh(server: &str) -> Result<SshSettings> {
+        match server.parse()? {
+            TransportSettings::Ssh(settings) => Ok(settings),
+            _ => bail!("not an ssh server"),
+        }
+    }
+
+    #[test]
+    fn ssh_destinations() {
+        let settings = ssh("ssh://deploy@example.com:2222/run/nots.sock").unwrap();
+        assert_eq!(settings.username.as_deref(), Some("deploy"));
+        assert_eq!(settings.host, "example.com");
+        assert_eq!(settings.port, 2222);
+        assert_eq!(settings.socket, PathBuf::from("/run/nots.sock"));
+
+        let settings = ssh("ssh://example.com").unwrap();
+        assert_eq!(settings.username, None);
+        assert_eq!(settings.port, 22);
+        assert_eq!(settings.socket, PathBuf::from(DEFAULT_API_SOCKET));
+    }
+
+    #[test]
+    fn ssh_ipv6_hosts() {
+        let settings = ssh("ssh://root@[2001:db8::1]:2222").unwrap();
+        assert_eq!(settings.host, "2001:db8::1");
+        assert_eq!(settings.port, 2222);
+        assert_eq!(settings.destination(), "root@2001:db8::1");
+        assert_eq!(settings.url_host(), "root@[2001:db8::1]");
+
+        let settings = ssh("ssh://[::1]/tmp/api.sock").unwrap();
+        assert_eq!(settings.host, "::1");
+        assert_eq!(settings.port, 22);
+
+        // ambiguous without brackets
+        assert!(ssh("ssh://2001:db8::1").is_err());
+        assert!(ssh("ssh://[::1").is_err());
+        assert!(ssh("ssh://[::1]2222").is_err());
+    }
+
+    #[test]
+    fn ssh_hosts_are_not_options() {
+        assert!(ssh("ssh://-oProxyCommand=sh").is_err());
+        assert!(ssh("ssh://user@-oProxyCommand=sh").is_err());
+        assert!(ssh("ssh://").is_err());
+        assert!(ssh("ssh://example.com:port").is_err());
     }
 }
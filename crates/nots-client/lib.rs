@@ -5,6 +5,9 @@
 #[cfg(feature = "api")]
 pub mod api;
 
+#[cfg(feature = "manifest")]
+pub mod manifest;
+
 pub mod models;
 pub mod utils;
 
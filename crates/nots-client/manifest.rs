@@ -0,0 +1,584 @@
+//! Parser for `nots.toml` app manifests
+//!
+//! See `crates/nots-cli/nots.example.toml` for an example.
+
+use std::{collections::HashMap, fmt, ops::Range, path::Path};
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::models::{
+    App, DockerRuntimeOptions, HealthCheck, HealthCheckKind, Match, ResourceLimits, WorkerRuntimeOptions,
+    WorkerSettings, DEFAULT_PROJECT,
+};
+
+pub const MANIFEST_FILE: &str = "nots.toml";
+
+pub struct Manifest {
+    source: String,
+    pub root: ManifestSettings,
+}
+
+/// Keys of a manifest, used for both the top level and `[env.<stage>]` overlays
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestSettings {
+    pub name: Option<Spanned<String>>,
+    pub project: Option<String>,
+
+    pub main: Option<Spanned<String>>,
+    pub command: Option<Spanned<String>>,
+    pub prepare: Option<String>,
+    pub port: Option<u16>,
+
+    pub hostnames: Option<Spanned<Vec<ManifestMatch>>>,
+    pub routes: Option<Spanned<Vec<ManifestMatch>>>,
+    pub route_priority: Option<i16>,
+
+    /// Seconds without requests until the app is scaled to zero
+    pub idle_timeout: Option<u64>,
+    /// Seconds without requests until the app is paused, it keeps its memory but doesn't use any CPU
+    pub pause_timeout: Option<u64>,
+    pub health_check: Option<ManifestHealthCheck>,
+    /// Number of workers requests are balanced across
+    pub replicas: Option<u32>,
+    pub limits: Option<ManifestLimits>,
+
+    pub vars: Option<HashMap<String, String>>,
+    pub runtime: Option<ManifestRuntime>,
+
+    #[serde(default)]
+    pub env: HashMap<String, ManifestSettings>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestRuntime {
+    /// Name of the runtime, e.g. `bun`, or `process` to run without docker
+    pub name: Option<String>,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub opts: HashMap<String, String>,
+
+    /// Custom docker image, can't be combined with `name` or `version`
+    pub image: Option<Spanned<String>>,
+    pub tag: Option<Spanned<String>>,
+}
+
+/// HTTP check if `path` is set, otherwise the worker only has to accept connections
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestHealthCheck {
+    pub path: Option<String>,
+    pub interval: Option<u64>,
+    pub timeout: Option<u64>,
+    pub healthy_threshold: Option<u32>,
+    pub unhealthy_threshold: Option<u32>,
+    pub start_period: Option<u64>,
+}
+
+impl From<ManifestHealthCheck> for HealthCheck {
+    fn from(check: ManifestHealthCheck) -> Self {
+        let default = HealthCheck::default();
+        HealthCheck {
+            kind: match check.path {
+                Some(path) => HealthCheckKind::Http { path },
+                None => HealthCheckKind::Tcp,
+            },
+            interval: check.interval.unwrap_or(default.interval),
+            timeout: check.timeout.unwrap_or(default.timeout),
+            healthy_threshold: check.healthy_threshold.unwrap_or(default.healthy_threshold),
+            unhealthy_threshold: check.unhealthy_threshold.unwrap_or(default.unhealthy_threshold),
+            start_period: check.start_period.unwrap_or(default.start_period),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestLimits {
+    pub cpu_shares: Option<u64>,
+    pub cpus: Option<f64>,
+    pub memory: Option<ManifestBytes>,
+    pub swap: Option<ManifestBytes>,
+    pub pids: Option<u64>,
+}
+
+/// A number of bytes, or a size like `512m`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged, expecting = "a number of bytes, or a size like `512m` or `1g`")]
+pub enum ManifestBytes {
+    Bytes(u64),
+    Size(String),
+}
+
+impl ManifestBytes {
+    fn bytes(&self) -> Result<u64, String> {
+        match self {
+            ManifestBytes::Bytes(bytes) => Ok(*bytes),
+            ManifestBytes::Size(size) => ResourceLimits::parse_bytes(size),
+        }
+    }
+}
+
+/// Either a glob, or a table with a `glob` or `regex` key
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged, expecting = "a glob, or a table with a `glob` or `regex` key")]
+pub enum ManifestMatch {
+    Glob(String),
+    Table(ManifestMatchTable),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum ManifestMatchTable {
+    Glob(String),
+    Regex(String),
+}
+
+impl From<ManifestMatch> for Match {
+    fn from(m: ManifestMatch) -> Self {
+        match m {
+            ManifestMatch::Glob(glob) | ManifestMatch::Table(ManifestMatchTable::Glob(glob)) => Match::Glob(glob),
+            ManifestMatch::Table(ManifestMatchTable::Regex(regex)) => Match::Regex(regex),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ManifestError {
+    pub message: String,
+    /// Dotted path of the offending key, e.g. `env.staging.routes`
+    pub key: Option<String>,
+    /// 1-based line number
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.line, &self.key) {
+            (Some(line), Some(key)) => write!(f, "line {}, key `{}`: {}", line, key, self.message),
+            (Some(line), None) => write!(f, "line {}: {}", line, self.message),
+            (None, Some(key)) => write!(f, "key `{}`: {}", key, self.message),
+            (None, None) => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl Manifest {
+    pub fn parse(source: &str) -> Result<Self, ManifestError> {
+        let de = toml::Deserializer::new(source);
+        let root: ManifestSettings = serde_path_to_error::deserialize(de).map_err(|err| {
+            // spans are tracked through a private wrapper that shouldn't show up in the key
+            let key = err.path().to_string().replace(".$__serde_spanned_private_value", "");
+            let err = err.into_inner();
+            ManifestError {
+                message: err.message().to_string(),
+                key: (key != ".").then_some(key),
+                line: err.span().map(|span| line_of(source, span)),
+            }
+        })?;
+
+        let manifest = Self {
+            source: source.to_string(),
+            root,
+        };
+
+        if manifest.root.name.is_none() {
+            return Err(manifest.error("name", None, "missing key `name`"));
+        }
+
+        for (stage, overlay) in &manifest.root.env {
+            if let Some((nested, _)) = overlay.env.iter().next() {
+                let key = format!("env.{}.env.{}", stage, nested);
+                return Err(manifest.error(&key, None, "stages can't be nested"));
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn from_path(path: &Path) -> color_eyre::Result<Self> {
+        use color_eyre::eyre::WrapErr;
+
+        let source = std::fs::read_to_string(path).wrap_err_with(|| format!("Could not read {}", path.display()))?;
+        Self::parse(&source).wrap_err_with(|| format!("Invalid manifest {}", path.display()))
+    }
+
+    /// Names of the stages defined in `[env.<stage>]` tables
+    pub fn stages(&self) -> Vec<&str> {
+        let mut stages: Vec<_> = self.root.env.keys().map(|s| s.as_str()).collect();
+        stages.sort();
+        stages
+    }
+
+    /// Merge the overlay of `stage` on top of the top level settings and resolve them into an [`App`]
+    pub fn app(&self, stage: Option<&str>) -> Result<App, ManifestError> {
+        let overlay = match stage {
+            Some(stage) => Some(self.root.env.get(stage).ok_or_else(|| {
+                let msg = format!("unknown stage `{}`, available: [{}]", stage, self.stages().join(", "));
+                self.error("env", None, &msg)
+            })?),
+            None => None,
+        };
+
+        // errors should point at the overlay if the value came from there
+        let key = |field: &str, in_overlay: bool| match (stage, in_overlay) {
+            (Some(stage), true) => format!("env.{}.{}", stage, field),
+            _ => field.to_string(),
+        };
+        let from_overlay = |f: fn(&ManifestSettings) -> bool| overlay.map(f).unwrap_or(false);
+
+        let settings = match overlay {
+            Some(overlay) => self.root.clone().merge(overlay.clone()),
+            None => self.root.clone(),
+        };
+
+        let name = settings.name.as_ref().expect("checked in parse");
+        if name.get_ref().trim().is_empty() {
+            let key = key("name", from_overlay(|o| o.name.is_some()));
+            return Err(self.error(&key, Some(name.span()), "name can't be empty"));
+        }
+
+        let runtime = settings.runtime.clone().unwrap_or_default();
+        let worker_runtime = runtime_options(&runtime).map_err(|(field, span, msg)| {
+            let in_overlay = overlay
+                .and_then(|o| o.runtime.as_ref())
+                .map(|r| match field {
+                    "image" => r.image.is_some(),
+                    _ => r.tag.is_some(),
+                })
+                .unwrap_or(false);
+
+            self.error(&key(&format!("runtime.{}", field), in_overlay), Some(span), msg)
+        })?;
+
+        let custom_image = matches!(
+            worker_runtime,
+            WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Custom { .. })
+        );
+
+        // if both are set, `command` is run with `main` as its argument
+        if settings.main.is_none() && settings.command.is_none() && !custom_image {
+            return Err(self.error("main", None, "either `main` or `command` has to be set"));
+        }
+
+        let hostnames = settings.hostnames.as_ref().map(|m| {
+            let key = key("hostnames", from_overlay(|o| o.hostnames.is_some()));
+            self.matches(m, &key)
+        });
+
+        let routes = settings.routes.as_ref().map(|m| {
+            let key = key("routes", from_overlay(|o| o.routes.is_some()));
+            self.matches(m, &key)
+        });
+
+        let limits = settings.limits.as_ref().map(|limits| {
+            let bytes = |field: &str, size: &Option<ManifestBytes>| {
+                let key = key(&format!("limits.{}", field), from_overlay(|o| o.limits.is_some()));
+                size.as_ref()
+                    .map(ManifestBytes::bytes)
+                    .transpose()
+                    .map_err(|e| self.error(&key, None, &e))
+            };
+
+            Ok(ResourceLimits {
+                cpu_shares: limits.cpu_shares,
+                cpus: limits.cpus,
+                memory: bytes("memory", &limits.memory)?,
+                swap: bytes("swap", &limits.swap)?,
+                pids: limits.pids,
+            })
+        });
+
+        Ok(App {
+            name: name.get_ref().trim().to_string(),
+            hostnames: hostnames.transpose()?.unwrap_or_default(),
+            routes: routes.transpose()?.unwrap_or_default(),
+            route_priority: settings.route_priority.unwrap_or_default(),
+            worker_settings: WorkerSettings {
+                port: settings.port,
+                prepare: settings.prepare,
+                command: settings.command.map(Spanned::into_inner),
+                main: settings.main.map(Spanned::into_inner),
+                env: settings.vars.unwrap_or_default(),
+                limits: limits.transpose()?.unwrap_or_default(),
+            },
+            worker_runtime,
+            version: None,
+            updated_at: None,
+            needs_restart_since: None,
+            project: settings.project.unwrap_or(DEFAULT_PROJECT.to_string()),
+            git: None,
+            commit: None,
+            idle_timeout: settings.idle_timeout.filter(|t| *t > 0),
+            pause_timeout: settings.pause_timeout.filter(|t| *t > 0),
+            health_check: settings.health_check.map(HealthCheck::from),
+            replicas: settings.replicas.unwrap_or(1),
+        })
+    }
+
+    fn matches(&self, m: &Spanned<Vec<ManifestMatch>>, key: &str) -> Result<Vec<Match>, ManifestError> {
+        let mut res = Vec::new();
+        for (i, item) in m.get_ref().iter().enumerate() {
+            let item = Match::from(item.clone());
+            let error = match &item {
+                Match::Glob(_) => item.clone().regex().err().map(|e| e.to_string()),
+                Match::Regex(regex) => regex::Regex::new(regex).err().map(|e| e.to_string()),
+            };
+
+            if let Some(e) = error {
+                let msg = format!("invalid pattern `{}`: {}", item, e);
+                return Err(self.error(&format!("{}[{}]", key, i), Some(m.span()), &msg));
+            }
+
+            res.push(item);
+        }
+        Ok(res)
+    }
+
+    fn error(&self, key: &str, span: Option<Range<usize>>, message: &str) -> ManifestError {
+        ManifestError {
+            message: message.to_string(),
+            key: Some(key.to_string()),
+            line: span.map(|span| line_of(&self.source, span)),
+        }
+    }
+}
+
+impl ManifestSettings {
+    /// Values set in `overlay` replace the ones in `self`, `vars` and `runtime` are merged key by key
+    fn merge(mut self, overlay: ManifestSettings) -> Self {
+        macro_rules! replace {
+            ($($field:ident),*) => {
+                $(if overlay.$field.is_some() {
+                    self.$field = overlay.$field;
+                })*
+            };
+        }
+        replace!(
+            name,
+            project,
+            main,
+            command,
+            prepare,
+            port,
+            hostnames,
+            routes,
+            route_priority,
+            idle_timeout,
+            pause_timeout,
+            health_check,
+            replicas,
+            limits
+        );
+
+        if let Some(vars) = overlay.vars {
+            self.vars.get_or_insert_with(HashMap::new).extend(vars);
+        }
+
+        self.runtime = match (self.runtime, overlay.runtime) {
+            (Some(mut base), Some(overlay)) => {
+                // switching between a runtime and a custom image drops the settings of the other
+                if overlay.image.is_some() {
+                    (base.name, base.version) = (None, None);
+                }
+                if overlay.name.is_some() {
+                    (base.image, base.tag) = (None, None);
+                }
+
+                base.opts.extend(overlay.opts);
+                Some(ManifestRuntime {
+                    name: overlay.name.or(base.name),
+                    version: overlay.version.or(base.version),
+                    opts: base.opts,
+                    image: overlay.image.or(base.image),
+                    tag: overlay.tag.or(base.tag),
+                })
+            }
+            (base, overlay) => overlay.or(base),
+        };
+
+        self
+    }
+}
+
+type RuntimeError = (&'static str, Range<usize>, &'static str);
+
+fn runtime_options(runtime: &ManifestRuntime) -> Result<WorkerRuntimeOptions, RuntimeError> {
+    if let Some(image) = &runtime.image {
+        if runtime.name.is_some() || runtime.version.is_some() {
+            return Err((
+                "image",
+                image.span(),
+                "`image` can't be combined with `name` or `version`",
+            ));
+        }
+
+        return Ok(WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Custom {
+            image: image.get_ref().clone(),
+            tag: runtime
+                .tag
+                .as_ref()
+                .map(|t| t.get_ref().clone())
+                .unwrap_or("latest".to_string()),
+        }));
+    }
+
+    if let Some(tag) = &runtime.tag {
+        return Err(("tag", tag.span(), "`tag` can only be used together with `image`"));
+    }
+
+    Ok(match runtime.name.as_deref().unwrap_or("bun") {
+        "process" => WorkerRuntimeOptions::Process {},
+        name => WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Runtime {
+            runtime: name.to_string(),
+            version: runtime.version.clone().unwrap_or("latest".to_string()),
+            opts: runtime.opts.clone(),
+        }),
+    })
+}
+
+fn line_of(source: &str, span: Range<usize>) -> usize {
+    let end = span.start.min(source.len());
+    source[..end].bytes().filter(|b| *b == b'\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+name = "web"
+main = "index.ts"
+hostnames = ["example.com"]
+routes = ["/api/*"]
+vars = { LOG = "info", REGION = "eu" }
+runtime = { name = "bun", version = "1.1", opts = { smol = "true" } }
+limits = { memory = "512m" }
+
+[env.staging]
+name = "web-staging"
+hostnames = ["staging.example.com"]
+vars = { LOG = "debug" }
+runtime = { version = "canary" }
+
+[env.custom]
+runtime = { image = "nginx" }
+routes = [{ regex = "(" }]
+"#;
+
+    fn patterns(matches: &[Match]) -> Vec<String> {
+        matches.iter().map(Match::to_string).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+        assert_eq!(manifest.stages(), ["custom", "staging"]);
+
+        let app = manifest.app(None).unwrap();
+        assert_eq!(app.name, "web");
+        assert_eq!(app.project, DEFAULT_PROJECT);
+        assert_eq!(patterns(&app.hostnames), ["example.com"]);
+        assert_eq!(app.worker_settings.main.as_deref(), Some("index.ts"));
+        assert_eq!(app.worker_settings.limits.memory, Some(512 * 1024 * 1024));
+        assert_eq!(app.replicas, 1);
+        assert!(matches!(
+            app.worker_runtime,
+            WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Runtime { ref version, .. }) if version == "1.1"
+        ));
+    }
+
+    #[test]
+    fn overlay_precedence() {
+        let app = Manifest::parse(MANIFEST).unwrap().app(Some("staging")).unwrap();
+
+        // replaced by the overlay
+        assert_eq!(app.name, "web-staging");
+        assert_eq!(patterns(&app.hostnames), ["staging.example.com"]);
+        // kept from the top level
+        assert_eq!(patterns(&app.routes), ["/api/*"]);
+        assert_eq!(app.worker_settings.main.as_deref(), Some("index.ts"));
+
+        // merged key by key
+        assert_eq!(app.worker_settings.env["LOG"], "debug");
+        assert_eq!(app.worker_settings.env["REGION"], "eu");
+        let WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Runtime { runtime, version, opts }) = app.worker_runtime
+        else {
+            panic!("expected a runtime");
+        };
+        assert_eq!((runtime.as_str(), version.as_str()), ("bun", "canary"));
+        assert_eq!(opts["smol"], "true");
+    }
+
+    #[test]
+    fn overlay_image_replaces_runtime() {
+        let manifest = Manifest::parse(&MANIFEST.replace(r#"[{ regex = "(" }]"#, "[]")).unwrap();
+        let app = manifest.app(Some("custom")).unwrap();
+        assert!(matches!(
+            app.worker_runtime,
+            WorkerRuntimeOptions::Docker(DockerRuntimeOptions::Custom { ref image, ref tag }) if image == "nginx" && tag == "latest"
+        ));
+    }
+
+    #[test]
+    fn main_and_command() {
+        let manifest = Manifest::parse("name = \"web\"\nmain = \"index.ts\"\ncommand = \"bun --smol\"").unwrap();
+        let settings = manifest.app(None).unwrap().worker_settings;
+        assert_eq!(settings.main.as_deref(), Some("index.ts"));
+        assert_eq!(settings.command.as_deref(), Some("bun --smol"));
+
+        let err = Manifest::parse("name = \"web\"").unwrap().app(None).err().unwrap();
+        assert_eq!(err.key.as_deref(), Some("main"));
+    }
+
+    #[test]
+    fn error_spans() {
+        let err = Manifest::parse("name = \"web\"\nmain = \"index.ts\"\nreplica = 2\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.line, Some(3));
+        assert!(err.message.contains("unknown field `replica`"), "{}", err.message);
+
+        let err = Manifest::parse("name = \"web\"\n\n[env.staging]\nport = \"80\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.key.as_deref(), Some("env.staging.port"));
+        assert_eq!(err.line, Some(4));
+        assert_eq!(
+            err.to_string(),
+            format!("line 4, key `env.staging.port`: {}", err.message)
+        );
+
+        let err = Manifest::parse("main = \"index.ts\"").err().unwrap();
+        assert_eq!(err.key.as_deref(), Some("name"));
+        assert_eq!(err.line, None);
+
+        let err = Manifest::parse("name = \"web\"\n[env.a.env.b]\n").err().unwrap();
+        assert_eq!(err.key.as_deref(), Some("env.a.env.b"));
+    }
+
+    #[test]
+    fn overlay_error_spans() {
+        let manifest = Manifest::parse(MANIFEST).unwrap();
+
+        // the invalid pattern comes from the overlay, so the error points there
+        let err = manifest.app(Some("custom")).err().unwrap();
+        assert_eq!(err.key.as_deref(), Some("env.custom.routes[0]"));
+        assert_eq!(
+            err.line,
+            Some(MANIFEST.lines().position(|l| l.starts_with("routes = [{")).unwrap() + 1)
+        );
+
+        let manifest = Manifest::parse("name = \"web\"\nmain = \"index.ts\"\n[env.prod]\nname = \" \"\n").unwrap();
+        let err = manifest.app(Some("prod")).err().unwrap();
+        assert_eq!(err.key.as_deref(), Some("env.prod.name"));
+        assert_eq!(err.line, Some(4));
+
+        let err = manifest.app(Some("dev")).err().unwrap();
+        assert_eq!(err.message, "unknown stage `dev`, available: [prod]");
+    }
+}
@@ -1,13 +1,52 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
 pub struct WorkerSettings {
     pub port: Option<u16>,            // port to listen on
     pub prepare: Option<String>,      // command to run before starting the worker
     pub command: Option<String>,      // command to run to start the worker
     pub main: Option<String>,         // file to pass to the command
     pub env: HashMap<String, String>, // env vars to pass to the command
+    #[serde(default)]
+    pub limits: ResourceLimits, // resources the worker may use
+}
+
+/// Resources a worker may use, `None` is unlimited
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct ResourceLimits {
+    pub cpu_shares: Option<u64>, // relative CPU weight when CPUs are contended, 1024 is the default
+    pub cpus: Option<f64>,       // CPU time in cores, e.g. `0.5` for half a core
+    pub memory: Option<u64>,     // bytes
+    pub swap: Option<u64>,       // bytes of swap on top of `memory`, requires `memory`
+    pub pids: Option<u64>,       // number of processes and threads
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Parse a size like `1048576`, `512k`, `256m` or `1g` (powers of 1024) into bytes
+    pub fn parse_bytes(value: &str) -> Result<u64, String> {
+        let value = value.trim();
+        let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+        let (number, unit) = value.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid size `{}`, expected e.g. 512m or 1g", value))?;
+
+        let factor: u64 = match unit.to_ascii_lowercase().trim_end_matches('b') {
+            "" => 1,
+            "k" => 1 << 10,
+            "m" => 1 << 20,
+            "g" => 1 << 30,
+            _ => return Err(format!("unknown unit `{}`, expected k, m or g", unit)),
+        };
+        number
+            .checked_mul(factor)
+            .ok_or_else(|| format!("size `{}` is too large", value))
+    }
 }
 // pub secrets: HashMap<String, String>, // secrets available to the worker, key:
 
@@ -19,13 +58,13 @@ pub struct Worker {
     pub state: WorkerState,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub enum WorkerRuntimeOptions {
     Docker(DockerRuntimeOptions),
     Process {},
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub enum DockerRuntimeOptions {
     Custom {
         image: String,
@@ -42,6 +81,8 @@ pub enum DockerRuntimeOptions {
 pub struct WorkerState {
     pub status: WorkerStatus,
     pub restart_count: Option<u64>,
+    #[serde(default)]
+    pub oom_killed: bool, // the worker was killed for exceeding its memory limit
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -55,8 +96,36 @@ pub enum WorkerStatus {
     Dead,
 }
 
+/// A line a worker wrote to stdout or stderr
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    pub timestamp: time::OffsetDateTime, // when notsd received the line
+    pub worker_id: String,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// Project apps belong to if none is given
+pub const DEFAULT_PROJECT: &str = "default";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Project {
+    pub name: String,           // unique name of the project
+    pub hostnames: Vec<String>, // domains owned by the project, its apps can use them and their subdomains
+    pub created_at: time::OffsetDateTime,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct App {
+    pub name: String, // name of the app, unique within its project
+
     pub hostnames: Vec<Match>, // hostname to match
     pub routes: Vec<Match>,    // routes to match (ignores query string, glob is case insensitive)
     pub route_priority: i16,   // higher priority routes are matched first, default 0
@@ -64,8 +133,180 @@ pub struct App {
     pub worker_settings: WorkerSettings,
     pub worker_runtime: WorkerRuntimeOptions,
 
+    pub version: Option<String>, // id of the deployed artifact
+
     pub updated_at: Option<time::OffsetDateTime>,
     pub needs_restart_since: Option<time::OffsetDateTime>,
+
+    #[serde(default = "default_project")]
+    pub project: String, // name of the project the app belongs to
+
+    #[serde(default)]
+    pub git: Option<GitSource>, // repository new commits are deployed from
+    #[serde(default)]
+    pub commit: Option<String>, // git commit of the deployed version, if it came from a repository
+
+    #[serde(default)]
+    pub idle_timeout: Option<u64>, // seconds without requests until the app is scaled to zero, never if `None`
+    #[serde(default)]
+    pub pause_timeout: Option<u64>, // seconds without requests until the workers are frozen, never if `None`
+
+    #[serde(default)]
+    pub health_check: Option<HealthCheck>, // workers only get traffic once healthy if set
+
+    #[serde(default = "default_replicas")]
+    pub replicas: u32, // number of workers requests are balanced across
+}
+
+fn default_replicas() -> u32 {
+    1
+}
+
+/// Checks whether a worker can handle requests, unhealthy workers are restarted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HealthCheck {
+    pub kind: HealthCheckKind,
+    pub interval: u64,            // seconds between checks
+    pub timeout: u64,             // seconds a single check may take
+    pub healthy_threshold: u32,   // consecutive successes until a worker gets traffic
+    pub unhealthy_threshold: u32, // consecutive failures until a worker is restarted
+    pub start_period: u64,        // seconds after the start of a worker in which failures don't count
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum HealthCheckKind {
+    Http { path: String }, // any 2xx or 3xx response is healthy
+    Tcp,                   // accepting connections is enough
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            kind: HealthCheckKind::Tcp,
+            interval: 10,
+            timeout: 5,
+            healthy_threshold: 1,
+            unhealthy_threshold: 3,
+            start_period: 30,
+        }
+    }
+}
+
+/// A branch of a git repository an app is deployed from
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GitSource {
+    pub repo: Repo,
+    pub branch: String,
+    pub path: Option<String>, // subdirectory to deploy, defaults to the whole repository
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Repo {
+    PublicHttps { url: String },
+    DeployKey { url: String, id: String },
+    // MachineUser { url: String, id: String },
+}
+
+impl Repo {
+    pub fn url(&self) -> &str {
+        match self {
+            Repo::PublicHttps { url } | Repo::DeployKey { url, .. } => url,
+        }
+    }
+}
+
+fn default_project() -> String {
+    DEFAULT_PROJECT.to_string()
+}
+
+/// A stored secret, without its value
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SecretInfo {
+    pub name: String,
+    pub created_at: time::OffsetDateTime,
+    pub updated_at: time::OffsetDateTime,
+}
+
+/// What an API token is allowed to do, each scope includes the ones before it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenScope {
+    ReadOnly, // read apps, projects and artifacts, but not secret values
+    Deploy,   // create, change and deploy apps and manage their secrets
+    Admin,    // everything, including deleting apps and managing projects and tokens
+}
+
+impl std::fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenScope::ReadOnly => write!(f, "read-only"),
+            TokenScope::Deploy => write!(f, "deploy"),
+            TokenScope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl std::str::FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" => Ok(TokenScope::ReadOnly),
+            "deploy" => Ok(TokenScope::Deploy),
+            "admin" => Ok(TokenScope::Admin),
+            _ => Err(format!("unknown scope `{}`, expected read-only, deploy or admin", s)),
+        }
+    }
+}
+
+/// An API token, without its secret
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TokenInfo {
+    pub id: String,
+    pub name: String,
+    pub scope: TokenScope,
+    pub project: Option<String>, // the token can only access this project if set
+    pub created_at: time::OffsetDateTime,
+}
+
+/// An SSH key used to fetch private repositories, without its private half
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeployKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub project: String,
+    pub public_key: String, // OpenSSH format, to be added to the repository on the forge
+    pub created_at: time::OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Artifact {
+    pub id: String,
+    pub app_id: String,
+    pub kind: ArtifactKind,
+    pub size: u64, // size of the upload in bytes
+    pub created_at: time::OffsetDateTime,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ArtifactKind {
+    File { name: String },  // a single file, e.g. `index.ts`
+    Archive,                // a `.tar.gz` archive, extracted on upload
+    Git { commit: String }, // a checkout of the app's git repository
+}
+
+/// A deploy of an app, never changed afterwards so the app can be rolled back to it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Release {
+    pub number: u64, // counts up per app, starting at 1
+    pub app_id: String,
+    pub artifact_id: String,
+    pub commit: Option<String>, // git commit of the artifact, if it came from a repository
+    pub worker_settings: WorkerSettings,
+    pub worker_runtime: WorkerRuntimeOptions,
+    pub deployed_by: String,      // name of the token, or what deployed it automatically
+    pub rollback_of: Option<u64>, // number of the release that was deployed again
+    pub created_at: time::OffsetDateTime,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -74,6 +315,27 @@ pub enum Match {
     Regex(String),
 }
 
+impl std::fmt::Display for Match {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Match::Glob(glob) => write!(f, "{}", glob),
+            Match::Regex(regex) => write!(f, "regex:{}", regex),
+        }
+    }
+}
+
+impl std::str::FromStr for Match {
+    type Err = std::convert::Infallible;
+
+    /// Parses `regex:<pattern>` as a regex, everything else as a glob
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.strip_prefix("regex:") {
+            Some(regex) => Match::Regex(regex.to_string()),
+            None => Match::Glob(s.to_string()),
+        })
+    }
+}
+
 impl Match {
     #[cfg(feature = "glob")]
     pub fn regex(self) -> Result<String, globset::Error> {
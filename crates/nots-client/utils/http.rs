@@ -77,3 +77,21 @@ pub async fn download_github_release_artifact(
 
     Ok(path)
 }
+
+/// Turn error responses from notsd (`{"error": "..."}`) into errors
+pub async fn check_response(res: reqwest::Response) -> Result<reqwest::Response> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+
+    #[derive(serde::Deserialize)]
+    struct ErrorResponse {
+        error: String,
+    }
+
+    let status = res.status();
+    match res.json::<ErrorResponse>().await {
+        Ok(err) => Err(color_eyre::eyre::eyre!("{} ({})", err.error, status)),
+        Err(_) => Err(color_eyre::eyre::eyre!("Request failed ({})", status)),
+    }
+}
@@ -13,3 +13,13 @@ struct Release {
 
 mod http;
 pub use http::*;
+
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use unix::*;
+
+#[cfg(all(feature = "ssh", unix))]
+mod ssh;
+#[cfg(all(feature = "ssh", unix))]
+pub use ssh::*;
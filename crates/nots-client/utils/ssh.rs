@@ -0,0 +1,80 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use tempfile::TempDir;
+
+/// How long to wait for ssh to connect, including the time it takes to enter a password
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// An `ssh` process forwarding a local Unix socket to the API socket of a remote notsd
+///
+/// The system `ssh` is used so the user's config, agent and known hosts just work.
+/// The tunnel is closed when it is dropped.
+pub struct SshTunnel {
+    child: Child,
+    _dir: TempDir,   // removed after ssh exited
+    socket: PathBuf, // local end of the tunnel
+}
+
+impl SshTunnel {
+    pub async fn open(destination: &str, port: u16, remote_socket: &Path) -> Result<Self> {
+        // only the current user may use the tunnel, the remote side trusts us by our ssh login. The directory
+        // has a random name and is created with mode 0700, so no one else can create or replace the socket
+        let dir = tempfile::Builder::new()
+            .prefix("nots-ssh-")
+            .tempdir()
+            .wrap_err("Could not create a directory for the ssh tunnel")?;
+        let socket = dir.path().join("api.sock");
+
+        let child = Command::new("ssh")
+            .arg("-N")
+            .args(["-o", "ExitOnForwardFailure=yes"])
+            .args(["-o", "StreamLocalBindUnlink=yes"])
+            .args(["-p", &port.to_string()])
+            .arg("-L")
+            .arg(format!("{}:{}", socket.display(), remote_socket.display()))
+            // keeps a destination starting with `-` from being read as an option
+            .arg("--")
+            .arg(destination)
+            .stdout(Stdio::null())
+            .spawn()
+            .wrap_err("Could not start ssh, is it installed?")?;
+
+        let mut tunnel = Self {
+            child,
+            _dir: dir,
+            socket,
+        };
+        tunnel.wait_until_ready().await?;
+        Ok(tunnel)
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    async fn wait_until_ready(&mut self) -> Result<()> {
+        let started = Instant::now();
+        while !self.socket.exists() {
+            if let Some(status) = self.child.try_wait()? {
+                bail!("ssh exited with {}", status);
+            }
+            if started.elapsed() > CONNECT_TIMEOUT {
+                bail!("Timed out waiting for ssh to connect");
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SshTunnel {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
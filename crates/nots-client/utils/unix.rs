@@ -0,0 +1,18 @@
+use std::path::Path;
+
+use color_eyre::eyre::Result;
+
+use super::default_headers;
+
+/// HTTP client that sends every request to the Unix socket at `path`, whatever host the URL names
+///
+/// The socket authorizes us by our uid, so requests must not pass through anything other users can connect to.
+pub fn create_unix_client(path: &Path) -> Result<reqwest::Client> {
+    let client = reqwest::Client::builder()
+        .http1_only()
+        .unix_socket(path)
+        .default_headers(default_headers())
+        .build()?;
+
+    Ok(client)
+}
@@ -0,0 +1,229 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use nots_client::models::ResourceLimits;
+use tracing::warn;
+
+/// Time the kernel gets to freeze or kill all processes of a cgroup
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Controllers resource limits are enforced with
+const CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+/// Period `cpu.max` quotas refer to, in microseconds
+const CPU_PERIOD: u64 = 100_000;
+
+/// The cgroup v2 directory worker cgroups are created in, below the cgroup notsd runs in
+pub struct Cgroups {
+    root: PathBuf,
+}
+
+impl Cgroups {
+    /// `None` if cgroup v2 isn't mounted or notsd can't create cgroups, e.g. without delegation
+    pub fn detect() -> Option<Self> {
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+        let (mount_root, mount_point) = mountinfo.lines().find_map(|line| {
+            let (fields, fs) = line.split_once(" - ")?;
+            if !fs.starts_with("cgroup2 ") {
+                return None;
+            }
+
+            let fields: Vec<&str> = fields.split(' ').collect();
+            Some((fields.get(3)?.to_string(), fields.get(4)?.to_string()))
+        })?;
+
+        let own = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+        let own = own.lines().find_map(|line| line.strip_prefix("0::"))?;
+        let own = own.strip_prefix(mount_root.trim_end_matches('/')).unwrap_or(own);
+
+        let own = Path::new(&mount_point).join(own.trim_start_matches('/'));
+        let root = own.join("nots-workers");
+        std::fs::create_dir_all(&root).ok()?;
+
+        let is_root_cgroup = own == Path::new(&mount_point);
+        enable_controllers(&own, &root, is_root_cgroup);
+        Some(Self { root })
+    }
+
+    /// Fails if a limit needs a controller that isn't enabled for worker cgroups
+    pub fn check_limits(&self, limits: &ResourceLimits) -> Result<()> {
+        let enabled = std::fs::read_to_string(self.root.join("cgroup.subtree_control")).unwrap_or_default();
+        let required = [
+            ("cpu", limits.cpu_shares.is_some() || limits.cpus.is_some()),
+            ("memory", limits.memory.is_some() || limits.swap.is_some()),
+            ("pids", limits.pids.is_some()),
+        ];
+
+        for (controller, _) in required.iter().filter(|(_, required)| *required) {
+            if !enabled.split_whitespace().any(|c| c == *controller) {
+                bail!("The cgroup {} controller is not available to notsd", controller);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create(&self, worker_id: &str) -> Result<Cgroup> {
+        let path = self.root.join(worker_id);
+        std::fs::create_dir_all(&path).wrap_err_with(|| format!("Could not create cgroup {}", path.display()))?;
+        Ok(Cgroup { path })
+    }
+}
+
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Writing `0` to this file moves the writing process into the cgroup, see `pre_exec`
+    pub fn procs_file(&self) -> Result<File> {
+        let path = self.path.join("cgroup.procs");
+        File::options()
+            .write(true)
+            .open(&path)
+            .wrap_err_with(|| format!("Could not open {}", path.display()))
+    }
+
+    /// Apply resource limits, has to happen before the worker joins the cgroup
+    pub fn set_limits(&self, limits: &ResourceLimits) -> Result<()> {
+        if let Some(shares) = limits.cpu_shares {
+            // the same conversion container runtimes use, shares range from 2 to 262144 and weights from 1 to 10000
+            let weight = 1 + (shares.clamp(2, 262_144) - 2) * 9999 / 262_142;
+            self.write("cpu.weight", &weight.to_string())?;
+        }
+        if let Some(cpus) = limits.cpus {
+            let quota = ((cpus * CPU_PERIOD as f64) as u64).max(1000);
+            self.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD))?;
+        }
+        if let Some(memory) = limits.memory {
+            self.write("memory.max", &memory.to_string())?;
+            // an OOM kill takes down the whole worker instead of leaving it half working
+            self.write("memory.oom.group", "1")?;
+        }
+        if let Some(swap) = limits.swap {
+            self.write("memory.swap.max", &swap.to_string())?;
+        }
+        if let Some(pids) = limits.pids {
+            self.write("pids.max", &pids.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Whether the kernel killed a process of the cgroup because it exceeded its memory limit
+    pub fn oom_killed(&self) -> bool {
+        let events = std::fs::read_to_string(self.path.join("memory.events")).unwrap_or_default();
+        events.lines().any(|line| {
+            line.strip_prefix("oom_kill ")
+                .and_then(|count| count.parse::<u64>().ok())
+                .is_some_and(|count| count > 0)
+        })
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        std::fs::write(self.path.join(file), value)
+            .wrap_err_with(|| format!("Could not write {} to {}", value, self.path.join(file).display()))
+    }
+
+    /// Freeze or thaw all processes in the cgroup and wait until the kernel is done
+    pub async fn set_frozen(&self, frozen: bool) -> Result<()> {
+        std::fs::write(self.path.join("cgroup.freeze"), if frozen { "1" } else { "0" })
+            .wrap_err("Could not write cgroup.freeze")?;
+
+        let expected = if frozen { "frozen 1" } else { "frozen 0" };
+        self.wait_for_event(expected).await
+    }
+
+    /// Kill everything that is left in the cgroup and remove it
+    pub async fn remove(&self) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        if self.event("populated 1") {
+            // `cgroup.kill` also works on frozen processes
+            std::fs::write(self.path.join("cgroup.kill"), "1").wrap_err("Could not write cgroup.kill")?;
+            self.wait_for_event("populated 0").await?;
+        }
+
+        std::fs::remove_dir(&self.path).wrap_err_with(|| format!("Could not remove cgroup {}", self.path.display()))
+    }
+
+    async fn wait_for_event(&self, line: &str) -> Result<()> {
+        let settled = async {
+            while !self.event(line) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+
+        if tokio::time::timeout(SETTLE_TIMEOUT, settled).await.is_err() {
+            bail!("cgroup {} did not reach `{}` in time", self.path.display(), line);
+        }
+        Ok(())
+    }
+
+    fn event(&self, line: &str) -> bool {
+        std::fs::read_to_string(self.path.join("cgroup.events")).is_ok_and(|events| events.lines().any(|l| l == line))
+    }
+}
+
+/// Pass the controllers limits need down to the worker cgroups
+///
+/// cgroups can only enable controllers for their children if they don't contain processes themselves,
+/// so unless notsd runs in the root cgroup, it moves itself to a leaf first, which requires a cgroup of its own.
+fn enable_controllers(own: &Path, root: &Path, is_root_cgroup: bool) {
+    let available = std::fs::read_to_string(own.join("cgroup.controllers")).unwrap_or_default();
+    let controllers: Vec<&str> = CONTROLLERS
+        .into_iter()
+        .filter(|c| available.split_whitespace().any(|a| a == *c))
+        .collect();
+    if controllers.is_empty() {
+        return;
+    }
+
+    if !is_root_cgroup {
+        if let Err(e) = move_to_leaf(own) {
+            warn!(
+                "Could not move notsd to its own cgroup, resource limits are not available: {:?}",
+                e
+            );
+            return;
+        }
+    }
+
+    for controller in controllers {
+        let enable = format!("+{}", controller);
+        let res = std::fs::write(own.join("cgroup.subtree_control"), &enable)
+            .and_then(|_| std::fs::write(root.join("cgroup.subtree_control"), &enable));
+        if let Err(e) = res {
+            warn!(
+                "Could not enable the cgroup {} controller for workers: {}",
+                controller, e
+            );
+        }
+    }
+}
+
+/// Only notsd itself is moved, processes it doesn't own are left alone
+fn move_to_leaf(own: &Path) -> Result<()> {
+    let pid = std::process::id().to_string();
+    let procs = std::fs::read_to_string(own.join("cgroup.procs")).wrap_err("Could not read cgroup.procs")?;
+    // processes that exited in the meantime don't count
+    let others: Vec<&str> = procs
+        .lines()
+        .filter(|p| *p != pid && Path::new("/proc").join(p).exists())
+        .collect();
+    if !others.is_empty() {
+        bail!(
+            "cgroup {} is shared with other processes ({}), run notsd in a delegated cgroup of its own, e.g. with `Delegate=yes` in its systemd unit",
+            own.display(),
+            others.join(", ")
+        );
+    }
+
+    let leaf = own.join("notsd");
+    std::fs::create_dir_all(&leaf).wrap_err("Could not create cgroup")?;
+    std::fs::write(leaf.join("cgroup.procs"), &pid).wrap_err("Could not move notsd")
+}
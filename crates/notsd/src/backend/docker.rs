@@ -1,19 +1,31 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 
-use super::{CreateWorker, WorkerState, WorkerStatus};
+use super::{send_log, shell_quote, CreateWorker, CreatedWorker, LineBuffer, LogSender, WorkerState, WorkerStatus};
 use crate::backend::NotsBackend;
 use axum::async_trait;
+use axum::body::Bytes;
 use bollard::{
     container::*,
+    image::CreateImageOptions,
     network::CreateNetworkOptions,
     service::{ContainerSummary, Ipam},
 };
-use color_eyre::eyre::{bail, Result};
-use nots_client::models::{DockerRuntimeOptions, WorkerRuntimeOptions};
+use color_eyre::eyre::{bail, Context, Result};
+use futures::StreamExt;
+use nots_client::models::{DockerRuntimeOptions, LogStream, ResourceLimits, WorkerRuntimeOptions, WorkerSettings};
+use tracing::info;
+use zeroize::Zeroizing;
+
+/// Port workers listen on if the app doesn't specify one, passed as `PORT`
+const DEFAULT_PORT: u16 = 3000;
+
+/// Directory the code of the app is copied to
+const CODE_DIR: &str = "/app";
 
 pub struct DockerBackendSettings {
     pub worker_prefix: String,
     pub worker_labels: HashMap<String, String>,
+    pub network: String,
 }
 
 impl Default for DockerBackendSettings {
@@ -21,10 +33,22 @@ impl Default for DockerBackendSettings {
         Self {
             worker_prefix: "nots_worker".to_string(),
             worker_labels: HashMap::from([("nots".to_string(), "worker".to_string())]),
+            network: "nots_workers".to_string(),
         }
     }
 }
 
+struct WorkerContainer<'a> {
+    name: &'a str,
+    image: &'a str,
+    cmd: Option<Vec<String>>,
+    env: Vec<Zeroizing<String>>, // `KEY=value`, might contain secrets
+    labels: HashMap<String, String>,
+    port: u16,
+    binds: Option<Vec<String>>,
+    limits: &'a ResourceLimits,
+}
+
 pub struct DockerRuntime {
     client: bollard::Docker,
     settings: DockerBackendSettings,
@@ -32,57 +56,121 @@ pub struct DockerRuntime {
 
 #[async_trait]
 impl NotsBackend for DockerRuntime {
-    async fn worker_create(&self, worker: CreateWorker) -> Result<()> {
-        let name = format!("{}-{}", self.settings.worker_prefix, worker.worker_id);
+    async fn worker_create(&self, worker: CreateWorker) -> Result<CreatedWorker> {
+        let name = self.container_name(&worker.worker_id);
 
-        let WorkerRuntimeOptions::Docker(opt) = worker.runtime_options else {
+        let WorkerRuntimeOptions::Docker(opt) = &worker.runtime_options else {
             bail!("Invalid runtime options for runtime");
         };
 
-        match opt {
-            DockerRuntimeOptions::Custom { image, tag } => {
-                self.create_worker_container(&name, &image, &tag, None).await?;
-            }
+        let (image, tag, binds, runtime) = match opt {
+            DockerRuntimeOptions::Custom { image, tag } => (image.clone(), tag.clone(), None, None),
             DockerRuntimeOptions::Runtime { opts, runtime, version } => {
-                let image = format!("ghcr.io/explodingcamera/nots-worker:bun-{}", version);
-                let tag = "latest".to_string();
+                let image = "ghcr.io/explodingcamera/nots-worker".to_string();
+                let tag = format!("{}-{}", runtime, version);
 
-                let binds = if opts.get("bun-cache").is_some() {
+                let binds = if opts.contains_key("bun-cache") {
                     Some(vec!["nots_bun_cache:/tmp/bun-cache:rw".to_string()])
                 } else {
                     None
                 };
 
-                self.create_worker_container(&name, &image, &tag, binds).await?;
+                (image, tag, binds, Some(runtime.as_str()))
             }
         };
 
-        Ok(())
+        let cmd = worker_command(&worker.settings, runtime)?;
+        let port = worker.settings.port.unwrap_or(DEFAULT_PORT);
+
+        let mut env: Vec<Zeroizing<String>> = worker
+            .env
+            .iter()
+            .map(|(k, v)| Zeroizing::new(format!("{}={}", k, v.as_str())))
+            .collect();
+        env.push(Zeroizing::new(format!("PORT={}", port)));
+
+        let mut labels = self.settings.worker_labels.clone();
+        labels.insert("nots.app_id".to_string(), worker.app_id.clone());
+        labels.insert("nots.app_version".to_string(), worker.app_version.clone());
+
+        self.pull_image(&image, &tag).await?;
+        self.ensure_network().await?;
+
+        let container_id = self
+            .create_worker_container(WorkerContainer {
+                name: &name,
+                image: &format!("{}:{}", image, tag),
+                cmd,
+                env,
+                labels,
+                port,
+                binds,
+                limits: &worker.settings.limits,
+            })
+            .await?;
+
+        // the code is copied instead of mounted, since notsd might run in a container itself
+        let code = pack_code(worker.code_path.clone()).await?;
+        self.client
+            .upload_to_container(
+                &container_id,
+                Some(UploadToContainerOptions {
+                    path: "/",
+                    ..Default::default()
+                }),
+                code,
+            )
+            .await?;
+
+        self.start_container(&container_id).await?;
+        tokio::spawn(forward_logs(
+            self.client.clone(),
+            container_id.clone(),
+            worker.worker_id.clone(),
+            worker.logs.clone(),
+        ));
+
+        let container = self.client.inspect_container(&container_id, None).await?;
+        let address = container
+            .network_settings
+            .and_then(|n| n.networks)
+            .and_then(|n| n.get(&self.settings.network).cloned())
+            .and_then(|n| n.ip_address)
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| format!("{}:{}", ip, port));
+
+        Ok(CreatedWorker {
+            container_id: Some(container_id),
+            address,
+            ..Default::default()
+        })
     }
 
     async fn workers_get(&self) -> Result<HashMap<String, WorkerStatus>> {
         let all = self.get_all_worker_containers().await?;
         let mut workers = HashMap::new();
         for c in all {
-            let Some(id) = c.id else {
+            // container names are prefixed with a slash
+            let prefix = format!("/{}-", self.settings.worker_prefix);
+            let Some(id) = c.names.iter().flatten().find_map(|n| n.strip_prefix(&prefix)) else {
                 continue;
             };
 
-            let status = string_to_status(c.status);
-            workers.insert(id, status);
+            let status = string_to_status(c.state);
+            workers.insert(id.to_string(), status);
         }
         Ok(workers)
     }
 
     async fn worker_state(&self, id: &str) -> Result<WorkerState> {
-        let container = self.client.inspect_container(id, None).await?;
+        let container = self.client.inspect_container(&self.container_name(id), None).await?;
         Ok(inspect_to_state(container))
     }
 
     async fn worker_remove(&self, id: &str) -> Result<()> {
         self.client
             .remove_container(
-                id,
+                &self.container_name(id),
                 Some(RemoveContainerOptions {
                     force: true,
                     ..Default::default()
@@ -91,6 +179,21 @@ impl NotsBackend for DockerRuntime {
             .await?;
         Ok(())
     }
+
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    async fn worker_pause(&self, id: &str) -> Result<()> {
+        // docker uses the cgroup freezer for this as well
+        self.client.pause_container(&self.container_name(id)).await?;
+        Ok(())
+    }
+
+    async fn worker_resume(&self, id: &str) -> Result<()> {
+        self.client.unpause_container(&self.container_name(id)).await?;
+        Ok(())
+    }
 }
 
 impl DockerRuntime {
@@ -100,6 +203,10 @@ impl DockerRuntime {
         Ok(Self { client, settings })
     }
 
+    fn container_name(&self, worker_id: &str) -> String {
+        format!("{}-{}", self.settings.worker_prefix, worker_id)
+    }
+
     async fn get_all_worker_containers(&self) -> Result<Vec<ContainerSummary>> {
         let mut filters = HashMap::new();
         filters.insert("label".to_string(), vec!["nots=worker".to_string()]);
@@ -122,28 +229,44 @@ impl DockerRuntime {
         Ok(())
     }
 
-    async fn create_worker_container(
-        &self,
-        name: &str,
-        image: &str,
-        tag: &str,
-        binds: Option<Vec<String>>,
-    ) -> Result<String> {
-        let mut binds = binds.unwrap_or_default();
+    async fn create_worker_container(&self, container: WorkerContainer<'_>) -> Result<String> {
+        let mut binds = container.binds.unwrap_or_default();
         binds.push("notsd-worker-api:/tmp/nots/worker:rw".to_string());
 
+        let limits = container.limits;
         let host_config = bollard::models::HostConfig {
             binds: Some(binds),
+            network_mode: Some(self.settings.network.clone()),
+            cpu_shares: limits.cpu_shares.map(|shares| shares as i64),
+            nano_cpus: limits.cpus.map(|cpus| (cpus * 1e9) as i64),
+            memory: limits.memory.map(|memory| memory as i64),
+            // docker limits memory and swap together, -1 doesn't limit swap like it is for process workers
+            memory_swap: limits
+                .memory
+                .map(|memory| limits.swap.map_or(-1, |swap| memory.saturating_add(swap) as i64)),
+            pids_limit: limits.pids.map(|pids| pids as i64),
             ..Default::default()
         };
 
+        // borrowed so the env vars are only kept in the zeroized buffers
+        let port = format!("{}/tcp", container.port);
         let c = self
             .client
             .create_container(
-                Some(CreateContainerOptions { name, platform: None }),
+                Some(CreateContainerOptions {
+                    name: container.name,
+                    platform: None,
+                }),
                 bollard::container::Config {
-                    image: Some("hello-world"),
-                    cmd: Some(vec!["echo", "hello world"]),
+                    image: Some(container.image),
+                    cmd: container
+                        .cmd
+                        .as_ref()
+                        .map(|cmd| cmd.iter().map(|s| s.as_str()).collect()),
+                    env: Some(container.env.iter().map(|s| s.as_str()).collect()),
+                    labels: Some(container.labels.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()),
+                    working_dir: Some(CODE_DIR),
+                    exposed_ports: Some(HashMap::from([(port.as_str(), HashMap::new())])),
                     host_config: Some(host_config),
                     ..Default::default()
                 },
@@ -153,6 +276,47 @@ impl DockerRuntime {
         Ok(c.id)
     }
 
+    async fn pull_image(&self, image: &str, tag: &str) -> Result<()> {
+        if self.client.inspect_image(&format!("{}:{}", image, tag)).await.is_ok() {
+            return Ok(());
+        }
+
+        info!("Pulling image {}:{}", image, tag);
+        let options = CreateImageOptions {
+            from_image: image,
+            tag,
+            ..Default::default()
+        };
+
+        let mut stream = self.client.create_image(Some(options), None, None);
+        while let Some(info) = stream.next().await {
+            info.wrap_err_with(|| format!("Could not pull image {}:{}", image, tag))?;
+        }
+
+        Ok(())
+    }
+
+    /// Workers share a network that notsd uses to reach them
+    async fn ensure_network(&self) -> Result<()> {
+        if self
+            .client
+            .inspect_network::<String>(&self.settings.network, None)
+            .await
+            .is_ok()
+        {
+            return Ok(());
+        }
+
+        match self.create_network(&self.settings.network, true).await {
+            Err(e) => match e.downcast_ref() {
+                // another worker created it in the meantime
+                Some(bollard::errors::Error::DockerResponseServerError { status_code: 409, .. }) => Ok(()),
+                _ => Err(e),
+            },
+            res => res,
+        }
+    }
+
     async fn create_network(&self, name: &str, external_access: bool) -> Result<()> {
         self.client
             .create_network(CreateNetworkOptions {
@@ -181,6 +345,7 @@ fn inspect_to_state(container: bollard::service::ContainerInspectResponse) -> Wo
     WorkerState {
         status: string_to_status(Some(status)),
         restart_count,
+        oom_killed: state.oom_killed.unwrap_or_default(),
     }
 }
 
@@ -196,3 +361,70 @@ fn string_to_status(s: Option<String>) -> WorkerStatus {
         _ => WorkerStatus::Dead,
     }
 }
+
+/// Command to start the worker with, `None` uses the default command of the image
+fn worker_command(settings: &WorkerSettings, runtime: Option<&str>) -> Result<Option<Vec<String>>> {
+    let main = settings.main.as_deref().map(shell_quote);
+    let start = match (&settings.command, main, runtime) {
+        (Some(command), Some(main), _) => format!("{} {}", command, main),
+        (Some(command), None, _) => command.clone(),
+        (None, Some(main), Some(runtime)) => format!("{} {}", runtime, main),
+        (None, Some(main), None) => format!("./{}", main),
+        (None, None, None) if settings.prepare.is_none() => return Ok(None),
+        (None, None, None) => bail!("`prepare` requires `command` or `main` to be set"),
+        (None, None, Some(_)) => bail!("Either `command` or `main` has to be set"),
+    };
+
+    let script = match &settings.prepare {
+        Some(prepare) => format!("{} && {}", prepare, start),
+        None => start,
+    };
+
+    Ok(Some(vec!["sh".to_string(), "-c".to_string(), script]))
+}
+
+/// Tar the code directory so it ends up in [`CODE_DIR`] when extracted at `/`
+async fn pack_code(code_path: PathBuf) -> Result<Bytes> {
+    tokio::task::spawn_blocking(move || {
+        let mut archive = tar::Builder::new(Vec::new());
+        archive.follow_symlinks(false);
+        archive
+            .append_dir_all(CODE_DIR.trim_start_matches('/'), &code_path)
+            .wrap_err_with(|| format!("Could not read code at {}", code_path.display()))?;
+        Ok(Bytes::from(archive.into_inner()?))
+    })
+    .await?
+}
+
+/// Follow the output of a container and send it to the worker's log store until the container stops
+async fn forward_logs(client: bollard::Docker, container_id: String, worker_id: String, logs: LogSender) {
+    let options = LogsOptions::<String> {
+        follow: true,
+        stdout: true,
+        stderr: true,
+        ..Default::default()
+    };
+
+    // a chunk of output doesn't necessarily end with a newline, so incomplete lines are kept until the rest arrives
+    let (mut stdout, mut stderr) = (LineBuffer::default(), LineBuffer::default());
+    let mut output = client.logs(&container_id, Some(options));
+    while let Some(Ok(chunk)) = output.next().await {
+        let (stream, buf, message) = match chunk {
+            LogOutput::StdErr { message } => (LogStream::Stderr, &mut stderr, message),
+            LogOutput::StdOut { message } | LogOutput::Console { message } => (LogStream::Stdout, &mut stdout, message),
+            LogOutput::StdIn { .. } => continue,
+        };
+
+        for line in buf.push(&message) {
+            if send_log(&logs, &worker_id, stream, &line).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    for (stream, buf) in [(LogStream::Stdout, stdout), (LogStream::Stderr, stderr)] {
+        if let Some(rest) = buf.finish() {
+            let _ = send_log(&logs, &worker_id, stream, &rest).await;
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use axum::async_trait;
-use color_eyre::eyre::Result;
-use nots_client::models::{WorkerRuntimeOptions, WorkerState, WorkerStatus};
-use std::collections::HashMap;
+use color_eyre::eyre::{bail, Result};
+use nots_client::models::{
+    LogEntry, LogStream, ResourceLimits, WorkerRuntimeOptions, WorkerSettings, WorkerState, WorkerStatus,
+};
+use std::{collections::HashMap, path::PathBuf};
+use zeroize::Zeroizing;
 
 #[cfg(feature = "docker")]
 mod docker;
@@ -9,6 +12,8 @@ mod docker;
 #[cfg(feature = "docker")]
 pub use docker::*;
 
+#[cfg(feature = "process")]
+mod cgroup;
 #[cfg(feature = "process")]
 mod process;
 
@@ -22,15 +27,155 @@ pub fn try_new(backend: &str) -> Result<Box<dyn NotsBackend + Sync>> {
     }
 }
 
+/// A runtime for workers, all ids are the ids of the workers, not the ids used by the runtime
 #[async_trait]
 pub trait NotsBackend: Send + Sync {
     async fn workers_get(&self) -> Result<HashMap<String, WorkerStatus>>;
-    async fn worker_create(&self, worker: CreateWorker) -> Result<()>;
+    async fn worker_create(&self, worker: CreateWorker) -> Result<CreatedWorker>;
     async fn worker_state(&self, id: &str) -> Result<WorkerState>;
     async fn worker_remove(&self, id: &str) -> Result<()>;
+
+    /// Fails if the backend can't enforce the given limits
+    fn check_limits(&self, _limits: &ResourceLimits) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether workers can be paused, which keeps them in memory without using any CPU time
+    fn can_pause(&self) -> bool {
+        false
+    }
+
+    /// Freeze all processes of a running worker, its status becomes `WorkerStatus::Paused`
+    async fn worker_pause(&self, _id: &str) -> Result<()> {
+        bail!("Pausing workers is not supported by this backend")
+    }
+
+    /// Thaw a paused worker
+    async fn worker_resume(&self, _id: &str) -> Result<()> {
+        bail!("Pausing workers is not supported by this backend")
+    }
 }
 
 pub struct CreateWorker {
     pub worker_id: String,
+    pub app_id: String,
+    pub app_version: String,
     pub runtime_options: WorkerRuntimeOptions,
+    pub settings: WorkerSettings,
+    pub env: Vec<(String, Zeroizing<String>)>, // `settings.env` with secrets resolved, use this instead
+    pub code_path: PathBuf,                    // unpacked artifact of `app_version`
+    pub logs: LogSender,                       // receives the output of the worker line by line
+}
+
+/// Channel to the log store of the worker's app
+pub type LogSender = tokio::sync::mpsc::Sender<LogEntry>;
+
+/// Longer lines of a worker's output are truncated
+pub const MAX_LINE_LENGTH: usize = 16 * 1024;
+
+/// Send a line of a worker's output to the log store, fails if the store doesn't accept logs anymore
+pub async fn send_log(logs: &LogSender, worker_id: &str, stream: LogStream, line: &[u8]) -> Result<()> {
+    let line = match line.strip_suffix(b"\n") {
+        Some(line) => line.strip_suffix(b"\r").unwrap_or(line),
+        None => line,
+    };
+    let line = if line.len() > MAX_LINE_LENGTH {
+        format!("{} [truncated]", String::from_utf8_lossy(&line[..MAX_LINE_LENGTH]))
+    } else {
+        String::from_utf8_lossy(line).to_string()
+    };
+
+    let entry = LogEntry {
+        timestamp: time::OffsetDateTime::now_utc(),
+        worker_id: worker_id.to_string(),
+        stream,
+        line,
+    };
+    if logs.send(entry).await.is_err() {
+        bail!("Log store of worker {} is closed", worker_id);
+    }
+    Ok(())
+}
+
+/// Splits a worker's output into lines, keeping only as much of a line as `send_log` stores
+#[derive(Default)]
+pub struct LineBuffer {
+    line: Vec<u8>, // incomplete last line, at most one byte longer than `MAX_LINE_LENGTH`
+}
+
+impl LineBuffer {
+    /// Append a chunk of output and return the lines it completes, including their newline
+    pub fn push(&mut self, mut chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        while !chunk.is_empty() {
+            let (part, end) = match chunk.iter().position(|b| *b == b'\n') {
+                Some(end) => (&chunk[..=end], true),
+                None => (chunk, false),
+            };
+            chunk = &chunk[part.len()..];
+
+            // the rest of an overlong line is dropped, one extra byte marks it as truncated
+            let room = (MAX_LINE_LENGTH + 1).saturating_sub(self.line.len());
+            self.line.extend_from_slice(&part[..part.len().min(room)]);
+            if end {
+                lines.push(std::mem::take(&mut self.line));
+            }
+        }
+        lines
+    }
+
+    /// The last line if it didn't end with a newline
+    pub fn finish(self) -> Option<Vec<u8>> {
+        (!self.line.is_empty()).then_some(self.line)
+    }
+}
+
+/// Quote an argument for `sh -c`, e.g. a `main` file name with spaces or quotes
+#[cfg(any(feature = "docker", feature = "process"))]
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[derive(Default)]
+pub struct CreatedWorker {
+    pub container_id: Option<String>,
+    pub process_id: Option<u32>,
+    pub address: Option<String>, // host:port the worker can be reached at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_split_across_chunks() {
+        let mut buf = LineBuffer::default();
+        assert!(buf.push(b"hel").is_empty());
+        assert_eq!(buf.push(b"lo\nworld\n!"), [b"hello\n".to_vec(), b"world\n".to_vec()]);
+        assert_eq!(buf.finish(), Some(b"!".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn long_lines_are_truncated() {
+        let mut buf = LineBuffer::default();
+        for _ in 0..4 {
+            assert!(buf.push(&[b'x'; MAX_LINE_LENGTH]).is_empty());
+        }
+        let lines = buf.push(b"xx\nnext\n");
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), MAX_LINE_LENGTH + 1);
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        send_log(&tx, "w1", LogStream::Stdout, &lines[0]).await.unwrap();
+        let entry = rx.recv().await.unwrap();
+        assert_eq!(entry.line.len(), MAX_LINE_LENGTH + " [truncated]".len());
+        assert!(entry.line.ends_with("x [truncated]"));
+    }
+
+    #[cfg(any(feature = "docker", feature = "process"))]
+    #[test]
+    fn arguments_are_quoted() {
+        assert_eq!(shell_quote("index.js"), "'index.js'");
+        assert_eq!(shell_quote("it's; rm -rf /"), "'it'\\''s; rm -rf /'");
+    }
 }
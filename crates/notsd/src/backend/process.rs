@@ -1 +1,289 @@
+use std::{collections::HashMap, os::fd::AsRawFd, path::Path, process::Stdio, sync::Arc, time::Duration};
 
+use super::{
+    cgroup::{Cgroup, Cgroups},
+    send_log, shell_quote, CreateWorker, CreatedWorker, LineBuffer, LogSender, NotsBackend, WorkerState, WorkerStatus,
+};
+use axum::async_trait;
+use color_eyre::eyre::{bail, Context, ContextCompat, Result};
+use nots_client::models::{LogStream, ResourceLimits, WorkerRuntimeOptions};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::{Child, Command},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+/// Time a worker has to shut down after SIGTERM before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct ProcessWorker {
+    child: Child,
+    pid: u32,
+    cgroup: Option<Cgroup>, // all processes of the worker, if cgroup v2 is available
+    paused: bool,
+}
+
+/// Runs workers as child processes of notsd, without any isolation
+#[derive(Default)]
+pub struct ProcessRuntime {
+    workers: Arc<Mutex<HashMap<String, ProcessWorker>>>,
+    cgroups: Option<Cgroups>,
+}
+
+#[async_trait]
+impl NotsBackend for ProcessRuntime {
+    async fn workers_get(&self) -> Result<HashMap<String, WorkerStatus>> {
+        let mut workers = self.workers.lock().await;
+        let mut res = HashMap::new();
+        for (id, w) in workers.iter_mut() {
+            res.insert(id.clone(), status(w)?);
+        }
+        Ok(res)
+    }
+
+    async fn worker_create(&self, worker: CreateWorker) -> Result<CreatedWorker> {
+        let WorkerRuntimeOptions::Process {} = worker.runtime_options else {
+            bail!("Invalid runtime options for runtime");
+        };
+
+        let code_path = std::fs::canonicalize(&worker.code_path)
+            .wrap_err_with(|| format!("Code of version {} not found", worker.app_version))?;
+
+        let port = match worker.settings.port {
+            Some(port) => port,
+            None => free_port()?,
+        };
+
+        if let Some(prepare) = &worker.settings.prepare {
+            info!("Worker {}: running `{}`", worker.worker_id, prepare);
+            let mut cmd = shell(prepare);
+            let out = configure(&mut cmd, &code_path, &worker.env, port).output().await?;
+            log_lines(&worker.logs, &worker.worker_id, LogStream::Stdout, &out.stdout).await;
+            log_lines(&worker.logs, &worker.worker_id, LogStream::Stderr, &out.stderr).await;
+            if !out.status.success() {
+                bail!("Prepare command `{}` failed with {}", prepare, out.status);
+            }
+        }
+
+        let mut cmd = match (&worker.settings.command, &worker.settings.main) {
+            (Some(command), Some(main)) => shell(&format!("{} {}", command, shell_quote(main))),
+            (Some(command), None) => shell(command),
+            (None, Some(main)) => Command::new(code_path.join(main)),
+            (None, None) => bail!("Either `command` or `main` has to be set to run an app as a process"),
+        };
+
+        self.check_limits(&worker.settings.limits)?;
+        let cgroup = match &self.cgroups {
+            Some(cgroups) => Some(cgroups.create(&worker.worker_id)?),
+            None => None,
+        };
+        if let Some(cgroup) = &cgroup {
+            cgroup.set_limits(&worker.settings.limits)?;
+        }
+        let procs = cgroup.as_ref().map(Cgroup::procs_file).transpose()?;
+        let procs_fd = procs.as_ref().map(|f| f.as_raw_fd());
+
+        configure(&mut cmd, &code_path, &worker.env, port)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        unsafe {
+            // the worker gets its own process group so commands run through `sh` are stopped as well
+            cmd.pre_exec(|| match libc::setpgid(0, 0) {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            });
+        }
+
+        #[cfg(target_os = "linux")]
+        unsafe {
+            // don't leave workers behind if notsd is killed
+            cmd.pre_exec(move || {
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+
+                // join the worker's cgroup before exec, so everything the worker starts ends up in it
+                if let Some(fd) = procs_fd {
+                    if libc::write(fd, b"0".as_ptr().cast(), 1) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        let mut child = cmd
+            .spawn()
+            .wrap_err_with(|| format!("Could not start worker {}", worker.worker_id))?;
+        drop(procs);
+        let pid = child.id().context("Worker exited immediately")?;
+
+        if let Some(stdout) = child.stdout.take() {
+            let (id, logs) = (worker.worker_id.clone(), worker.logs.clone());
+            tokio::spawn(async move { forward_output(&logs, &id, LogStream::Stdout, stdout).await });
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let (id, logs) = (worker.worker_id.clone(), worker.logs.clone());
+            tokio::spawn(async move { forward_output(&logs, &id, LogStream::Stderr, stderr).await });
+        }
+
+        info!("Worker {}: started process {} on port {}", worker.worker_id, pid, port);
+        self.workers.lock().await.insert(
+            worker.worker_id,
+            ProcessWorker {
+                child,
+                pid,
+                cgroup,
+                paused: false,
+            },
+        );
+
+        Ok(CreatedWorker {
+            process_id: Some(pid),
+            address: Some(format!("127.0.0.1:{}", port)),
+            ..Default::default()
+        })
+    }
+
+    async fn worker_state(&self, id: &str) -> Result<WorkerState> {
+        let mut workers = self.workers.lock().await;
+        let worker = workers.get_mut(id).context("Worker not found")?;
+
+        Ok(WorkerState {
+            status: status(worker)?,
+            restart_count: None,
+            oom_killed: worker.cgroup.as_ref().is_some_and(Cgroup::oom_killed),
+        })
+    }
+
+    async fn worker_remove(&self, id: &str) -> Result<()> {
+        let Some(mut worker) = self.workers.lock().await.remove(id) else {
+            return Ok(());
+        };
+
+        if worker.child.try_wait()?.is_none() {
+            stop(id, &mut worker).await?;
+        }
+
+        if let Some(cgroup) = &worker.cgroup {
+            cgroup.remove().await?;
+        }
+        Ok(())
+    }
+
+    fn check_limits(&self, limits: &ResourceLimits) -> Result<()> {
+        match &self.cgroups {
+            Some(cgroups) => cgroups.check_limits(limits),
+            None if limits.is_empty() => Ok(()),
+            None => bail!("Resource limits need cgroup v2"),
+        }
+    }
+
+    fn can_pause(&self) -> bool {
+        self.cgroups.is_some()
+    }
+
+    async fn worker_pause(&self, id: &str) -> Result<()> {
+        self.set_paused(id, true).await
+    }
+
+    async fn worker_resume(&self, id: &str) -> Result<()> {
+        self.set_paused(id, false).await
+    }
+}
+
+impl ProcessRuntime {
+    pub fn new() -> Self {
+        let cgroups = Cgroups::detect();
+        if cgroups.is_none() {
+            warn!("cgroup v2 is not available, process workers can't be paused or limited");
+        }
+
+        Self {
+            cgroups,
+            ..Default::default()
+        }
+    }
+
+    async fn set_paused(&self, id: &str, paused: bool) -> Result<()> {
+        let mut workers = self.workers.lock().await;
+        let worker = workers.get_mut(id).context("Worker not found")?;
+        let cgroup = worker.cgroup.as_ref().context("Worker has no cgroup")?;
+
+        cgroup.set_frozen(paused).await?;
+        worker.paused = paused;
+        Ok(())
+    }
+}
+
+/// SIGTERM the whole process group first to allow a graceful shutdown, then SIGKILL it
+async fn stop(id: &str, worker: &mut ProcessWorker) -> Result<()> {
+    // frozen processes can't handle SIGTERM
+    if let (Some(cgroup), true) = (&worker.cgroup, worker.paused) {
+        cgroup.set_frozen(false).await?;
+    }
+
+    unsafe { libc::kill(-(worker.pid as i32), libc::SIGTERM) };
+    if tokio::time::timeout(STOP_TIMEOUT, worker.child.wait()).await.is_err() {
+        warn!("Worker {} did not stop in time, killing it", id);
+        unsafe { libc::kill(-(worker.pid as i32), libc::SIGKILL) };
+        worker.child.wait().await?;
+    }
+    Ok(())
+}
+
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+fn configure<'a>(
+    cmd: &'a mut Command,
+    code_path: &Path,
+    env: &[(String, Zeroizing<String>)],
+    port: u16,
+) -> &'a mut Command {
+    cmd.current_dir(code_path)
+        .envs(env.iter().map(|(k, v)| (k, v.as_str())))
+        .env("PORT", port.to_string())
+        .stdin(Stdio::null())
+}
+
+fn status(worker: &mut ProcessWorker) -> Result<WorkerStatus> {
+    Ok(match worker.child.try_wait()? {
+        Some(_) => WorkerStatus::Exited,
+        None if worker.paused => WorkerStatus::Paused,
+        None => WorkerStatus::Running,
+    })
+}
+
+/// Ask the OS for a port that is currently unused
+fn free_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Send the output of a worker to its log store until the worker closes it
+async fn forward_output(logs: &LogSender, worker_id: &str, stream: LogStream, mut output: impl AsyncRead + Unpin) {
+    let mut lines = LineBuffer::default();
+    let mut chunk = vec![0; 8 * 1024];
+    while let Ok(n @ 1..) = output.read(&mut chunk).await {
+        for line in lines.push(&chunk[..n]) {
+            // keep reading if the store is gone, the worker would block on a full pipe otherwise
+            let _ = send_log(logs, worker_id, stream, &line).await;
+        }
+    }
+
+    if let Some(rest) = lines.finish() {
+        let _ = send_log(logs, worker_id, stream, &rest).await;
+    }
+}
+
+async fn log_lines(logs: &LogSender, worker_id: &str, stream: LogStream, output: &[u8]) {
+    for line in output.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+        let _ = send_log(logs, worker_id, stream, line).await;
+    }
+}
@@ -1 +1,222 @@
+use std::{
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use nots_client::models::{App, ArtifactKind, GitSource, Repo};
+use tokio::{process::Command, task::JoinSet};
+use tracing::{debug, error, info, warn};
+
+use crate::{state::AppStateInner, utils::AwaitAll};
+
+/// How often repositories are checked for new commits
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time a single git command may take, e.g. the initial clone of a large repository
+const GIT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+impl AppStateInner {
+    /// Deploy new commits of apps with a git repository
+    pub async fn watch_repos(self: Arc<Self>) {
+        loop {
+            if let Err(e) = self.check_repos().await {
+                error!("Git: {:?}", e);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                _ = self.repos_changed.notified() => debug!("Git: woken up by app change"),
+            }
+        }
+    }
+
+    async fn check_repos(self: &Arc<Self>) -> Result<()> {
+        let mut set = JoinSet::new();
+        for (app_id, app) in self.get_apps()? {
+            if app.git.is_some() {
+                let state = self.clone();
+                set.spawn(async move { state.check_repo(&app_id, &app).await });
+            }
+        }
+        set.await_all("git").await
+    }
+
+    /// Fetch the app's branch and deploy it if the latest commit wasn't deployed yet
+    async fn check_repo(&self, app_id: &str, app: &App) -> Result<()> {
+        let Some(source) = &app.git else {
+            return Ok(());
+        };
+
+        let mirror = self.file.local_path(&format!("repos/{}", app_id));
+        let key = match &source.repo {
+            Repo::DeployKey { id, .. } => {
+                let key = self.get_deploy_private_key(id)?;
+                Some(KeyFile::create(
+                    &self.file.local_path(&format!("keys/{}", app_id)),
+                    &key,
+                )?)
+            }
+            Repo::PublicHttps { .. } => None,
+        };
+
+        fetch(&mirror, source, key.as_ref())
+            .await
+            .wrap_err_with(|| format!("Could not fetch {} for app {}", source.repo.url(), app.name))?;
+
+        let commit = git(
+            &mirror,
+            &[
+                "rev-parse",
+                "--verify",
+                &format!("refs/heads/{}^{{commit}}", source.branch),
+            ],
+        )
+        .await?;
+        let commit = String::from_utf8(commit)?.trim().to_string();
+        if self.latest_git_commit(app_id)?.as_deref() == Some(commit.as_str()) {
+            return Ok(());
+        }
+
+        // deploys through the API fail in this case, here the commit is picked up again once the secrets are set
+        let missing = self.missing_secrets(app_id, &app.worker_settings.env)?;
+        if !missing.is_empty() {
+            warn!(
+                "Git: not deploying commit {} to app {}, missing secrets: {}",
+                commit,
+                app.name,
+                missing.join(", ")
+            );
+            return Ok(());
+        }
+
+        info!(
+            "Git: deploying commit {} of {} to app {}",
+            commit,
+            source.repo.url(),
+            app.name
+        );
+        let tree = match &source.path {
+            Some(path) => format!("{}:{}", commit, path.trim_matches('/')),
+            None => commit.clone(),
+        };
+
+        let archive = git(&mirror, &["archive", "--format=tar.gz", &tree]).await?;
+        let body = futures::stream::once(async { Ok::<_, std::io::Error>(archive.into()) });
+        let artifact = self
+            .store_artifact(app_id, ArtifactKind::Git { commit }, Box::pin(body))
+            .await?;
+
+        self.deploy_artifact(app_id, &artifact.id, "git")?;
+        // every pushed commit is a new artifact, old ones would fill the disk otherwise
+        self.prune_artifacts(app_id).await
+    }
+
+    /// Commit of the newest artifact created from the app's repository
+    fn latest_git_commit(&self, app_id: &str) -> Result<Option<String>> {
+        Ok(self
+            .get_artifacts(app_id)?
+            .into_iter()
+            .rev()
+            .find_map(|artifact| match artifact.kind {
+                ArtifactKind::Git { commit } => Some(commit),
+                _ => None,
+            }))
+    }
+}
+
+/// Keep a bare mirror of the watched branch, creating it on first use
+async fn fetch(mirror: &Path, source: &GitSource, key: Option<&KeyFile>) -> Result<()> {
+    if !mirror.exists() {
+        std::fs::create_dir_all(mirror)?;
+        git(mirror, &["init", "--bare", "--quiet"]).await?;
+    }
+
+    let mut cmd = git_command(mirror);
+    if let Some(key) = key {
+        // ignore the user's ssh config and agent, and remember the host key on first use
+        let known_hosts = mirror.join("known_hosts");
+        cmd.env(
+            "GIT_SSH_COMMAND",
+            format!(
+                "ssh -F /dev/null -i '{}' -o IdentitiesOnly=yes -o IdentityAgent=none -o BatchMode=yes \
+                 -o StrictHostKeyChecking=accept-new -o UserKnownHostsFile='{}'",
+                key.0.display(),
+                known_hosts.display()
+            ),
+        );
+    }
+
+    // the url is passed on every fetch instead of configuring a remote, so changing the app's repository just works
+    let refspec = format!("+refs/heads/{0}:refs/heads/{0}", source.branch);
+    cmd.args([
+        "fetch",
+        "--quiet",
+        "--depth=1",
+        "--no-tags",
+        source.repo.url(),
+        &refspec,
+    ]);
+    run(cmd, "fetch").await?;
+    Ok(())
+}
+
+/// Private key written for a single fetch, ssh can only read keys from files
+struct KeyFile(PathBuf);
+
+impl KeyFile {
+    fn create(path: &Path, key: &[u8]) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        }
+
+        let _ = std::fs::remove_file(path);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .wrap_err("Could not write deploy key")?;
+
+        file.write_all(key)?;
+        Ok(Self(path.to_path_buf()))
+    }
+}
+
+impl Drop for KeyFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn git_command(dir: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C")
+        .arg(dir)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        // urls are validated by the API, this also keeps submodules and redirects from using e.g. `ext::` or `file://`
+        .env("GIT_ALLOW_PROTOCOL", "https:ssh")
+        .kill_on_drop(true);
+    cmd
+}
+
+async fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let mut cmd = git_command(dir);
+    cmd.args(args);
+    run(cmd, args[0]).await
+}
+
+async fn run(mut cmd: Command, name: &str) -> Result<Vec<u8>> {
+    let out = tokio::time::timeout(GIT_TIMEOUT, cmd.output())
+        .await
+        .map_err(|_| eyre!("git {} timed out", name))?
+        .wrap_err("Could not run git, is it installed?")?;
+
+    if !out.status.success() {
+        bail!("git {} failed: {}", name, String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(out.stdout)
+}
@@ -2,6 +2,9 @@ pub struct Env {
     pub nots_secret: String,
     pub nots_api_bind: String,
     pub nots_backend: String,
+
+    pub nots_socket_uid: Option<u32>, // owner of the API socket, set by `nots server`
+    pub nots_socket_gid: Option<u32>, // group of the API socket, usually `nots`
 }
 
 pub fn new() -> Env {
@@ -21,6 +24,12 @@ impl Env {
             nots_secret,
             nots_backend: env::var("NOTS_BACKEND").unwrap_or("docker".to_string()),
             nots_api_bind: env::var("NOTS_API_BIND").unwrap_or("/tmp/nots/api.sock".to_string()),
+            nots_socket_uid: env::var("NOTS_SOCKET_UID")
+                .ok()
+                .map(|uid| uid.parse().expect("Invalid NOTS_SOCKET_UID")),
+            nots_socket_gid: env::var("NOTS_SOCKET_GID")
+                .ok()
+                .map(|gid| gid.parse().expect("Invalid NOTS_SOCKET_GID")),
         }
     }
 }
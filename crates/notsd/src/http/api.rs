@@ -1,16 +1,34 @@
-use axum::extract::State;
-use axum::http::HeaderValue;
+use std::collections::HashMap;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderValue, StatusCode};
 use axum::middleware::Next;
-use axum::response::{IntoResponse, Response};
+use axum::response::Response;
 use axum::routing::{get, post};
-use axum::{Json, Router};
+use axum::{Extension, Json, Router};
+use futures::StreamExt;
 use hyper::Request;
-use nots_client::api::{CreateAppRequest, ServerStatus};
+use nots_client::api::{
+    CreateAppRequest, CreateAppResponse, CreateDeployKeyRequest, CreateProjectRequest, CreateTokenRequest,
+    CreateTokenResponse, DeployRequest, LogsQuery, RollbackRequest, SecretValue, ServerStatus, SetSecretRequest,
+    UpdateAppRequest, UpdateProjectRequest, UploadArtifactQuery,
+};
+use nots_client::models::{
+    App, Artifact, DeployKeyInfo, GitSource, HealthCheck, HealthCheckKind, Match, Project, Release, Repo,
+    ResourceLimits, SecretInfo, TokenInfo, WorkerRuntimeOptions, WorkerSettings, DEFAULT_PROJECT,
+};
+use zeroize::Zeroizing;
 
-use crate::state::AppState;
+use super::auth::{self, can_access};
+use super::Error;
+use crate::state::{is_within, previous_release, AppState};
 
 const POWERED_BY: &str = concat!("nots/", env!("CARGO_PKG_VERSION"));
 
+/// Workers per app, all of them run on this server
+const MAX_REPLICAS: u32 = 64;
+
 async fn add_version(request: Request<axum::body::Body>, next: Next) -> Response {
     let mut response = next.run(request).await;
     response
@@ -21,12 +39,40 @@ async fn add_version(request: Request<axum::body::Body>, next: Next) -> Response
 
 pub fn new(app_state: AppState) -> Router {
     Router::new()
-        .route("/", get(hi))
         .route("/status", get(server_status))
         .route("/app", post(create_app))
-        .route("/app/:id", post(update_app))
-        .route("/app/:id", get(get_app))
+        .route("/app/:id", get(get_app).post(update_app).delete(delete_app))
+        .route("/app/:id/artifacts", get(get_artifacts).post(upload_artifact))
+        .route("/app/:id/deploy", post(deploy))
+        .route("/app/:id/logs", get(get_logs))
+        .route("/app/:id/releases", get(get_releases))
+        .route("/app/:id/rollback", post(rollback))
+        .route("/app/:id/secrets", get(get_secrets))
+        .route(
+            "/app/:id/secret/:name",
+            get(get_secret).post(set_secret).delete(delete_secret),
+        )
         .route("/apps", get(get_apps))
+        .route("/project", post(create_project))
+        .route(
+            "/project/:project",
+            get(get_project).post(update_project).delete(delete_project),
+        )
+        .route("/project/:project/app", post(create_project_app))
+        .route("/project/:project/app/:name", get(get_project_app))
+        .route("/project/:project/apps", get(get_project_apps))
+        .route("/project/:project/key", post(create_deploy_key))
+        .route("/project/:project/key/:key", axum::routing::delete(delete_deploy_key))
+        .route("/project/:project/keys", get(get_deploy_keys))
+        .route("/projects", get(get_projects))
+        .route("/token", post(create_token))
+        .route("/token/:id", axum::routing::delete(revoke_token))
+        .route("/tokens", get(get_tokens))
+        .route_layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::require_token,
+        ))
+        .route("/", get(hi))
         .with_state(app_state)
         .layer(axum::middleware::from_fn(add_version))
 }
@@ -40,22 +86,969 @@ async fn server_status(State(app): State<AppState>) -> Json<ServerStatus> {
     })
 }
 
-async fn create_app(State(app): State<AppState>, body: Json<CreateAppRequest>) -> impl IntoResponse {
-    unimplemented!()
+async fn create_app(
+    State(state): State<AppState>,
+    Extension(token): Extension<TokenInfo>,
+    Json(req): Json<CreateAppRequest>,
+) -> Result<Json<CreateAppResponse>, Error> {
+    let project = req.project.as_deref().unwrap_or(DEFAULT_PROJECT);
+    if !can_access(&token, project) {
+        return Err(Error(
+            format!(
+                "This token is restricted to project {}",
+                token.project.unwrap_or_default()
+            ),
+            403,
+        ));
+    }
+
+    let Some(project) = state.get_project(project)? else {
+        return Err(Error(format!("Project {} not found", project), 404));
+    };
+
+    validate_app_name(&req.name)?;
+    validate_matches(&req.hostnames)?;
+    validate_matches(&req.routes)?;
+    validate_hostnames(&state, &project, &req.hostnames)?;
+    if let Some(git) = &req.git {
+        validate_git(&state, &project.name, git)?;
+    }
+    if let Some(pause_timeout) = req.pause_timeout {
+        validate_pause_timeout(&state, pause_timeout)?;
+    }
+    if let Some(health_check) = &req.health_check {
+        validate_health_check(health_check)?;
+    }
+    validate_limits(&state, &req.worker_settings.limits)?;
+    validate_port(&req.worker_settings, &req.worker_runtime)?;
+    if let Some(replicas) = req.replicas {
+        validate_replicas(replicas)?;
+    }
+
+    if state.get_app_by_name(&project.name, &req.name)?.is_some() {
+        return Err(Error(
+            format!("App {} already exists in project {}", req.name, project.name),
+            409,
+        ));
+    }
+
+    let app = App {
+        name: req.name,
+        hostnames: req.hostnames,
+        routes: req.routes,
+        route_priority: req.route_priority,
+        worker_settings: req.worker_settings,
+        worker_runtime: req.worker_runtime,
+        version: None,
+        updated_at: Some(time::OffsetDateTime::now_utc()),
+        needs_restart_since: None,
+        project: project.name,
+        git: req.git,
+        commit: None,
+        idle_timeout: req.idle_timeout.filter(|t| *t > 0),
+        pause_timeout: req.pause_timeout.filter(|t| *t > 0),
+        health_check: req.health_check,
+        replicas: req.replicas.unwrap_or(1),
+    };
+
+    let has_git = app.git.is_some();
+    let Some(id) = state.create_app(app)? else {
+        return Err(Error("Could not create app".to_string(), 500));
+    };
+
+    if has_git {
+        state.repos_changed.notify_one();
+    }
+
+    Ok(Json(CreateAppResponse { id }))
+}
+
+async fn update_app(
+    State(state): State<AppState>,
+    Extension(token): Extension<TokenInfo>,
+    Path(id): Path<String>,
+    Json(req): Json<UpdateAppRequest>,
+) -> Result<Json<App>, Error> {
+    let Some(mut app) = state.get_app(&id)? else {
+        return Err(Error(format!("App {} not found", id), 404));
+    };
+
+    if let Some(name) = req.name {
+        validate_app_name(&name)?;
+        if let Some((other_id, _)) = state.get_app_by_name(&app.project, &name)? {
+            if other_id != id {
+                return Err(Error(
+                    format!("App {} already exists in project {}", name, app.project),
+                    409,
+                ));
+            }
+        }
+        app.name = name;
+    }
+
+    if let Some(hostnames) = req.hostnames {
+        validate_matches(&hostnames)?;
+        if let Some(project) = state.get_project(&app.project)? {
+            validate_hostnames(&state, &project, &hostnames)?;
+        }
+        app.hostnames = hostnames;
+    }
+
+    if let Some(routes) = req.routes {
+        validate_matches(&routes)?;
+        app.routes = routes;
+    }
+
+    if let Some(route_priority) = req.route_priority {
+        app.route_priority = route_priority;
+    }
+
+    if let Some(idle_timeout) = req.idle_timeout {
+        app.idle_timeout = (idle_timeout > 0).then_some(idle_timeout);
+    }
+
+    if let Some(pause_timeout) = req.pause_timeout {
+        validate_pause_timeout(&state, pause_timeout)?;
+        app.pause_timeout = (pause_timeout > 0).then_some(pause_timeout);
+    }
+
+    if let Some(replicas) = req.replicas {
+        validate_replicas(replicas)?;
+        app.replicas = replicas;
+    }
+
+    if let Some(health_check) = req.health_check {
+        if let Some(health_check) = &health_check {
+            validate_health_check(health_check)?;
+        }
+        app.health_check = health_check;
+    }
+
+    if let Some(git) = req.git.filter(|g| *g != app.git) {
+        if let Some(git) = &git {
+            validate_git(&state, &app.project, git)?;
+        }
+        app.git = git;
+        state.repos_changed.notify_one();
+    }
+
+    // workers only need to be restarted if the way they are started changes
+    let mut restart = false;
+    if let Some(worker_settings) = req.worker_settings.filter(|s| *s != app.worker_settings) {
+        validate_limits(&state, &worker_settings.limits)?;
+        // the workers are restarted right away, so they would fail to start
+        let missing = state.missing_secrets(&id, &worker_settings.env)?;
+        if !missing.is_empty() {
+            return Err(Error(format!("Missing secrets: {}", missing.join(", ")), 400));
+        }
+
+        app.worker_settings = worker_settings;
+        restart = true;
+    }
+
+    if let Some(worker_runtime) = req.worker_runtime.filter(|r| *r != app.worker_runtime) {
+        app.worker_runtime = worker_runtime;
+        restart = true;
+    }
+
+    if restart {
+        validate_port(&app.worker_settings, &app.worker_runtime)?;
+        return Ok(Json(state.update_app_settings(&id, app, &token.name)?));
+    }
+
+    app.updated_at = Some(time::OffsetDateTime::now_utc());
+    state.update_app(&id, app.clone())?;
+    Ok(Json(app))
+}
+
+async fn delete_app(State(state): State<AppState>, Path(id): Path<String>) -> Result<StatusCode, Error> {
+    if state.get_app(&id)?.is_none() {
+        return Err(Error(format!("App {} not found", id), 404));
+    }
+
+    // workers of deleted apps are cleaned up by the scheduler
+    state.delete_app(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_app(State(state): State<AppState>, Path(id): Path<String>) -> Result<Json<App>, Error> {
+    match state.get_app(&id)? {
+        Some(app) => Ok(Json(app)),
+        None => Err(Error(format!("App {} not found", id), 404)),
+    }
+}
+
+async fn get_apps(
+    State(state): State<AppState>,
+    Extension(token): Extension<TokenInfo>,
+) -> Result<Json<HashMap<String, App>>, Error> {
+    let mut apps = state.get_apps()?;
+    apps.retain(|_, app| can_access(&token, &app.project));
+    Ok(Json(apps))
+}
+
+async fn upload_artifact(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<UploadArtifactQuery>,
+    body: Body,
+) -> Result<Json<Artifact>, Error> {
+    let filename = query.filename.as_str();
+    if filename.is_empty() || filename.contains('/') || filename.starts_with('.') {
+        return Err(Error(format!("Invalid filename: {}", filename), 400));
+    }
+
+    if state.get_app(&id)?.is_none() {
+        return Err(Error(format!("App {} not found", id), 404));
+    }
+
+    match state.upload_artifact(&id, filename, body.into_data_stream()).await {
+        Ok(artifact) => Ok(Json(artifact)),
+        Err(e) => Err(Error(format!("Could not store artifact: {}", e), 400)),
+    }
+}
+
+async fn get_artifacts(State(state): State<AppState>, Path(id): Path<String>) -> Result<Json<Vec<Artifact>>, Error> {
+    if state.get_app(&id)?.is_none() {
+        return Err(Error(format!("App {} not found", id), 404));
+    }
+
+    Ok(Json(state.get_artifacts(&id)?))
+}
+
+async fn get_logs(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> Result<Response, Error> {
+    if state.get_app(&id)?.is_none() {
+        return Err(Error(format!("App {} not found", id), 404));
+    }
+
+    let since = query.since.and_then(|secs| {
+        let secs = time::Duration::seconds(secs.try_into().unwrap_or(i64::MAX));
+        time::OffsetDateTime::now_utc().checked_sub(secs)
+    });
+
+    let lines = state.get_logs(&id, since, query.follow).map(|entry| {
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        Ok::<_, serde_json::Error>(line)
+    });
+
+    Response::builder()
+        .header("Content-Type", "application/x-ndjson")
+        .body(Body::from_stream(lines))
+        .map_err(|e| Error(e.to_string(), 500))
+}
+
+async fn deploy(
+    State(state): State<AppState>,
+    Extension(token): Extension<TokenInfo>,
+    Path(id): Path<String>,
+    Json(req): Json<DeployRequest>,
+) -> Result<Json<App>, Error> {
+    let Some(app) = state.get_app(&id)? else {
+        return Err(Error(format!("App {} not found", id), 404));
+    };
+
+    if state.get_artifact(&id, &req.artifact_id)?.is_none() {
+        return Err(Error(format!("Artifact {} not found", req.artifact_id), 404));
+    }
+
+    let settings = req.worker_settings.as_ref().unwrap_or(&app.worker_settings);
+    let runtime = req.worker_runtime.as_ref().unwrap_or(&app.worker_runtime);
+    if let Some(worker_settings) = &req.worker_settings {
+        validate_limits(&state, &worker_settings.limits)?;
+    }
+    if req.worker_settings.is_some() || req.worker_runtime.is_some() {
+        validate_port(settings, runtime)?;
+    }
+
+    // fail early instead of leaving the app without a worker that can start
+    let missing = state.missing_secrets(&id, &settings.env)?;
+    if !missing.is_empty() {
+        return Err(Error(format!("Missing secrets: {}", missing.join(", ")), 400));
+    }
+
+    let app = state.deploy_artifact_with_settings(
+        &id,
+        &req.artifact_id,
+        req.worker_settings,
+        req.worker_runtime,
+        &token.name,
+    )?;
+    state.prune_artifacts(&id).await?;
+    Ok(Json(app))
+}
+
+async fn get_releases(State(state): State<AppState>, Path(id): Path<String>) -> Result<Json<Vec<Release>>, Error> {
+    if state.get_app(&id)?.is_none() {
+        return Err(Error(format!("App {} not found", id), 404));
+    }
+
+    Ok(Json(state.get_releases(&id)?))
+}
+
+async fn rollback(
+    State(state): State<AppState>,
+    Extension(token): Extension<TokenInfo>,
+    Path(id): Path<String>,
+    Json(req): Json<RollbackRequest>,
+) -> Result<Json<Release>, Error> {
+    if state.get_app(&id)?.is_none() {
+        return Err(Error(format!("App {} not found", id), 404));
+    }
+
+    let releases = state.get_releases(&id)?;
+    let Some(current) = releases.last() else {
+        return Err(Error("App has no releases to roll back to".to_string(), 404));
+    };
+
+    let release = match req.release {
+        Some(_) if req.previous_version => {
+            return Err(Error(
+                "`release` and `previous_version` can't be used together".to_string(),
+                400,
+            ));
+        }
+        Some(number) if number == current.number => {
+            return Err(Error(format!("Release {} is the current release", number), 400));
+        }
+        Some(number) => match state.get_release(&id, number)? {
+            Some(release) => release,
+            None => return Err(Error(format!("Release {} not found", number), 404)),
+        },
+        None => match previous_release(&releases, req.previous_version) {
+            Some(release) => release.clone(),
+            None if req.previous_version => {
+                return Err(Error(
+                    "App has no earlier release with a different version".to_string(),
+                    404,
+                ))
+            }
+            None => return Err(Error("App has no earlier release".to_string(), 404)),
+        },
+    };
+    if state.get_artifact(&id, &release.artifact_id)?.is_none() {
+        return Err(Error(
+            format!(
+                "Artifact {} of release {} not found",
+                release.artifact_id, release.number
+            ),
+            404,
+        ));
+    }
+
+    let missing = state.missing_secrets(&id, &release.worker_settings.env)?;
+    if !missing.is_empty() {
+        return Err(Error(format!("Missing secrets: {}", missing.join(", ")), 400));
+    }
+    validate_limits(&state, &release.worker_settings.limits)?;
+    validate_port(&release.worker_settings, &release.worker_runtime)?;
+
+    Ok(Json(state.roll_back_to(&id, &release, &token.name)?))
+}
+
+async fn create_project_app(
+    state: State<AppState>,
+    token: Extension<TokenInfo>,
+    Path(project): Path<String>,
+    Json(mut req): Json<CreateAppRequest>,
+) -> Result<Json<CreateAppResponse>, Error> {
+    req.project = Some(project);
+    create_app(state, token, Json(req)).await
+}
+
+async fn get_project_app(
+    State(state): State<AppState>,
+    Path((project, name)): Path<(String, String)>,
+) -> Result<Json<CreateAppResponse>, Error> {
+    match state.get_app_by_name(&project, &name)? {
+        Some((id, _)) => Ok(Json(CreateAppResponse { id })),
+        None => Err(Error(format!("App {} not found in project {}", name, project), 404)),
+    }
+}
+
+async fn get_project_apps(
+    State(state): State<AppState>,
+    Path(project): Path<String>,
+) -> Result<Json<HashMap<String, App>>, Error> {
+    if state.get_project(&project)?.is_none() {
+        return Err(Error(format!("Project {} not found", project), 404));
+    }
+
+    Ok(Json(state.get_project_apps(&project)?))
+}
+
+async fn create_project(
+    State(state): State<AppState>,
+    Json(req): Json<CreateProjectRequest>,
+) -> Result<Json<Project>, Error> {
+    validate_project_name(&req.name)?;
+    if state.get_project(&req.name)?.is_some() {
+        return Err(Error(format!("Project {} already exists", req.name), 409));
+    }
+
+    let hostnames = validate_domains(&state, &req.name, req.hostnames)?;
+    let project = Project {
+        name: req.name,
+        hostnames,
+        created_at: time::OffsetDateTime::now_utc(),
+    };
+
+    state.set_project(project.clone())?;
+    Ok(Json(project))
+}
+
+async fn update_project(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<UpdateProjectRequest>,
+) -> Result<Json<Project>, Error> {
+    let Some(mut project) = state.get_project(&name)? else {
+        return Err(Error(format!("Project {} not found", name), 404));
+    };
+
+    if let Some(hostnames) = req.hostnames {
+        project.hostnames = validate_domains(&state, &name, hostnames)?;
+
+        // apps can't be left with hostnames outside of their project
+        for app in state.get_project_apps(&name)?.values() {
+            validate_hostnames(&state, &project, &app.hostnames)
+                .map_err(|Error(msg, _)| Error(format!("App {}: {}", app.name, msg), 409))?;
+        }
+    }
+
+    state.set_project(project.clone())?;
+    Ok(Json(project))
+}
+
+async fn delete_project(State(state): State<AppState>, Path(name): Path<String>) -> Result<StatusCode, Error> {
+    if name == DEFAULT_PROJECT {
+        return Err(Error("The default project can't be deleted".to_string(), 400));
+    }
+
+    if state.get_project(&name)?.is_none() {
+        return Err(Error(format!("Project {} not found", name), 404));
+    }
+
+    if !state.get_project_apps(&name)?.is_empty() {
+        return Err(Error(format!("Project {} still has apps", name), 409));
+    }
+
+    for key in state.get_deploy_keys(&name)? {
+        state.delete_deploy_key(&key.id)?;
+    }
+
+    state.delete_project(&name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_project(State(state): State<AppState>, Path(name): Path<String>) -> Result<Json<Project>, Error> {
+    match state.get_project(&name)? {
+        Some(project) => Ok(Json(project)),
+        None => Err(Error(format!("Project {} not found", name), 404)),
+    }
+}
+
+async fn get_projects(
+    State(state): State<AppState>,
+    Extension(token): Extension<TokenInfo>,
+) -> Result<Json<Vec<Project>>, Error> {
+    let mut projects = state.get_projects()?;
+    projects.retain(|project| can_access(&token, &project.name));
+    Ok(Json(projects))
+}
+
+async fn create_deploy_key(
+    State(state): State<AppState>,
+    Path(project): Path<String>,
+    Json(req): Json<CreateDeployKeyRequest>,
+) -> Result<Json<DeployKeyInfo>, Error> {
+    if req.name.is_empty() || req.name.len() > 64 {
+        return Err(Error("Deploy key names must be 1-64 characters".to_string(), 400));
+    }
+
+    if state.get_project(&project)?.is_none() {
+        return Err(Error(format!("Project {} not found", project), 404));
+    }
+
+    Ok(Json(state.create_deploy_key(&project, &req.name)?))
+}
+
+async fn get_deploy_keys(
+    State(state): State<AppState>,
+    Path(project): Path<String>,
+) -> Result<Json<Vec<DeployKeyInfo>>, Error> {
+    if state.get_project(&project)?.is_none() {
+        return Err(Error(format!("Project {} not found", project), 404));
+    }
+
+    Ok(Json(state.get_deploy_keys(&project)?))
+}
+
+async fn delete_deploy_key(
+    State(state): State<AppState>,
+    Path((project, key)): Path<(String, String)>,
+) -> Result<StatusCode, Error> {
+    if state.get_deploy_key(&key)?.is_none_or(|k| k.project != project) {
+        return Err(Error(
+            format!("Deploy key {} not found in project {}", key, project),
+            404,
+        ));
+    }
+
+    let used_by: Vec<String> = state
+        .get_project_apps(&project)?
+        .into_values()
+        .filter(|app| matches!(&app.git, Some(GitSource { repo: Repo::DeployKey { id, .. }, .. }) if *id == key))
+        .map(|app| app.name)
+        .collect();
+
+    if !used_by.is_empty() {
+        return Err(Error(
+            format!("Deploy key {} is still used by {}", key, used_by.join(", ")),
+            409,
+        ));
+    }
+
+    state.delete_deploy_key(&key)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_secrets(State(state): State<AppState>, Path(id): Path<String>) -> Result<Json<Vec<SecretInfo>>, Error> {
+    if state.get_app(&id)?.is_none() {
+        return Err(Error(format!("App {} not found", id), 404));
+    }
+
+    Ok(Json(state.get_secrets(&id)?))
+}
+
+async fn get_secret(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+) -> Result<Json<SecretValue>, Error> {
+    let Some(mut value) = state.get_secret(&id, &name)? else {
+        return Err(Error(format!("Secret {} not found", name), 404));
+    };
+
+    // validate first, `value` is zeroized when dropped on error
+    if std::str::from_utf8(&value).is_err() {
+        return Err(Error("Secret is not valid UTF-8".to_string(), 500));
+    }
+
+    let value = String::from_utf8(std::mem::take(&mut *value)).expect("validated above");
+    Ok(Json(SecretValue { name, value }))
+}
+
+async fn set_secret(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+    Json(req): Json<SetSecretRequest>,
+) -> Result<Json<SecretInfo>, Error> {
+    validate_secret_name(&name)?;
+    if state.get_app(&id)?.is_none() {
+        return Err(Error(format!("App {} not found", id), 404));
+    }
+
+    let value = Zeroizing::new(req.value.into_bytes());
+    Ok(Json(state.set_secret(&id, &name, value)?))
+}
+
+async fn delete_secret(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(String, String)>,
+) -> Result<StatusCode, Error> {
+    match state.delete_secret(&id, &name)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(Error(format!("Secret {} not found", name), 404)),
+    }
+}
+
+async fn create_token(
+    State(state): State<AppState>,
+    Json(req): Json<CreateTokenRequest>,
+) -> Result<Json<CreateTokenResponse>, Error> {
+    if req.name.is_empty() || req.name.len() > 64 {
+        return Err(Error("Token names must be 1-64 characters".to_string(), 400));
+    }
+
+    if let Some(project) = &req.project {
+        if state.get_project(project)?.is_none() {
+            return Err(Error(format!("Project {} not found", project), 404));
+        }
+    }
+
+    let (token, info) = state.create_token(&req.name, req.scope, req.project)?;
+    Ok(Json(CreateTokenResponse {
+        token: token.to_string(),
+        info,
+    }))
+}
+
+async fn get_tokens(State(state): State<AppState>) -> Result<Json<Vec<TokenInfo>>, Error> {
+    Ok(Json(state.get_tokens()?))
+}
+
+async fn revoke_token(State(state): State<AppState>, Path(id): Path<String>) -> Result<StatusCode, Error> {
+    match state.revoke_token(&id)? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(Error(format!("Token {} not found", id), 404)),
+    }
+}
+
+fn validate_secret_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name.len() <= 128
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if !valid {
+        return Err(Error(
+            "Secret names must be 1-128 characters of a-z, A-Z, 0-9, -, _ or .".to_string(),
+            400,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Hostnames owned by another project can't be used, and apps of projects with domains have to stay within them
+fn validate_hostnames(state: &AppState, project: &Project, hostnames: &[Match]) -> Result<(), Error> {
+    for hostname in hostnames {
+        if let Some(owner) = state.hostname_owner(hostname)? {
+            if owner.name != project.name {
+                return Err(Error(
+                    format!("Hostname {} belongs to project {}", hostname, owner.name),
+                    409,
+                ));
+            }
+        }
+
+        if project.hostnames.is_empty() {
+            continue;
+        }
+
+        let within = match hostname {
+            Match::Glob(glob) => project
+                .hostnames
+                .iter()
+                .any(|domain| is_within(&glob.to_ascii_lowercase(), domain)),
+            Match::Regex(_) => false,
+        };
+
+        if !within {
+            return Err(Error(
+                format!(
+                    "Hostname {} is not within the domains of project {}: {}",
+                    hostname,
+                    project.name,
+                    project.hostnames.join(", ")
+                ),
+                400,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Domains of a project have to be valid and can't overlap with the ones of other projects
+fn validate_domains(state: &AppState, project: &str, domains: Vec<String>) -> Result<Vec<String>, Error> {
+    let domains: Vec<String> = domains.into_iter().map(|d| d.trim().to_ascii_lowercase()).collect();
+    let others = state.get_projects()?.into_iter().filter(|p| p.name != project);
+
+    for domain in &domains {
+        let valid = !domain.is_empty()
+            && domain.len() <= 253
+            && !domain.starts_with(['.', '-'])
+            && !domain.ends_with('.')
+            && domain
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.');
+
+        if !valid {
+            return Err(Error(format!("Invalid domain: {}", domain), 400));
+        }
+    }
+
+    for other in others {
+        for domain in &domains {
+            if let Some(taken) = other
+                .hostnames
+                .iter()
+                .find(|d| is_within(domain, d) || is_within(d, domain))
+            {
+                return Err(Error(
+                    format!("Domain {} overlaps with {} of project {}", domain, taken, other.name),
+                    409,
+                ));
+            }
+        }
+    }
+
+    // apps of other projects can't keep hostnames that would now belong to this project
+    for app in state.get_apps()?.into_values().filter(|app| app.project != project) {
+        for hostname in &app.hostnames {
+            let Match::Glob(hostname) = hostname else { continue };
+            let hostname = hostname.to_ascii_lowercase();
+            if let Some(domain) = domains.iter().find(|d| is_within(&hostname, d)) {
+                return Err(Error(
+                    format!(
+                        "Domain {} is used by app {} of project {}",
+                        domain, app.name, app.project
+                    ),
+                    409,
+                ));
+            }
+        }
+    }
+
+    Ok(domains)
+}
+
+fn validate_project_name(name: &str) -> Result<(), Error> {
+    validate_app_name(name).map_err(|_| {
+        Error(
+            "Project names must be 1-64 characters of a-z, 0-9 or - and not start with -".to_string(),
+            400,
+        )
+    })
+}
+
+fn validate_app_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-');
+
+    if !valid {
+        return Err(Error(
+            "App names must be 1-64 characters of a-z, 0-9 or - and not start with -".to_string(),
+            400,
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_git(state: &AppState, project: &str, git: &GitSource) -> Result<(), Error> {
+    if cfg!(not(feature = "git")) {
+        return Err(Error("notsd was built without git support".to_string(), 400));
+    }
+
+    validate_git_source(git)?;
+    if let Repo::DeployKey { id, .. } = &git.repo {
+        if state.get_deploy_key(id)?.is_none_or(|key| key.project != project) {
+            return Err(Error(
+                format!("Deploy key {} not found in project {}", id, project),
+                400,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the url, branch and path of a repository, they end up as arguments of git commands
+fn validate_git_source(git: &GitSource) -> Result<(), Error> {
+    let url = git.repo.url();
+    if url.starts_with('-') || url.chars().any(char::is_whitespace) {
+        return Err(Error(format!("Invalid repository url {}", url), 400));
+    }
+
+    match &git.repo {
+        Repo::PublicHttps { url } if !url.starts_with("https://") => {
+            return Err(Error(
+                format!("Invalid repository url {}, it has to use https://", url),
+                400,
+            ));
+        }
+        Repo::DeployKey { url, .. } => {
+            // either ssh://[user@]host/path or the scp-like [user@]host:path
+            let is_ssh = url.starts_with("ssh://")
+                || (!url.contains("://") && url.split_once(':').is_some_and(|(host, _)| !host.contains('/')));
+            if !is_ssh {
+                return Err(Error(
+                    format!("Invalid repository url {}, deploy keys require an ssh url", url),
+                    400,
+                ));
+            }
+        }
+        _ => {}
+    }
+
+    // see `git check-ref-format`, stricter to keep branches from being read as options or revisions
+    let branch = &git.branch;
+    let valid_branch = !branch.is_empty()
+        && !branch.starts_with(['-', '/', '.'])
+        && !branch.ends_with(['/', '.'])
+        && !branch.contains("..")
+        && !branch.contains("@{")
+        && !branch
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c));
+
+    if !valid_branch {
+        return Err(Error(format!("Invalid branch {}", branch), 400));
+    }
+
+    if let Some(path) = &git.path {
+        if path.split('/').any(|segment| segment == "..") || path.contains(':') {
+            return Err(Error(format!("Invalid path {}", path), 400));
+        }
+    }
+
+    Ok(())
 }
 
-async fn update_app(State(app): State<AppState>, body: String) -> impl IntoResponse {
-    unimplemented!()
+fn validate_pause_timeout(state: &AppState, pause_timeout: u64) -> Result<(), Error> {
+    if pause_timeout > 0 && !state.processes.can_pause() {
+        return Err(Error(
+            "Workers can't be paused, the backend doesn't support it (process workers need cgroup v2)".to_string(),
+            400,
+        ));
+    }
+    Ok(())
 }
 
-async fn get_app(State(app): State<AppState>, body: String) -> impl IntoResponse {
-    unimplemented!()
+fn validate_replicas(replicas: u32) -> Result<(), Error> {
+    if !(1..=MAX_REPLICAS).contains(&replicas) {
+        return Err(Error(format!("Apps need between 1 and {} replicas", MAX_REPLICAS), 400));
+    }
+    Ok(())
 }
 
-async fn get_apps(State(app): State<AppState>, body: String) -> impl IntoResponse {
-    unimplemented!()
+/// Process workers share the host's network, so replicas and rollouts need a different port for each worker
+fn validate_port(settings: &WorkerSettings, runtime: &WorkerRuntimeOptions) -> Result<(), Error> {
+    if settings.port.is_some() && matches!(runtime, WorkerRuntimeOptions::Process {}) {
+        return Err(Error(
+            "Process workers can't use a fixed port, they have to listen on the port in $PORT".to_string(),
+            400,
+        ));
+    }
+    Ok(())
+}
+
+fn validate_limits(state: &AppState, limits: &ResourceLimits) -> Result<(), Error> {
+    let invalid = |message: &str| Err(Error(message.to_string(), 400));
+
+    if limits.cpu_shares.is_some_and(|shares| !(2..=262_144).contains(&shares)) {
+        return invalid("CPU shares have to be between 2 and 262144");
+    }
+    if limits.cpus.is_some_and(|cpus| !cpus.is_finite() || cpus < 0.01) {
+        return invalid("Workers need at least 0.01 CPUs");
+    }
+    // docker doesn't accept less, and few runtimes start with less anyway
+    if limits.memory.is_some_and(|memory| memory < 6 * 1024 * 1024) {
+        return invalid("Workers need at least 6 MiB of memory");
+    }
+    if limits.swap.is_some() && limits.memory.is_none() {
+        return invalid("A swap limit requires a memory limit");
+    }
+    if limits.pids == Some(0) {
+        return invalid("Workers need at least one process");
+    }
+
+    state
+        .processes
+        .check_limits(limits)
+        .map_err(|e| Error(format!("Resource limits can't be enforced: {}", e), 400))
+}
+
+fn validate_health_check(check: &HealthCheck) -> Result<(), Error> {
+    if check.interval == 0 || check.timeout == 0 || check.timeout > check.interval {
+        return Err(Error(
+            "Health checks need an interval and a timeout of at least a second, and the timeout can't be longer than the interval".to_string(),
+            400,
+        ));
+    }
+
+    if check.healthy_threshold == 0 || check.unhealthy_threshold == 0 {
+        return Err(Error("Health check thresholds have to be at least 1".to_string(), 400));
+    }
+
+    if let HealthCheckKind::Http { path } = &check.kind {
+        if !path.starts_with('/') || path.len() > 1024 || path.parse::<hyper::http::uri::PathAndQuery>().is_err() {
+            return Err(Error(format!("Invalid health check path {}", path), 400));
+        }
+    }
+    Ok(())
+}
+
+fn validate_matches(matches: &[Match]) -> Result<(), Error> {
+    for m in matches {
+        if let Err(e) = m.clone().regex() {
+            return Err(Error(format!("Invalid pattern: {}", e), 400));
+        }
+    }
+    Ok(())
 }
 
 async fn hi() -> &'static str {
     "Hello, World!"
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(repo: Repo, branch: &str) -> GitSource {
+        GitSource {
+            repo,
+            branch: branch.to_string(),
+            path: None,
+        }
+    }
+
+    fn https(url: &str) -> Repo {
+        Repo::PublicHttps { url: url.to_string() }
+    }
+
+    fn ssh(url: &str) -> Repo {
+        Repo::DeployKey {
+            url: url.to_string(),
+            id: "key".to_string(),
+        }
+    }
+
+    #[test]
+    fn git_urls() {
+        let valid = |repo: Repo| validate_git_source(&git(repo, "main")).is_ok();
+
+        assert!(valid(https("https://github.com/explodingcamera/nots.git")));
+        assert!(!valid(https("http://github.com/explodingcamera/nots.git")));
+        assert!(!valid(https("file:///etc")));
+        assert!(!valid(https("ext::sh -c touch% /tmp/pwned")));
+        assert!(!valid(https("-uhttps://github.com")));
+        assert!(!valid(https("https://github.com/a b")));
+
+        assert!(valid(ssh("git@github.com:explodingcamera/nots.git")));
+        assert!(valid(ssh("ssh://git@github.com/explodingcamera/nots.git")));
+        assert!(!valid(ssh("https://github.com/explodingcamera/nots.git")));
+        assert!(!valid(ssh("./local/repo:path")));
+        assert!(!valid(ssh("-oProxyCommand=sh:repo")));
+    }
+
+    #[test]
+    fn git_branches_and_paths() {
+        let valid = |branch: &str| validate_git_source(&git(https("https://example.com/repo.git"), branch)).is_ok();
+
+        assert!(valid("main"));
+        assert!(valid("feature/login"));
+        for branch in ["", "-f", "--upload-pack=sh", "/main", "main/", "a..b", "main@{1}", "a b", "HEAD~1", "a:b"] {
+            assert!(!valid(branch), "{}", branch);
+        }
+
+        let with_path = |path: &str| GitSource {
+            path: Some(path.to_string()),
+            ..git(https("https://example.com/repo.git"), "main")
+        };
+        assert!(validate_git_source(&with_path("apps/web")).is_ok());
+        assert!(validate_git_source(&with_path("../outside")).is_err());
+        assert!(validate_git_source(&with_path("apps/../../outside")).is_err());
+    }
+}
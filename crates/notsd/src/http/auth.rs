@@ -0,0 +1,196 @@
+use axum::extract::{ConnectInfo, MatchedPath, RawPathParams, Request, State};
+use axum::http::{header, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use nots_client::models::{TokenInfo, TokenScope};
+
+use super::Error;
+use crate::state::AppState;
+use crate::utils::{SocketOwner, UdsConnectInfo};
+
+/// Authorizes API requests by their bearer token or socket peer and makes the `TokenInfo` available to handlers
+pub async fn require_token(
+    State(state): State<AppState>,
+    path: MatchedPath,
+    params: RawPathParams,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let peer = request.extensions().get::<ConnectInfo<UdsConnectInfo>>();
+    let owner = request.extensions().get::<SocketOwner>();
+    let token = match (peer, owner) {
+        (Some(ConnectInfo(peer)), Some(owner)) => peer_token(peer, owner),
+        _ => None,
+    };
+
+    let token = match token {
+        Some(token) => token,
+        None => bearer_token(&state, &request)?,
+    };
+
+    let scope = required_scope(request.method(), path.as_str());
+    if token.scope < scope {
+        return Err(Error(format!("This requires a token with the {} scope", scope), 403));
+    }
+
+    if let Some(project) = &token.project {
+        let param = params.iter().find(|(key, _)| matches!(*key, "id" | "project"));
+        if !allowed_in_project(&state, project, path.as_str(), param)? {
+            return Err(Error(format!("This token is restricted to project {}", project), 403));
+        }
+    }
+
+    request.extensions_mut().insert(token);
+    Ok(next.run(request).await)
+}
+
+/// Whether a token restricted to `project` may use a route, `param` is its `id` or `project` path parameter
+fn allowed_in_project(state: &AppState, project: &str, path: &str, param: Option<(&str, &str)>) -> Result<bool, Error> {
+    Ok(match param {
+        // restricted tokens can't manage tokens, not even for their own project
+        _ if path.starts_with("/token") => false,
+        // the project of unknown apps can't be checked, so they are rejected before reaching the handler
+        Some(("id", id)) => match state.get_app(id)? {
+            Some(app) => app.project == project,
+            None => return Err(Error(format!("App {} not found", id), 404)),
+        },
+        Some((_, name)) => name == project,
+        // other projects can't be created, the remaining routes are filtered by their handlers
+        None => path != "/project",
+    })
+}
+
+fn bearer_token(state: &AppState, request: &Request) -> Result<TokenInfo, Error> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or(Error("Missing API token".to_string(), 401))?;
+
+    state
+        .verify_token(token)?
+        .ok_or(Error("Invalid API token".to_string(), 401))
+}
+
+/// Local callers on the API socket don't need a token if they are root, the socket owner or in its group
+fn peer_token(peer: &UdsConnectInfo, owner: &SocketOwner) -> Option<TokenInfo> {
+    let uid = peer.peer_cred.uid();
+    let in_group = |gid: u32| {
+        peer.peer_cred.gid() == gid
+            || peer
+                .peer_cred
+                .pid()
+                .is_some_and(|pid| supplementary_groups(pid).contains(&gid))
+    };
+
+    let authorized =
+        uid == 0 || uid == unsafe { libc::geteuid() } || owner.uid == Some(uid) || owner.gid.is_some_and(in_group);
+
+    authorized.then(|| TokenInfo {
+        id: format!("uid:{}", uid),
+        name: format!("unix socket (uid {})", uid),
+        scope: TokenScope::Admin,
+        project: None,
+        created_at: time::OffsetDateTime::now_utc(),
+    })
+}
+
+/// Groups of a process besides its primary group, empty if they can't be read (e.g. from inside a container)
+fn supplementary_groups(pid: i32) -> Vec<u32> {
+    let Ok(status) = std::fs::read_to_string(format!("/proc/{}/status", pid)) else {
+        return Vec::new();
+    };
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| groups.split_whitespace().filter_map(|g| g.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Reading is allowed for all tokens, except for secret values
+fn required_scope(method: &Method, path: &str) -> TokenScope {
+    match (method, path) {
+        (_, "/tokens" | "/token" | "/token/:id") => TokenScope::Admin,
+        (&Method::GET, "/app/:id/secret/:name") => TokenScope::Admin,
+        (&Method::GET, _) => TokenScope::ReadOnly,
+        (&Method::DELETE, "/app/:id") => TokenScope::Admin,
+        (_, "/project" | "/project/:project") => TokenScope::Admin,
+        _ => TokenScope::Deploy,
+    }
+}
+
+/// Whether a token may access apps of the given project
+pub fn can_access(token: &TokenInfo, project: &str) -> bool {
+    token.project.as_deref().is_none_or(|p| p == project)
+}
+
+#[cfg(test)]
+mod tests {
+    use nots_client::models::{App, Project};
+
+    use super::*;
+    use crate::state::tests::{app, state, FakeBackend};
+
+    #[test]
+    fn required_scopes() {
+        let cases = [
+            (Method::GET, "/apps", TokenScope::ReadOnly),
+            (Method::GET, "/app/:id", TokenScope::ReadOnly),
+            (Method::GET, "/app/:id/secrets", TokenScope::ReadOnly),
+            (Method::GET, "/app/:id/secret/:name", TokenScope::Admin),
+            (Method::POST, "/app/:id/secret/:name", TokenScope::Deploy),
+            (Method::DELETE, "/app/:id/secret/:name", TokenScope::Deploy),
+            (Method::POST, "/app/:id", TokenScope::Deploy),
+            (Method::POST, "/app/:id/deploy", TokenScope::Deploy),
+            (Method::DELETE, "/app/:id", TokenScope::Admin),
+            (Method::GET, "/projects", TokenScope::ReadOnly),
+            (Method::POST, "/project", TokenScope::Admin),
+            (Method::POST, "/project/:project", TokenScope::Admin),
+            (Method::DELETE, "/project/:project", TokenScope::Admin),
+            (Method::POST, "/project/:project/app", TokenScope::Deploy),
+            (Method::GET, "/tokens", TokenScope::Admin),
+            (Method::POST, "/token", TokenScope::Admin),
+            (Method::DELETE, "/token/:id", TokenScope::Admin),
+        ];
+
+        for (method, path, scope) in cases {
+            assert!(required_scope(&method, path) == scope, "{} {}", method, path);
+        }
+    }
+
+    #[tokio::test]
+    async fn restricted_tokens() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        state
+            .set_project(Project {
+                name: "other".to_string(),
+                hostnames: Vec::new(),
+                created_at: time::OffsetDateTime::now_utc(),
+            })
+            .unwrap();
+        let own = state.create_app(app("web")).unwrap().unwrap();
+        let other = state
+            .create_app(App {
+                project: "other".to_string(),
+                ..app("web")
+            })
+            .unwrap()
+            .unwrap();
+
+        let allowed = |path: &str, param: Option<(&str, &str)>| allowed_in_project(&state, "default", path, param).ok();
+        assert_eq!(allowed("/app/:id", Some(("id", &own))), Some(true));
+        assert_eq!(allowed("/app/:id", Some(("id", &other))), Some(false));
+        assert_eq!(allowed("/app/:id/secret/:name", Some(("id", &other))), Some(false));
+        let unknown = allowed_in_project(&state, "default", "/app/:id", Some(("id", "unknown")));
+        assert!(matches!(unknown, Err(Error(_, 404))));
+
+        assert_eq!(allowed("/project/:project/apps", Some(("project", "default"))), Some(true));
+        assert_eq!(allowed("/project/:project/apps", Some(("project", "other"))), Some(false));
+        assert_eq!(allowed("/project", None), Some(false));
+        assert_eq!(allowed("/apps", None), Some(true));
+        assert_eq!(allowed("/tokens", None), Some(false));
+        assert_eq!(allowed("/token/:id", Some(("id", &own))), Some(false));
+    }
+}
@@ -1,17 +1,28 @@
 use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 
-use color_eyre::eyre::Result;
-use tokio::net::TcpListener;
+use color_eyre::eyre::{Context, Result};
+use hyper::body::Incoming;
+use hyper::Request;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server;
+use tokio::net::{TcpListener, UnixListener};
+use tower::Service;
+use tracing::debug;
 
 use crate::state;
+use crate::utils::{SocketOwner, UdsConnectInfo};
 
 pub(crate) mod api;
+pub(crate) mod auth;
 pub(crate) mod proxy;
 
 use axum::{
     body::Body,
     http::StatusCode,
     response::{IntoResponse, Response},
+    Extension,
 };
 use serde_json::json;
 
@@ -30,6 +41,12 @@ impl IntoResponse for Error {
     }
 }
 
+impl From<color_eyre::Report> for Error {
+    fn from(err: color_eyre::Report) -> Self {
+        Self(err.to_string(), 500)
+    }
+}
+
 pub async fn create_reverse_proxy(reverse_proxy_addr: &str, app_state: state::AppState) -> Result<()> {
     let listerner = TcpListener::bind(reverse_proxy_addr).await?;
 
@@ -42,7 +59,12 @@ pub async fn create_reverse_proxy(reverse_proxy_addr: &str, app_state: state::Ap
     Ok(())
 }
 
-pub async fn create_api(api_addr: &str, app_state: state::AppState) -> Result<()> {
+/// Serves the API on a Unix socket if `api_addr` is a path, or on a TCP address otherwise
+pub async fn create_api(api_addr: &str, socket_owner: SocketOwner, app_state: state::AppState) -> Result<()> {
+    if api_addr.starts_with('/') || api_addr.starts_with('.') {
+        return create_unix_api(Path::new(api_addr), socket_owner, app_state).await;
+    }
+
     let listener = TcpListener::bind(api_addr).await?;
 
     let api = axum::serve(
@@ -53,3 +75,41 @@ pub async fn create_api(api_addr: &str, app_state: state::AppState) -> Result<()
     api.await;
     Ok(())
 }
+
+async fn create_unix_api(path: &Path, socket_owner: SocketOwner, app_state: state::AppState) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    // a socket left behind by a previous run would make the bind fail
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let listener = UnixListener::bind(path).wrap_err_with(|| format!("Could not bind {}", path.display()))?;
+    std::os::unix::fs::chown(path, socket_owner.uid, socket_owner.gid)
+        .wrap_err_with(|| format!("Could not change the owner of {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+
+    let mut make_service = api::new(app_state)
+        .layer(Extension(socket_owner))
+        .into_make_service_with_connect_info::<UdsConnectInfo>();
+
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let Ok(tower_service) = make_service.call(&socket).await;
+
+        tokio::spawn(async move {
+            let hyper_service =
+                hyper::service::service_fn(move |request: Request<Incoming>| tower_service.clone().call(request));
+
+            if let Err(e) = server::conn::auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(socket), hyper_service)
+                .await
+            {
+                debug!("API connection failed: {}", e);
+            }
+        });
+    }
+}
@@ -1,12 +1,23 @@
 use super::Error;
 use crate::state::AppState;
+use axum::body::Body;
 use axum::extract::{ConnectInfo, Request, State};
 use axum::http::HeaderValue;
 use axum::response::{IntoResponse, Response};
 use axum::Router;
 use color_eyre::eyre::Result;
-use hyper::HeaderMap;
+use http_body_util::BodyExt;
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper::{header, HeaderMap, StatusCode};
+use hyper_util::rt::TokioIo;
 use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, warn};
+
+/// Upgraded connections without any traffic in either direction for this long are closed
+const UPGRADE_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
 
 pub fn new(app_state: AppState) -> Router {
     Router::new().fallback(handler).with_state(app_state)
@@ -17,15 +28,154 @@ pub async fn handler(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     mut req: Request,
 ) -> Result<Response, Error> {
+    let host = request_host(&req).unwrap_or_default();
+    let Some((app_id, target)) = state.get_proxy_target(&host, req.uri().path())? else {
+        return Err(Error(format!("No app found for {}{}", host, req.uri().path()), 404));
+    };
+
+    state.record_request(&app_id);
+    let target = match target {
+        Some(target) if !state.is_waking(&app_id) => Some(target),
+        // hold the request until a paused worker is resumed, or a scaled to zero app has a worker again
+        _ => state.wake(&app_id).await.unwrap_or_else(|e| {
+            warn!("Could not wake app {}: {:?}", app_id, e);
+            None
+        }),
+    };
+
+    let Some((connection, address)) = target else {
+        warn!("No running worker for app {}", app_id);
+        return Err(Error("App is not available".to_string(), 503));
+    };
+
     add_x_forwarded_for(req.headers_mut(), addr);
-    *req.uri_mut() = state.get_proxy_uri(req.uri().clone());
+    *req.uri_mut() = state.get_proxy_uri(&address, req.uri().clone())?;
+
+    // the client's side of the upgrade has to be taken before the request is forwarded
+    let client_upgrade = is_upgrade(req.headers()).then(|| hyper::upgrade::on(&mut req));
 
     let Ok(mut res) = state.client.request(req).await else {
+        state.eject(&connection);
         return Err(Error("Could not proxy request".to_string(), 500));
     };
 
-    remove_hop_by_hop_headers(res.headers_mut());
-    Ok(res.into_response())
+    let upgraded = res.status() == StatusCode::SWITCHING_PROTOCOLS;
+    remove_hop_by_hop_headers(res.headers_mut(), upgraded);
+
+    if let (true, Some(client_upgrade)) = (upgraded, client_upgrade) {
+        let worker_upgrade = hyper::upgrade::on(&mut res);
+        tokio::spawn(async move {
+            // the worker keeps counting the connection as an open request until it is closed
+            let _connection = connection;
+            if let Err(e) = splice(client_upgrade, worker_upgrade, &state, &app_id).await {
+                debug!("Closed upgraded connection to app {}: {}", app_id, e);
+            }
+        });
+        return Ok(res.into_response());
+    }
+
+    // the request is open until the whole body has been sent
+    Ok(res
+        .map(|body| {
+            Body::new(body.map_err(move |e| {
+                state.eject(&connection);
+                e
+            }))
+        })
+        .into_response())
+}
+
+/// Whether the client asks to switch protocols, e.g. to a WebSocket
+fn is_upgrade(headers: &HeaderMap<HeaderValue>) -> bool {
+    let connection = headers.get_all(header::CONNECTION).iter().any(|value| {
+        value
+            .to_str()
+            .is_ok_and(|v| v.split(',').any(|token| token.trim().eq_ignore_ascii_case("upgrade")))
+    });
+    connection && headers.contains_key(header::UPGRADE)
+}
+
+/// Copy data between both sides of an upgraded connection until both are closed or it is idle for too long
+async fn splice(client: OnUpgrade, worker: OnUpgrade, state: &AppState, app_id: &str) -> std::io::Result<()> {
+    let (client, worker) = tokio::try_join!(client, worker).map_err(std::io::Error::other)?;
+    let (mut client_read, mut client_write) = tokio::io::split(TokioIo::<Upgraded>::new(client));
+    let (mut worker_read, mut worker_write) = tokio::io::split(TokioIo::<Upgraded>::new(worker));
+
+    let activity = Activity {
+        last: Mutex::new(Instant::now()),
+        state,
+        app_id,
+    };
+
+    // if one direction fails the other one is dropped, which closes both connections
+    tokio::try_join!(
+        pipe(&mut client_read, &mut worker_write, &activity),
+        pipe(&mut worker_read, &mut client_write, &activity),
+    )?;
+    Ok(())
+}
+
+/// Last traffic on an upgraded connection, which also keeps the app from being scaled down
+struct Activity<'a> {
+    last: Mutex<Instant>,
+    state: &'a AppState,
+    app_id: &'a str,
+}
+
+impl Activity<'_> {
+    fn idle_for(&self) -> Duration {
+        self.last.lock().expect("activity lock poisoned").elapsed()
+    }
+
+    fn touch(&self) {
+        let mut last = self.last.lock().expect("activity lock poisoned");
+        if last.elapsed() > Duration::from_secs(1) {
+            self.state.record_request(self.app_id);
+        }
+        *last = Instant::now();
+    }
+}
+
+/// Copy one direction of an upgraded connection, the end of it is passed on as a half close
+async fn pipe(
+    from: &mut (impl AsyncRead + Unpin),
+    to: &mut (impl AsyncWrite + Unpin),
+    activity: &Activity<'_>,
+) -> std::io::Result<()> {
+    let idle = || std::io::Error::new(std::io::ErrorKind::TimedOut, "connection was idle for too long");
+
+    let mut buf = vec![0; 16 * 1024];
+    loop {
+        let remaining = UPGRADE_IDLE_TIMEOUT.checked_sub(activity.idle_for()).ok_or_else(idle)?;
+        let n = match tokio::time::timeout(remaining, from.read(&mut buf)).await {
+            Ok(n) => n?,
+            // the other direction might have been busy in the meantime
+            Err(_) => continue,
+        };
+
+        if n == 0 {
+            return to.shutdown().await;
+        }
+
+        let write = async {
+            to.write_all(&buf[..n]).await?;
+            to.flush().await
+        };
+        tokio::time::timeout(UPGRADE_IDLE_TIMEOUT, write)
+            .await
+            .map_err(|_| idle())??;
+        activity.touch();
+    }
+}
+
+/// Hostname of the request without the port, from the `Host` header or the URI for HTTP/2
+fn request_host(req: &Request) -> Option<String> {
+    let host = match req.headers().get(hyper::header::HOST) {
+        Some(host) => host.to_str().ok()?.parse::<hyper::http::uri::Authority>().ok()?,
+        None => req.uri().authority()?.clone(),
+    };
+
+    Some(host.host().to_ascii_lowercase())
 }
 
 fn add_x_forwarded_for(headers: &mut HeaderMap<HeaderValue>, addr: SocketAddr) {
@@ -40,13 +190,88 @@ fn add_x_forwarded_for(headers: &mut HeaderMap<HeaderValue>, addr: SocketAddr) {
     }
 }
 
-fn remove_hop_by_hop_headers(headers: &mut HeaderMap<HeaderValue>) {
-    headers.remove("connection");
+/// `upgraded` keeps the headers that complete a protocol switch
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap<HeaderValue>, upgraded: bool) {
+    if !upgraded {
+        headers.remove("connection");
+        headers.remove("upgrade");
+    }
     headers.remove("keep-alive");
     headers.remove("proxy-authenticate");
     headers.remove("proxy-authorization");
     headers.remove("te");
     headers.remove("trailers");
     headers.remove("transfer-encoding");
-    headers.remove("upgrade");
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::state::tests::{app, state, worker, FakeBackend};
+    use crate::state::Worker;
+
+    /// A worker that switches protocols on the first request and then echoes everything back
+    async fn echo_worker() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_head(&mut stream).await;
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: upgrade\r\nUpgrade: echo\r\n\r\n")
+                .await
+                .unwrap();
+
+            let (mut read, mut write) = stream.split();
+            tokio::io::copy(&mut read, &mut write).await.unwrap();
+            write.shutdown().await.unwrap();
+        });
+        address
+    }
+
+    async fn read_head(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    #[tokio::test]
+    async fn upgrades_are_spliced() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+        let worker = Worker {
+            address: Some(echo_worker().await),
+            ..worker(&app_id)
+        };
+        state.workers.set("w1", &worker).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap();
+        let service = new(state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+
+        let mut client = TcpStream::connect(proxy).await.unwrap();
+        client
+            .write_all(b"GET /ws HTTP/1.1\r\nHost: example.com\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\n")
+            .await
+            .unwrap();
+        let head = read_head(&mut client).await.to_ascii_lowercase();
+        assert!(head.starts_with("http/1.1 101"), "{}", head);
+        assert!(head.contains("upgrade: echo"), "{}", head);
+
+        client.write_all(b"hello").await.unwrap();
+        let mut echoed = [0; 5];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello");
+
+        // a half close is passed on, and the worker's close comes back
+        client.shutdown().await.unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
 }
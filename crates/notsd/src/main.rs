@@ -34,11 +34,18 @@ async fn main() -> Result<()> {
     .await?;
 
     let reverse_proxy = create_reverse_proxy("127.0.0.1:8080", app_state.clone());
-    let api = create_api(&env.nots_api_bind, app_state.clone());
+    let socket_owner = utils::SocketOwner {
+        uid: env.nots_socket_uid,
+        gid: env.nots_socket_gid,
+    };
+    let api = create_api(&env.nots_api_bind, socket_owner, app_state.clone());
 
     info!("Gateway listening on 127.0.0.1:8080");
     info!("API listening on {}", env.nots_api_bind);
-    let scheduler = app_state.run();
+    let scheduler = app_state.clone().run();
+
+    #[cfg(feature = "git")]
+    tokio::spawn(app_state.clone().watch_repos());
 
     tokio::select! {
         res = api => res?,
@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+
+use axum::body::Bytes;
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use futures::Stream;
+use nots_client::models::{App, Artifact, ArtifactKind, WorkerRuntimeOptions, WorkerSettings};
+use tracing::info;
+
+use super::AppStateInner;
+
+const MAX_ARTIFACT_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Limits for unpacked archives, compression makes the unpacked size almost unbounded otherwise
+const MAX_UNPACKED_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+const MAX_UNPACKED_ENTRIES: u64 = 500_000;
+
+/// Number of newest uploads and of last deployed versions whose artifacts are kept when pruning
+const KEPT_ARTIFACTS: usize = 10;
+
+impl AppStateInner {
+    /// Store an uploaded artifact, archives are unpacked into the app's code directory
+    pub(crate) async fn upload_artifact<S, E>(&self, app_id: &str, filename: &str, body: S) -> Result<Artifact>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let kind = match filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
+            true => ArtifactKind::Archive,
+            false => ArtifactKind::File {
+                name: filename.to_string(),
+            },
+        };
+
+        self.store_artifact(app_id, kind, body).await
+    }
+
+    /// Store a file or `.tar.gz` archive (for `Archive` and `Git` artifacts) as a new artifact
+    pub(crate) async fn store_artifact<S, E>(&self, app_id: &str, kind: ArtifactKind, body: S) -> Result<Artifact>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let id = cuid2::cuid();
+        let upload = format!("uploads/{}", id);
+        let code_dir = code_dir(app_id, &id);
+
+        let size = self.file.write_stream(&upload, body, MAX_ARTIFACT_SIZE).await?;
+
+        let res = match &kind {
+            ArtifactKind::Archive | ArtifactKind::Git { .. } => {
+                let (file, upload, code_dir) = (self.file.clone(), upload.clone(), code_dir.clone());
+                tokio::task::spawn_blocking(move || {
+                    file.unpack_archive(&upload, &code_dir, MAX_UNPACKED_SIZE, MAX_UNPACKED_ENTRIES)
+                })
+                .await?
+            }
+            ArtifactKind::File { name } => {
+                async {
+                    self.file.0.create_dir(&code_dir).await?;
+                    self.file.0.rename(&upload, &format!("{}{}", code_dir, name)).await?;
+                    Result::<()>::Ok(())
+                }
+                .await
+            }
+        };
+
+        self.file.0.delete(&upload).await?;
+        if let Err(e) = res {
+            self.file.0.remove_all(&code_dir).await?;
+            return Err(e);
+        }
+
+        let artifact = Artifact {
+            id: id.clone(),
+            app_id: app_id.to_string(),
+            kind,
+            size,
+            created_at: time::OffsetDateTime::now_utc(),
+        };
+
+        self.artifacts.set(&artifact_key(app_id, &id), &artifact)?;
+        info!("Stored artifact {} for app {} ({} bytes)", id, app_id, size);
+        Ok(artifact)
+    }
+
+    pub(crate) fn get_artifact(&self, app_id: &str, artifact_id: &str) -> Result<Option<Artifact>> {
+        Ok(self.artifacts.get(&artifact_key(app_id, artifact_id))?)
+    }
+
+    pub(crate) fn get_artifacts(&self, app_id: &str) -> Result<Vec<Artifact>> {
+        let mut artifacts: Vec<Artifact> = self
+            .artifacts
+            .iter_prefix::<String>(&format!("{}/", app_id))?
+            .filter_map(|res| res.ok())
+            .map(|(_, artifact)| artifact)
+            .collect();
+
+        artifacts.sort_by_key(|a| a.created_at);
+        Ok(artifacts)
+    }
+
+    /// Remove artifacts that are neither among the newest uploads nor one of the last deployed versions
+    ///
+    /// Releases of removed artifacts can no longer be rolled back to.
+    pub(crate) async fn prune_artifacts(&self, app_id: &str) -> Result<()> {
+        let mut keep = HashSet::new();
+        for release in self.get_releases(app_id)?.into_iter().rev() {
+            if keep.len() == KEPT_ARTIFACTS {
+                break;
+            }
+            keep.insert(release.artifact_id);
+        }
+
+        let artifacts = self.get_artifacts(app_id)?;
+        let newest = artifacts.len().saturating_sub(KEPT_ARTIFACTS);
+        keep.extend(artifacts[newest..].iter().map(|a| a.id.clone()));
+
+        for artifact in artifacts.into_iter().filter(|a| !keep.contains(&a.id)) {
+            self.artifacts.delete(&artifact_key(app_id, &artifact.id))?;
+            self.file.0.remove_all(&code_dir(app_id, &artifact.id)).await?;
+            info!("Pruned artifact {} of app {}", artifact.id, app_id);
+        }
+        Ok(())
+    }
+
+    /// Remove all artifacts of an app and the code they were unpacked to
+    pub(super) async fn delete_artifacts(&self, app_id: &str) -> Result<()> {
+        for artifact in self.get_artifacts(app_id)? {
+            self.artifacts.delete(&artifact_key(app_id, &artifact.id))?;
+        }
+        self.file.0.remove_all(&format!("code/{}/", app_id)).await?;
+        Ok(())
+    }
+
+    /// Make `artifact_id` the current version of the app and record it as a release, workers are rolled by the scheduler
+    pub(crate) fn deploy_artifact(&self, app_id: &str, artifact_id: &str, deployed_by: &str) -> Result<App> {
+        self.deploy_artifact_with_settings(app_id, artifact_id, None, None, deployed_by)
+    }
+
+    /// Deploy an artifact and replace the settings workers are started with in the same release
+    pub(crate) fn deploy_artifact_with_settings(
+        &self,
+        app_id: &str,
+        artifact_id: &str,
+        worker_settings: Option<WorkerSettings>,
+        worker_runtime: Option<WorkerRuntimeOptions>,
+        deployed_by: &str,
+    ) -> Result<App> {
+        let mut app = self.get_app(app_id)?.context("App not found")?;
+
+        let Some(artifact) = self.get_artifact(app_id, artifact_id)? else {
+            bail!("Artifact {} not found", artifact_id);
+        };
+
+        let now = time::OffsetDateTime::now_utc();
+        app.version = Some(artifact_id.to_string());
+        app.commit = match artifact.kind {
+            ArtifactKind::Git { commit } => Some(commit),
+            _ => None,
+        };
+        if let Some(worker_settings) = worker_settings {
+            app.worker_settings = worker_settings;
+        }
+        if let Some(worker_runtime) = worker_runtime {
+            app.worker_runtime = worker_runtime;
+        }
+        app.updated_at = Some(now);
+        app.needs_restart_since = Some(now);
+        self.update_app(app_id, app.clone())?;
+
+        let release = self.create_release(app_id, &app, deployed_by, None)?;
+        info!(
+            "Deployed artifact {} to app {} as release {}",
+            artifact_id, app_id, release.number
+        );
+        Ok(app)
+    }
+}
+
+/// Directory the artifact is unpacked to, relative to the file store
+pub(crate) fn code_dir(app_id: &str, artifact_id: &str) -> String {
+    format!("code/{}/{}/", app_id, artifact_id)
+}
+
+fn artifact_key(app_id: &str, artifact_id: &str) -> String {
+    format!("{}/{}", app_id, artifact_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::{app, artifact, state, FakeBackend};
+
+    #[tokio::test]
+    async fn prune_keeps_newest_and_deployed_artifacts() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+
+        let deployed = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &deployed.id, "test").unwrap();
+        let unused = artifact(&state, &app_id).await;
+        for _ in 0..KEPT_ARTIFACTS {
+            artifact(&state, &app_id).await;
+        }
+
+        state.prune_artifacts(&app_id).await.unwrap();
+        let ids: Vec<String> = state
+            .get_artifacts(&app_id)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.id)
+            .collect();
+        assert_eq!(ids.len(), KEPT_ARTIFACTS + 1);
+        assert!(ids.contains(&deployed.id));
+        assert!(!ids.contains(&unused.id));
+        assert!(!state.file.0.is_exist(&code_dir(&app_id, &unused.id)).await.unwrap());
+        assert!(state.file.0.is_exist(&code_dir(&app_id, &deployed.id)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn deleted_apps_have_no_artifacts() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+        let v1 = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &v1.id, "test").unwrap();
+
+        state.delete_app(&app_id).await.unwrap();
+        assert!(state.get_artifacts(&app_id).unwrap().is_empty());
+        assert!(!state.file.0.is_exist(&format!("code/{}/", app_id)).await.unwrap());
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::Result;
+use nots_client::models::WorkerStatus;
+use tracing::warn;
+
+use super::AppStateInner;
+
+/// Time a worker that failed a request gets no new ones, unless the app has no other worker
+const EJECT_DURATION: Duration = Duration::from_secs(10);
+
+/// The open request to the worker a request is sent to, and the worker's address
+pub(crate) type Target = (Connection, String);
+
+/// Spreads requests across the workers of an app, preferring the ones with the fewest open requests
+#[derive(Default)]
+pub(super) struct Balancer {
+    inner: Mutex<BalancerState>,
+}
+
+#[derive(Default)]
+struct BalancerState {
+    in_flight: HashMap<String, usize>, // open requests per worker id
+    ejected: HashMap<String, Instant>, // workers that failed a request, until the given time
+    next: HashMap<String, usize>,      // round robin position per app id, breaks ties
+    hidden: HashSet<String>,           // workers of a rollout that don't get requests (yet or anymore)
+}
+
+/// An open request to a worker, counted until it is dropped
+pub(crate) struct Connection {
+    balancer: Arc<Balancer>,
+    worker_id: String,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut inner = self.balancer.inner.lock().expect("balancer lock poisoned");
+        if let Some(count) = inner.in_flight.get_mut(&self.worker_id) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+impl Balancer {
+    pub(super) fn forget(&self, worker_id: &str) {
+        let mut inner = self.inner.lock().expect("balancer lock poisoned");
+        inner.in_flight.remove(worker_id);
+        inner.ejected.remove(worker_id);
+        inner.hidden.remove(worker_id);
+    }
+
+    /// Keep a worker from getting requests until it is switched to
+    pub(super) fn hide(&self, worker_id: &str) {
+        let mut inner = self.inner.lock().expect("balancer lock poisoned");
+        inner.hidden.insert(worker_id.to_string());
+    }
+
+    /// Send requests to `show` instead of `hide`, a request either goes to the old or the new workers
+    pub(super) fn switch(&self, show: &[String], hide: &[String]) {
+        let mut inner = self.inner.lock().expect("balancer lock poisoned");
+        for id in show {
+            inner.hidden.remove(id);
+        }
+        inner.hidden.extend(hide.iter().cloned());
+    }
+
+    /// Number of open requests to the given workers
+    pub(super) fn in_flight(&self, worker_ids: &[String]) -> usize {
+        let inner = self.inner.lock().expect("balancer lock poisoned");
+        worker_ids.iter().filter_map(|id| inner.in_flight.get(id)).sum()
+    }
+
+    pub(super) fn forget_app(&self, app_id: &str) {
+        self.inner.lock().expect("balancer lock poisoned").next.remove(app_id);
+    }
+}
+
+impl AppStateInner {
+    /// The worker the next request of an app should go to, the request is counted until the connection is dropped
+    ///
+    /// Only running workers that passed their health checks and aren't hidden by a rollout are considered.
+    /// Workers that failed a request recently are skipped as long as there are others.
+    pub(super) fn pick_worker(&self, app_id: &str) -> Result<Option<Target>> {
+        let Some(app) = self.get_app(app_id)? else {
+            return Ok(None);
+        };
+
+        let mut candidates: Vec<(String, String)> = self
+            .get_app_workers(app_id)?
+            .into_iter()
+            .filter(|(id, w)| matches!(w.state.status, WorkerStatus::Running) && self.is_healthy(id, &app))
+            .filter_map(|(id, w)| Some((id, w.address?)))
+            .collect();
+        candidates.sort();
+
+        // picking and counting happen under one lock, so a rollout never drains a worker that was just picked
+        let mut inner = self.balancer.inner.lock().expect("balancer lock poisoned");
+        candidates.retain(|(id, _)| !inner.hidden.contains(id));
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        let now = Instant::now();
+        inner.ejected.retain(|_, until| *until > now);
+        if candidates.iter().any(|(id, _)| !inner.ejected.contains_key(id)) {
+            candidates.retain(|(id, _)| !inner.ejected.contains_key(id));
+        }
+
+        let start = inner.next.get(app_id).copied().unwrap_or_default();
+        let picked = (0..candidates.len())
+            .map(|i| (start + i) % candidates.len())
+            .min_by_key(|i| inner.in_flight.get(&candidates[*i].0).copied().unwrap_or_default())
+            .unwrap_or_default();
+        inner.next.insert(app_id.to_string(), picked + 1);
+
+        let (worker_id, address) = candidates.swap_remove(picked);
+        Ok(Some((self.connect(&mut inner, worker_id), address)))
+    }
+
+    /// Count a request to a worker that was started for it
+    pub(super) fn connect_to(&self, worker_id: &str) -> Connection {
+        let mut inner = self.balancer.inner.lock().expect("balancer lock poisoned");
+        self.connect(&mut inner, worker_id.to_string())
+    }
+
+    fn connect(&self, inner: &mut BalancerState, worker_id: String) -> Connection {
+        *inner.in_flight.entry(worker_id.clone()).or_default() += 1;
+        Connection {
+            balancer: self.balancer.clone(),
+            worker_id,
+        }
+    }
+
+    /// Take a worker out of rotation after it failed a request, the scheduler checks on it right away
+    pub(crate) fn eject(&self, connection: &Connection) {
+        warn!(
+            "Worker {} failed a request, taking it out of rotation",
+            connection.worker_id
+        );
+        let mut inner = self.balancer.inner.lock().expect("balancer lock poisoned");
+        let until = Instant::now() + EJECT_DURATION;
+        inner.ejected.insert(connection.worker_id.clone(), until);
+        self.reconcile_now.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::{app, state, worker, FakeBackend};
+    use crate::state::{AppState, Worker};
+
+    async fn app_with_workers(ids: &[&str]) -> (AppState, tempfile::TempDir, String) {
+        let (state, dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+        for id in ids {
+            let address = Some(format!("{}:3000", id));
+            state.set_worker(id, Worker { address, ..worker(&app_id) }).unwrap();
+        }
+        (state, dir, app_id)
+    }
+
+    fn pick(state: &AppStateInner, app_id: &str) -> Option<Target> {
+        state.pick_worker(app_id).unwrap()
+    }
+
+    #[tokio::test]
+    async fn least_connections() {
+        let (state, _dir, app_id) = app_with_workers(&["w1", "w2", "w3"]).await;
+
+        // all idle, so requests go round robin
+        let picked: Vec<String> = (0..3).map(|_| pick(&state, &app_id).unwrap().1).collect();
+        assert_eq!(picked, ["w1:3000", "w2:3000", "w3:3000"]);
+
+        let (slow, address) = pick(&state, &app_id).unwrap();
+        assert_eq!(address, "w1:3000");
+        let (_other, address) = pick(&state, &app_id).unwrap();
+        assert_eq!(address, "w2:3000");
+
+        // w1 and w2 are busy
+        for _ in 0..3 {
+            assert_eq!(pick(&state, &app_id).unwrap().1, "w3:3000");
+        }
+        assert_eq!(state.balancer.in_flight(&["w1".to_string(), "w2".to_string()]), 2);
+
+        drop(slow);
+        assert_eq!(state.balancer.in_flight(&["w1".to_string()]), 0);
+        assert_eq!(pick(&state, &app_id).unwrap().1, "w1:3000");
+    }
+
+    #[tokio::test]
+    async fn only_running_workers() {
+        let (state, _dir, app_id) = app_with_workers(&["w1"]).await;
+        let mut stopped = worker(&app_id);
+        stopped.state.status = WorkerStatus::Exited;
+        stopped.address = Some("w2:3000".to_string());
+        state.set_worker("w2", stopped).unwrap();
+        state.set_worker("w3", worker(&app_id)).unwrap(); // no address yet
+
+        for _ in 0..3 {
+            assert_eq!(pick(&state, &app_id).unwrap().1, "w1:3000");
+        }
+    }
+
+    #[tokio::test]
+    async fn ejected_workers_are_skipped() {
+        let (state, _dir, app_id) = app_with_workers(&["w1", "w2"]).await;
+
+        let (failed, address) = pick(&state, &app_id).unwrap();
+        assert_eq!(address, "w1:3000");
+        state.eject(&failed);
+        drop(failed);
+        for _ in 0..3 {
+            assert_eq!(pick(&state, &app_id).unwrap().1, "w2:3000");
+        }
+
+        // unless there is no other worker
+        let (failed, _) = pick(&state, &app_id).unwrap();
+        state.eject(&failed);
+        drop(failed);
+        assert!(pick(&state, &app_id).is_some());
+    }
+
+    #[tokio::test]
+    async fn hidden_workers_get_no_requests() {
+        let (state, _dir, app_id) = app_with_workers(&["old", "new"]).await;
+        state.balancer.hide("new");
+        for _ in 0..3 {
+            assert_eq!(pick(&state, &app_id).unwrap().1, "old:3000");
+        }
+
+        state.balancer.switch(&["new".to_string()], &["old".to_string()]);
+        for _ in 0..3 {
+            assert_eq!(pick(&state, &app_id).unwrap().1, "new:3000");
+        }
+
+        state.balancer.hide("new");
+        assert!(pick(&state, &app_id).is_none());
+    }
+}
@@ -1,4 +1,260 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use axum::body::Bytes;
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use futures::{Stream, StreamExt};
 use opendal::Operator as Op;
 
 #[derive(Clone)]
 pub struct Fs(pub Op);
+
+impl Fs {
+    /// Path on the local filesystem, used when handing files to workers
+    pub fn local_path(&self, path: &str) -> PathBuf {
+        Path::new(self.0.info().root()).join(path)
+    }
+
+    /// Write a stream to `path`, failing if it is larger than `limit` bytes
+    pub async fn write_stream<S, E>(&self, path: &str, mut stream: S, limit: u64) -> Result<u64>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut writer = self.0.writer(path).await?;
+        let mut size = 0;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            if size > limit {
+                writer.abort().await?;
+                bail!("Upload exceeds the limit of {} bytes", limit);
+            }
+            writer.write(chunk).await?;
+        }
+
+        writer.close().await?;
+        Ok(size)
+    }
+
+    /// Unpack a `.tar.gz` archive into the `target` directory
+    ///
+    /// Links are only created once everything else is unpacked and must point inside of `target`.
+    /// Fails once the files add up to more than `max_size` bytes or there are more than `max_entries` entries.
+    /// This is blocking, so it should be run on a blocking thread.
+    pub fn unpack_archive(&self, archive: &str, target: &str, max_size: u64, max_entries: u64) -> Result<()> {
+        use tar::EntryType;
+
+        let op = self.0.blocking();
+        let size = op.stat(archive)?.content_length();
+        let reader = op.reader(archive)?.into_std_read(0..size);
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+
+        let mut symlinks = HashMap::new(); // link path -> link target, both relative to `target`
+        let mut hardlinks = Vec::new();
+
+        let (mut size, mut entries) = (0, 0);
+        op.create_dir(target)?;
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            entries += 1;
+            if entries > max_entries {
+                bail!("Archive has more than {} entries", max_entries);
+            }
+            let path = entry.path()?.into_owned();
+
+            // don't allow entries to escape the target directory
+            if !path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                bail!("Invalid path in archive: {}", path.display());
+            }
+
+            let relative: PathBuf = path.components().filter(|c| c != &Component::CurDir).collect();
+            if relative.ancestors().skip(1).any(|p| symlinks.contains_key(p)) {
+                bail!("Invalid path in archive: {} is inside of a symlink", path.display());
+            }
+
+            let path = format!("{}{}", target, relative.to_string_lossy());
+            match entry.header().entry_type() {
+                // metadata like the commit id `git archive` adds
+                EntryType::XGlobalHeader | EntryType::XHeader => continue,
+                EntryType::Directory => op.create_dir(&format!("{}/", path.trim_end_matches('/')))?,
+                EntryType::Regular => {
+                    // the size in the header is what is read from the archive
+                    size += entry.size();
+                    if size > max_size {
+                        bail!("Unpacked archive exceeds the limit of {} bytes", max_size);
+                    }
+
+                    let mut writer = op.writer(&path)?.into_std_write();
+                    std::io::copy(&mut entry, &mut writer)?;
+                    writer.close()?;
+
+                    // opendal doesn't know about permissions, but binaries need to stay executable
+                    #[cfg(unix)]
+                    if entry.header().mode()? & 0o111 != 0 {
+                        use std::os::unix::fs::PermissionsExt;
+                        std::fs::set_permissions(self.local_path(&path), std::fs::Permissions::from_mode(0o755))?;
+                    }
+                }
+                kind @ (EntryType::Symlink | EntryType::Link) => {
+                    let link = entry
+                        .link_name()?
+                        .with_context(|| format!("Link {} has no target", path))?;
+                    if link.has_root() {
+                        bail!("Invalid link in archive: {} points to {}", path, link.display());
+                    }
+
+                    match kind {
+                        EntryType::Symlink => symlinks.insert(relative, link.into_owned()),
+                        _ => {
+                            hardlinks.push((relative, link.into_owned()));
+                            None
+                        }
+                    };
+                }
+                kind => bail!("Unsupported entry {} in archive: {:?}", path, kind),
+            }
+        }
+
+        let local = |path: &Path| self.local_path(&format!("{}{}", target, path.to_string_lossy()));
+
+        // the targets of hard links are relative to the archive's root
+        for (path, link) in hardlinks {
+            let original = resolve_link(&symlinks, &link, 0)
+                .with_context(|| format!("Invalid link in archive: {} points outside of it", path.display()))?;
+            std::fs::hard_link(local(&original), local(&path))?;
+        }
+
+        for (path, link) in &symlinks {
+            let parent = path.parent().unwrap_or(Path::new(""));
+            if resolve_link(&symlinks, &parent.join(link), 0).is_none() {
+                bail!(
+                    "Invalid link in archive: {} points to {}, outside of it",
+                    path.display(),
+                    link.display()
+                );
+            }
+
+            #[cfg(unix)]
+            {
+                std::fs::create_dir_all(local(parent))?;
+                std::os::unix::fs::symlink(link, local(path))?;
+            }
+            #[cfg(not(unix))]
+            bail!("Symlinks in archives are only supported on unix");
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolve `path` relative to the root of an archive, following the archive's `symlinks`
+///
+/// Returns `None` if the path leaves the root at any point, or if there are too many levels of symlinks.
+fn resolve_link(symlinks: &HashMap<PathBuf, PathBuf>, path: &Path, depth: usize) -> Option<PathBuf> {
+    const MAX_DEPTH: usize = 40;
+
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                resolved.push(name);
+                if let Some(link) = symlinks.get(&resolved) {
+                    if depth >= MAX_DEPTH {
+                        return None;
+                    }
+                    let parent = resolved.parent().unwrap_or(Path::new(""));
+                    resolved = resolve_link(symlinks, &parent.join(link), depth + 1)?;
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir if resolved.pop() => {}
+            _ => return None,
+        }
+    }
+    Some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[(&str, usize)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast()));
+        for (path, size) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(*size as u64);
+            header.set_mode(0o644);
+            let data = std::io::Read::take(std::io::repeat(0), *size as u64);
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn unpack(archive: Vec<u8>, max_size: u64, max_entries: u64) -> (Result<()>, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let fs = Fs(crate::state::fs_operator(dir.path().to_str().unwrap()).unwrap());
+        fs.0.blocking().write("upload", archive).unwrap();
+        (fs.unpack_archive("upload", "code/", max_size, max_entries), dir)
+    }
+
+    #[test]
+    fn unpack_within_limits() {
+        let (res, dir) = unpack(archive(&[("a.js", 600), ("lib/b.js", 400)]), 1000, 2);
+        res.unwrap();
+        assert_eq!(std::fs::metadata(dir.path().join("code/lib/b.js")).unwrap().len(), 400);
+    }
+
+    #[test]
+    fn unpack_size_limit() {
+        // compresses to a few kilobytes
+        let (res, _dir) = unpack(archive(&[("a.js", 600), ("b.js", 10 * 1024 * 1024)]), 1024 * 1024, 10);
+        assert!(res.unwrap_err().to_string().contains("exceeds the limit"));
+    }
+
+    #[test]
+    fn unpack_entry_limit() {
+        let files: Vec<(String, usize)> = (0..11).map(|i| (format!("{}.js", i), 0)).collect();
+        let files: Vec<(&str, usize)> = files.iter().map(|(p, s)| (p.as_str(), *s)).collect();
+        let (res, _dir) = unpack(archive(&files), 1024, 10);
+        assert!(res.unwrap_err().to_string().contains("more than 10 entries"));
+    }
+
+    fn links(links: &[(&str, &str)]) -> HashMap<PathBuf, PathBuf> {
+        links.iter().map(|(path, link)| (path.into(), link.into())).collect()
+    }
+
+    #[test]
+    fn resolve_link_inside_root() {
+        let symlinks = links(&[("node_modules/foo", "../.pnpm/foo/node_modules/foo"), ("current", ".")]);
+        let resolve = |path: &str| resolve_link(&symlinks, Path::new(path), 0);
+
+        assert_eq!(resolve("bin/../app.js"), Some("app.js".into()));
+        assert_eq!(
+            resolve("node_modules/foo/index.js"),
+            Some(".pnpm/foo/node_modules/foo/index.js".into())
+        );
+        assert_eq!(resolve("current/current/app.js"), Some("app.js".into()));
+        assert_eq!(resolve(""), Some("".into()));
+    }
+
+    #[test]
+    fn resolve_link_outside_root() {
+        let symlinks = links(&[("current", "."), ("dir/up", ".."), ("loop", "loop")]);
+        let resolve = |path: &str| resolve_link(&symlinks, Path::new(path), 0);
+
+        assert_eq!(resolve(".."), None);
+        assert_eq!(resolve("a/../../b"), None);
+        assert_eq!(resolve("/etc/passwd"), None);
+        // `current` is the root itself, so its parent is outside
+        assert_eq!(resolve("current/.."), None);
+        assert_eq!(resolve("dir/up/.."), None);
+        assert_eq!(resolve("loop"), None);
+    }
+}
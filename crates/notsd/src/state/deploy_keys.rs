@@ -0,0 +1,90 @@
+use color_eyre::eyre::{eyre, Result};
+use nots_client::{
+    api::{DeployKey, SSHKeyType},
+    models::DeployKeyInfo,
+};
+use serde::{Deserialize, Serialize};
+use ssh_key::{rand_core::OsRng, Algorithm, LineEnding, PrivateKey};
+use tracing::info;
+use zeroize::Zeroizing;
+
+use super::AppStateInner;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredDeployKey {
+    pub name: String,
+    pub project: String,
+    pub public_key: String,
+    pub private_key: DeployKey, // encrypted with `kw_secret`, the key id is used as the salt
+    pub created_at: time::OffsetDateTime,
+}
+
+impl AppStateInner {
+    /// Generate a new Ed25519 key, only its public half ever leaves notsd
+    pub(crate) fn create_deploy_key(&self, project: &str, name: &str) -> Result<DeployKeyInfo> {
+        let id = cuid2::cuid();
+        let mut key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519)?;
+        key.set_comment(format!("nots deploy key {} ({})", name, project));
+
+        let private_key = key.to_openssh(LineEnding::LF)?;
+        let deploy_key = StoredDeployKey {
+            name: name.to_string(),
+            project: project.to_string(),
+            public_key: key.public_key().to_openssh()?,
+            private_key: DeployKey {
+                kind: SSHKeyType::Ed25519,
+                key: self
+                    .kw_secret
+                    .encrypt(Zeroizing::new(private_key.as_bytes().to_vec()), &id)?,
+            },
+            created_at: time::OffsetDateTime::now_utc(),
+        };
+
+        self.deploy_keys.set(&id, &deploy_key)?;
+        info!("Created deploy key {} ({}) for project {}", name, id, project);
+        Ok(deploy_key_info(&id, deploy_key))
+    }
+
+    pub(crate) fn get_deploy_key(&self, id: &str) -> Result<Option<DeployKeyInfo>> {
+        Ok(self.deploy_keys.get(id)?.map(|key| deploy_key_info(id, key)))
+    }
+
+    /// Decrypt the private half of a deploy key in the OpenSSH format
+    pub(crate) fn get_deploy_private_key(&self, id: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let key = self
+            .deploy_keys
+            .get(id)?
+            .ok_or_else(|| eyre!("Deploy key {} not found", id))?;
+        self.kw_secret.decrypt(&key.private_key.key, id)
+    }
+
+    /// All deploy keys of a project, sorted by creation time
+    pub(crate) fn get_deploy_keys(&self, project: &str) -> Result<Vec<DeployKeyInfo>> {
+        let mut keys: Vec<DeployKeyInfo> = self
+            .deploy_keys
+            .iter()?
+            .filter_map(|res| res.ok())
+            .filter(|(_, key)| key.project == project)
+            .map(|(id, key)| deploy_key_info(&id, key))
+            .collect();
+
+        keys.sort_by_key(|k| k.created_at);
+        Ok(keys)
+    }
+
+    pub(crate) fn delete_deploy_key(&self, id: &str) -> Result<()> {
+        self.deploy_keys.delete(id)?;
+        info!("Deleted deploy key {}", id);
+        Ok(())
+    }
+}
+
+fn deploy_key_info(id: &str, key: StoredDeployKey) -> DeployKeyInfo {
+    DeployKeyInfo {
+        id: id.to_string(),
+        name: key.name,
+        project: key.project,
+        public_key: key.public_key,
+        created_at: key.created_at,
+    }
+}
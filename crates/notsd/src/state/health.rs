@@ -0,0 +1,277 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, Result};
+use hyper::Request;
+use nots_client::models::{App, HealthCheck, HealthCheckKind, WorkerStatus};
+use tracing::{debug, error, info, warn};
+
+use super::{AppStateInner, Worker};
+
+/// How often the health checker looks for workers that are due for a check
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub(super) struct WorkerHealth {
+    healthy: bool,
+    failed: bool, // reached the unhealthy threshold, the scheduler restarts the worker
+    successes: u32,
+    failures: u32,
+    last_check: Option<Instant>,
+    checking: bool,
+}
+
+impl AppStateInner {
+    /// Probe the running workers of all apps with a health check whenever their interval is due
+    pub(super) async fn check_health(self: Arc<Self>) {
+        loop {
+            if let Err(e) = self.start_health_checks() {
+                error!("Health: {:?}", e);
+            }
+            tokio::time::sleep(TICK).await;
+        }
+    }
+
+    fn start_health_checks(self: &Arc<Self>) -> Result<()> {
+        let apps = self.get_apps()?;
+        for (id, worker) in self.get_workers()? {
+            let Some(check) = apps.get(&worker.app_id).and_then(|app| app.health_check.clone()) else {
+                continue;
+            };
+            if !matches!(worker.state.status, WorkerStatus::Running) {
+                continue;
+            }
+            let Some(address) = worker.address.clone() else {
+                continue;
+            };
+
+            {
+                let mut health = self.health.lock().expect("health lock poisoned");
+                let entry = health.entry(id.clone()).or_default();
+                let due = entry
+                    .last_check
+                    .is_none_or(|last| last.elapsed() >= Duration::from_secs(check.interval));
+                if entry.checking || !due {
+                    continue;
+                }
+                entry.checking = true;
+            }
+
+            let state = self.clone();
+            tokio::spawn(async move {
+                let res = state.probe(&address, &check).await;
+                state.record_health(&id, &worker, &check, res);
+            });
+        }
+        Ok(())
+    }
+
+    /// Run a single health check against a worker
+    async fn probe(&self, address: &str, check: &HealthCheck) -> Result<()> {
+        let timeout = Duration::from_secs(check.timeout);
+        match &check.kind {
+            HealthCheckKind::Tcp => {
+                match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(address)).await {
+                    Ok(Ok(_)) => Ok(()),
+                    Ok(Err(e)) => bail!("could not connect: {}", e),
+                    Err(_) => bail!("timed out after {}s", check.timeout),
+                }
+            }
+            HealthCheckKind::Http { path } => {
+                let req = Request::get(format!("http://{}{}", address, path))
+                    .header(hyper::header::USER_AGENT, "nots-health-check")
+                    .body(axum::body::Body::empty())?;
+
+                let res = match tokio::time::timeout(timeout, self.client.request(req)).await {
+                    Ok(res) => res?,
+                    Err(_) => bail!("timed out after {}s", check.timeout),
+                };
+
+                let status = res.status();
+                if !(status.is_success() || status.is_redirection()) {
+                    bail!("responded with {}", status);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn record_health(&self, id: &str, worker: &Worker, check: &HealthCheck, res: Result<()>) {
+        let mut health = self.health.lock().expect("health lock poisoned");
+        // the worker was removed while it was being checked
+        let Some(entry) = health.get_mut(id) else {
+            return;
+        };
+        entry.checking = false;
+        entry.last_check = Some(Instant::now());
+
+        match res {
+            Ok(()) => {
+                entry.failures = 0;
+                entry.successes = entry.successes.saturating_add(1);
+                if !entry.healthy && entry.successes >= check.healthy_threshold {
+                    entry.healthy = true;
+                    info!("Worker {} of app {} is healthy", id, worker.app_id);
+                }
+            }
+            Err(e) => {
+                entry.successes = 0;
+
+                // failures of a worker that is still booting don't count
+                let running_for = time::OffsetDateTime::now_utc() - worker.updated_at;
+                if !entry.healthy && running_for < time::Duration::seconds(check.start_period as i64) {
+                    return;
+                }
+
+                entry.failures = entry.failures.saturating_add(1);
+                warn!("Health check of worker {} of app {} failed: {}", id, worker.app_id, e);
+                if !entry.failed && entry.failures >= check.unhealthy_threshold {
+                    entry.healthy = false;
+                    entry.failed = true;
+                    self.reconcile_now.notify_one();
+                }
+            }
+        }
+    }
+
+    /// Whether the proxy may send requests to a worker, workers of apps without a health check always are
+    pub(super) fn is_healthy(&self, id: &str, app: &App) -> bool {
+        if app.health_check.is_none() {
+            return true;
+        }
+
+        let health = self.health.lock().expect("health lock poisoned");
+        health.get(id).is_some_and(|h| h.healthy)
+    }
+
+    /// Whether a worker reached its unhealthy threshold and has to be restarted
+    pub(super) fn health_failed(&self, id: &str) -> bool {
+        let health = self.health.lock().expect("health lock poisoned");
+        health.get(id).is_some_and(|h| h.failed)
+    }
+
+    /// Check a freshly started worker until it is healthy, so a cold start can route the request right away
+    pub(super) async fn wait_until_healthy(&self, id: &str, address: &str, check: &HealthCheck) {
+        while let Err(e) = self.probe(address, check).await {
+            debug!("Worker {} is not healthy yet: {}", id, e);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let mut health = self.health.lock().expect("health lock poisoned");
+        let entry = health.entry(id.to_string()).or_default();
+        entry.healthy = true;
+        entry.successes = check.healthy_threshold;
+        entry.last_check = Some(Instant::now());
+    }
+
+    pub(super) fn forget_health(&self, id: &str) {
+        self.health.lock().expect("health lock poisoned").remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::eyre;
+
+    use super::*;
+    use crate::state::tests::{app, state, worker, FakeBackend};
+
+    fn check(healthy_threshold: u32, unhealthy_threshold: u32) -> HealthCheck {
+        HealthCheck {
+            kind: HealthCheckKind::Tcp,
+            healthy_threshold,
+            unhealthy_threshold,
+            start_period: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Record the results of checks of a worker that is being checked
+    fn record(state: &AppStateInner, worker: &Worker, check: &HealthCheck, results: &[bool]) {
+        for ok in results {
+            state.health.lock().unwrap().entry("w1".to_string()).or_default().checking = true;
+            let res = if *ok { Ok(()) } else { Err(eyre!("failed")) };
+            state.record_health("w1", worker, check, res);
+        }
+    }
+
+    #[tokio::test]
+    async fn healthy_threshold() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let check = check(2, 3);
+        let app = App {
+            health_check: Some(check.clone()),
+            ..app("web")
+        };
+        let worker = worker("app");
+
+        // workers of apps with a health check need to pass it first
+        assert!(!state.is_healthy("w1", &app));
+        record(&state, &worker, &check, &[true, false, true]);
+        assert!(!state.is_healthy("w1", &app));
+        record(&state, &worker, &check, &[true]);
+        assert!(state.is_healthy("w1", &app));
+
+        assert!(state.is_healthy("w1", &App { health_check: None, ..app.clone() }));
+        assert!(state.is_healthy("w2", &App { health_check: None, ..app }));
+    }
+
+    #[tokio::test]
+    async fn unhealthy_threshold() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let check = check(1, 3);
+        let app = App {
+            health_check: Some(check.clone()),
+            ..app("web")
+        };
+        let worker = worker("app");
+
+        record(&state, &worker, &check, &[true, false, false, true, false, false]);
+        assert!(state.is_healthy("w1", &app));
+        assert!(!state.health_failed("w1"));
+
+        record(&state, &worker, &check, &[false]);
+        assert!(!state.is_healthy("w1", &app));
+        assert!(state.health_failed("w1"));
+
+        // removed workers aren't tracked again
+        state.forget_health("w1");
+        state.record_health("w1", &worker, &check, Ok(()));
+        assert!(!state.health_failed("w1"));
+        assert!(state.health.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn start_period() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let check = HealthCheck {
+            start_period: 60,
+            ..check(1, 1)
+        };
+
+        // failures while the worker boots don't count
+        record(&state, &worker("app"), &check, &[false, false, false]);
+        assert!(!state.health_failed("w1"));
+
+        let booted = Worker {
+            updated_at: time::OffsetDateTime::now_utc() - time::Duration::seconds(61),
+            ..worker("app")
+        };
+        record(&state, &booted, &check, &[false]);
+        assert!(state.health_failed("w1"));
+    }
+
+    #[tokio::test]
+    async fn tcp_probe() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let check = check(1, 1);
+
+        state.probe(&address, &check).await.unwrap();
+        drop(listener);
+        assert!(state.probe(&address, &check).await.is_err());
+    }
+}
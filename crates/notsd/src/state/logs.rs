@@ -0,0 +1,246 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use futures::{stream::BoxStream, StreamExt};
+use nots_client::models::LogEntry;
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::AbortHandle,
+};
+
+use super::AppStateInner;
+use crate::backend::LogSender;
+
+/// Lines kept per app, older ones are dropped
+const MAX_ENTRIES: usize = 10_000;
+
+/// Bytes of output kept per app, older lines are dropped
+const MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// Recent output of the workers of each app, kept in memory
+pub(super) struct LogStore {
+    apps: Mutex<HashMap<String, AppLogs>>,              // key: <app_id>
+    events: broadcast::Sender<Arc<(String, LogEntry)>>, // every new entry, for following logs
+}
+
+#[derive(Default)]
+struct AppLogs {
+    entries: VecDeque<LogEntry>,
+    bytes: usize,                 // size of the lines in `entries`
+    forwarders: Vec<AbortHandle>, // tasks moving entries from the senders into `entries`
+}
+
+impl Default for LogStore {
+    fn default() -> Self {
+        Self {
+            apps: Mutex::default(),
+            events: broadcast::channel(1024).0,
+        }
+    }
+}
+
+impl LogStore {
+    /// A channel the backend sends a worker's output to
+    pub(super) fn sender(self: &Arc<Self>, app_id: &str) -> LogSender {
+        let (tx, mut rx) = mpsc::channel(256);
+        let (store, id) = (self.clone(), app_id.to_string());
+
+        // registered before the forwarder can push, entries of unknown apps are dropped
+        let mut apps = self.apps.lock().expect("logs lock poisoned");
+        let forwarder = tokio::spawn(async move {
+            while let Some(entry) = rx.recv().await {
+                store.push(&id, entry);
+            }
+        });
+        let app = apps.entry(app_id.to_string()).or_default();
+        app.forwarders.retain(|f| !f.is_finished());
+        app.forwarders.push(forwarder.abort_handle());
+        tx
+    }
+
+    /// Entries of apps without a sender, e.g. removed ones, are dropped
+    fn push(&self, app_id: &str, entry: LogEntry) {
+        let mut apps = self.apps.lock().expect("logs lock poisoned");
+        let Some(app) = apps.get_mut(app_id) else {
+            return;
+        };
+
+        app.bytes += entry.line.len();
+        app.entries.push_back(entry.clone());
+        while app.entries.len() > MAX_ENTRIES || app.bytes > MAX_BYTES {
+            let Some(oldest) = app.entries.pop_front() else {
+                break;
+            };
+            app.bytes -= oldest.line.len();
+        }
+
+        // sent while holding the lock, so followers neither miss nor repeat entries
+        let _ = self.events.send(Arc::new((app_id.to_string(), entry)));
+    }
+
+    /// Drop the logs of an app and stop accepting new ones, the senders of its workers are closed
+    pub(super) fn remove(&self, app_id: &str) {
+        let app = self.apps.lock().expect("logs lock poisoned").remove(app_id);
+        for forwarder in app.into_iter().flat_map(|app| app.forwarders) {
+            forwarder.abort();
+        }
+    }
+
+    /// Entries of `app_id` that are newer than `since`, and new ones as they arrive if `follow` is set
+    fn stream(&self, app_id: &str, since: Option<time::OffsetDateTime>, follow: bool) -> BoxStream<'static, LogEntry> {
+        let (entries, events) = {
+            let apps = self.apps.lock().expect("logs lock poisoned");
+            let entries: Vec<LogEntry> = apps
+                .get(app_id)
+                .into_iter()
+                .flat_map(|app| &app.entries)
+                .filter(|e| since.is_none_or(|since| e.timestamp >= since))
+                .cloned()
+                .collect();
+            (entries, follow.then(|| self.events.subscribe()))
+        };
+
+        let stored = futures::stream::iter(entries);
+        let Some(events) = events else {
+            return stored.boxed();
+        };
+
+        let app_id = app_id.to_string();
+        let new = futures::stream::unfold(events, move |mut events| {
+            let app_id = app_id.clone();
+            async move {
+                loop {
+                    match events.recv().await {
+                        Ok(event) if event.0 == app_id => return Some((event.1.clone(), events)),
+                        // entries are dropped for followers that can't keep up
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+
+        stored.chain(new).boxed()
+    }
+}
+
+impl AppStateInner {
+    /// Stored log entries of an app that are newer than `since`, followed by new ones if `follow` is set
+    pub(crate) fn get_logs(
+        &self,
+        app_id: &str,
+        since: Option<time::OffsetDateTime>,
+        follow: bool,
+    ) -> BoxStream<'static, LogEntry> {
+        self.logs.stream(app_id, since, follow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nots_client::models::LogStream;
+    use time::{Duration, OffsetDateTime};
+
+    use super::*;
+
+    fn entry(worker_id: &str, line: &str, seconds: i64) -> LogEntry {
+        LogEntry {
+            timestamp: OffsetDateTime::UNIX_EPOCH + Duration::seconds(seconds),
+            worker_id: worker_id.to_string(),
+            stream: LogStream::Stdout,
+            line: line.to_string(),
+        }
+    }
+
+    fn lines(entries: &[LogEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.line.as_str()).collect()
+    }
+
+    fn store(apps: &[&str]) -> Arc<LogStore> {
+        let store = Arc::new(LogStore::default());
+        for app_id in apps {
+            drop(store.sender(app_id));
+        }
+        store
+    }
+
+    #[tokio::test]
+    async fn since() {
+        let store = store(&["app", "other"]);
+        store.push("app", entry("w1", "first", 0));
+        store.push("app", entry("w2", "second", 10));
+        store.push("other", entry("w3", "other app", 15));
+        store.push("app", entry("w1", "third", 20));
+
+        let all: Vec<_> = store.stream("app", None, false).collect().await;
+        assert_eq!(lines(&all), ["first", "second", "third"]);
+
+        // entries at exactly `since` are included
+        let since = OffsetDateTime::UNIX_EPOCH + Duration::seconds(10);
+        let recent: Vec<_> = store.stream("app", Some(since), false).collect().await;
+        assert_eq!(lines(&recent), ["second", "third"]);
+
+        assert!(store
+            .stream("unknown", None, false)
+            .collect::<Vec<_>>()
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn follow() {
+        let store = store(&["app", "other"]);
+        store.push("app", entry("w1", "stored", 0));
+
+        let mut stream = store.stream("app", None, true);
+        store.push("other", entry("w2", "other app", 1));
+        store.push("app", entry("w1", "new", 2));
+
+        assert_eq!(stream.next().await.unwrap().line, "stored");
+        assert_eq!(stream.next().await.unwrap().line, "new");
+    }
+
+    #[tokio::test]
+    async fn oldest_entries_are_dropped() {
+        let store = store(&["app"]);
+        for i in 0..MAX_ENTRIES + 5 {
+            store.push("app", entry("w1", &i.to_string(), i as i64));
+        }
+
+        let entries: Vec<_> = store.stream("app", None, false).collect().await;
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].line, "5");
+    }
+
+    #[tokio::test]
+    async fn size_is_limited() {
+        let store = store(&["app"]);
+        let line = "x".repeat(1024 * 1024);
+        for i in 0..6 {
+            store.push("app", entry("w1", &line, i));
+        }
+
+        let entries: Vec<_> = store.stream("app", None, false).collect().await;
+        assert_eq!(entries.len(), MAX_BYTES / line.len());
+        assert_eq!(entries[0].timestamp, OffsetDateTime::UNIX_EPOCH + Duration::seconds(2));
+    }
+
+    #[tokio::test]
+    async fn removed_apps_stay_removed() {
+        let store = Arc::new(LogStore::default());
+        let logs = store.sender("app");
+        logs.send(entry("w1", "before", 0)).await.unwrap();
+        tokio::task::yield_now().await;
+
+        store.remove("app");
+        store.push("app", entry("w1", "pushed", 1));
+        let _ = logs.send(entry("w1", "sent", 2)).await;
+        tokio::task::yield_now().await;
+
+        assert!(store.stream("app", None, false).collect::<Vec<_>>().await.is_empty());
+        // the forwarder is stopped, so workers of the app stop sending
+        tokio::time::timeout(std::time::Duration::from_secs(1), logs.closed()).await.unwrap();
+    }
+}
@@ -1,24 +1,38 @@
+mod artifacts;
+mod balancer;
 mod db;
+mod deploy_keys;
+mod health;
+mod logs;
+mod projects;
+mod releases;
+mod rollout;
+mod routing;
+mod scaling;
+mod scheduler;
+mod secrets;
+mod tokens;
 
 pub use db::fs_operator;
 use hyper_util::{
     client::legacy::{connect::HttpConnector, Client},
     rt::TokioExecutor,
 };
-use nots_client::models::{App, WorkerState};
+use nots_client::models::{App, Artifact, Project, Release, WorkerState};
 use okv::{backend::rocksdb::RocksDbOptimistic, types::serde::SerdeRmp, Database};
-use tokio::task::JoinSet;
+pub(crate) use projects::is_within;
+pub(crate) use releases::previous_release;
+use tokio::sync::Notify;
+use tracing::{debug, error};
 
-use crate::{
-    backend::NotsBackend,
-    utils::{AwaitAll, Secret},
-};
+use crate::{backend::NotsBackend, utils::Secret};
 use color_eyre::eyre::Result;
 use opendal::Operator;
+use routing::RoutingTable;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    sync::{atomic::AtomicBool, Arc},
+    collections::{HashMap, HashSet},
+    sync::{atomic::AtomicBool, Arc, Mutex, RwLock},
 };
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -31,6 +45,9 @@ pub struct Worker {
     pub process_id: Option<u32>,
 
     pub app_version: String,
+    pub address: Option<String>, // host:port the worker can be reached at
+    #[serde(default)]
+    pub release: Option<u64>, // release of the app the worker was started from
 }
 
 pub type AppState = Arc<AppStateInner>;
@@ -47,15 +64,27 @@ pub async fn try_new(
 
     let file = db::Fs(file);
 
+    let projects = db_env.open("projects")?;
     let apps = db_env.open("apps")?;
+    let artifacts = db_env.open("artifacts")?;
+    let secrets = db_env.open("secrets")?;
+    let tokens = db_env.open("tokens")?;
+    let deploy_keys = db_env.open("deploy_keys")?;
+    let releases = db_env.open("releases")?;
     let node_id = "1";
     let workers = db_env.open(&format!("workers-{}", node_id))?;
 
     let client = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
 
-    Ok(AppStateInner {
+    let state = AppStateInner {
         db_env: db_env.clone(),
+        projects,
         apps,
+        artifacts,
+        secrets,
+        tokens,
+        deploy_keys,
+        releases,
         workers,
         stated_at: time::OffsetDateTime::now_utc(),
         file,
@@ -63,13 +92,34 @@ pub async fn try_new(
         running: AtomicBool::new(false),
         processes,
         client,
-    }
-    .into())
+        routing: RwLock::default(),
+        reconcile_now: Notify::new(),
+        repos_changed: Notify::new(),
+        activity: Mutex::default(),
+        wake_locks: Mutex::default(),
+        starting: Mutex::default(),
+        health: Mutex::default(),
+        logs: Arc::default(),
+        balancer: Arc::default(),
+        rollouts: Mutex::default(),
+        release_numbers: Mutex::default(),
+    };
+
+    state.ensure_default_project()?;
+    state.ensure_initial_token()?;
+    state.refresh_routing()?;
+    Ok(state.into())
 }
 
 pub struct AppStateInner {
     pub db_env: okv::Env<RocksDbOptimistic>,
+    pub projects: Database<String, SerdeRmp<Project>, RocksDbOptimistic>, // key: <name>
     pub apps: Database<String, SerdeRmp<App>, RocksDbOptimistic>,
+    pub artifacts: Database<String, SerdeRmp<Artifact>, RocksDbOptimistic>, // key: <app_id>/<artifact_id>
+    pub secrets: Database<String, SerdeRmp<secrets::StoredSecret>, RocksDbOptimistic>, // key: <app_id>/<name>
+    pub tokens: Database<String, SerdeRmp<tokens::StoredToken>, RocksDbOptimistic>, // key: <token_id>
+    pub deploy_keys: Database<String, SerdeRmp<deploy_keys::StoredDeployKey>, RocksDbOptimistic>, // key: <key_id>
+    pub releases: Database<String, SerdeRmp<Release>, RocksDbOptimistic>,   // key: <app_id>/<number>
     pub workers: Database<String, SerdeRmp<Worker>, RocksDbOptimistic>,
 
     pub running: AtomicBool,
@@ -80,50 +130,70 @@ pub struct AppStateInner {
 
     pub kw_secret: Secret,
     pub client: Client<hyper_util::client::legacy::connect::HttpConnector, axum::body::Body>,
+    routing: RwLock<Arc<RoutingTable>>,
+    reconcile_now: Notify,            // wakes up the scheduler before the next interval
+    pub(crate) repos_changed: Notify, // wakes up the git watcher before the next interval
+
+    activity: Mutex<HashMap<String, time::OffsetDateTime>>, // last request per app id
+    wake_locks: Mutex<HashMap<String, scaling::WakeLock>>,  // held while an app is woken up
+    starting: Mutex<HashSet<String>>,                       // ids of workers that are being created and not stored yet
+    health: Mutex<HashMap<String, health::WorkerHealth>>,   // health check results per worker id
+    logs: Arc<logs::LogStore>,                              // recent output of the workers per app
+    balancer: Arc<balancer::Balancer>,                      // open requests and failed workers
+    rollouts: Mutex<rollout::Rollouts>,                     // apps whose workers are being replaced
+    release_numbers: Mutex<()>,                             // held while the next release number is assigned
 }
 
 impl AppStateInner {
-    pub async fn run(&self) -> Result<()> {
+    pub async fn run(self: Arc<Self>) -> Result<()> {
         use std::sync::atomic::Ordering::Relaxed;
         if self.running.load(Relaxed) {
             panic!("State is already running");
         }
 
         self.running.store(true, Relaxed);
-        let apps = self.get_apps()?;
-        let workers = self.get_workers()?;
-        let mut joinset = JoinSet::new();
-
+        tokio::spawn(self.clone().check_health());
         loop {
-            // check for invalid workers (not running, not needed)
-            for (id, w) in workers.iter() {
-                let Some(app) = apps.get(&w.app_id) else {
-                    todo!("Clean up invalid workers");
-                };
-
-                todo!("update worker state");
-
-                if app.needs_restart_since.unwrap_or(time::OffsetDateTime::UNIX_EPOCH) > w.updated_at {
-                    todo!("Restart worker");
-                }
-
-                joinset.spawn(async move { Result::<()>::Ok(()) });
+            if let Err(e) = self.reconcile().await {
+                error!("Scheduler: {:?}", e);
             }
-            joinset.await_all("workers").await?;
-
-            // check for new apps that need workers
-            for (aid, app) in apps.iter() {}
 
-            tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_secs(30)) => {}
+                _ = self.reconcile_now.notified() => debug!("Scheduler: woken up by app change"),
+            }
         }
     }
 
-    pub(crate) fn get_proxy_uri(&self, uri: hyper::Uri) -> hyper::Uri {
+    /// Find the app responsible for a request and the id and address of the worker it should go to
+    ///
+    /// Returns `Ok(None)` if no app matches the request.
+    pub(crate) fn get_proxy_target(
+        &self,
+        host: &str,
+        path: &str,
+    ) -> Result<Option<(String, Option<balancer::Target>)>> {
+        let routing = self.routing.read().expect("routing table lock poisoned").clone();
+        let Some(app_id) = routing.find(host, path) else {
+            return Ok(None);
+        };
+
+        Ok(Some((app_id.to_string(), self.pick_worker(app_id)?)))
+    }
+
+    pub(crate) fn get_proxy_uri(&self, address: &str, uri: hyper::Uri) -> Result<hyper::Uri> {
         let mut new_uri_parts = hyper::http::uri::Parts::default();
-        new_uri_parts.scheme = Some("http".parse().unwrap());
-        new_uri_parts.authority = Some("localhost:3333".parse().unwrap());
+        new_uri_parts.scheme = Some(hyper::http::uri::Scheme::HTTP);
+        new_uri_parts.authority = Some(address.parse()?);
         new_uri_parts.path_and_query = uri.path_and_query().cloned();
-        hyper::Uri::from_parts(new_uri_parts).unwrap()
+        Ok(hyper::Uri::from_parts(new_uri_parts)?)
+    }
+
+    /// Rebuild the routing table, has to be called whenever an app is changed
+    fn refresh_routing(&self) -> Result<()> {
+        let table = RoutingTable::new(&self.get_apps()?);
+        *self.routing.write().expect("routing table lock poisoned") = Arc::new(table);
+        Ok(())
     }
 
     fn delete_worker(&self, id: &str) -> Result<()> {
@@ -134,9 +204,19 @@ impl AppStateInner {
         Ok(self.workers.set(id, &worker)?)
     }
 
-    fn get_workers(&self) -> Result<Vec<(String, Worker)>> {
-        unimplemented!();
+    fn get_app_workers(&self, app_id: &str) -> Result<Vec<(String, Worker)>> {
+        let workers = self
+            .workers
+            .iter()?
+            .filter_map(|res| res.ok())
+            .filter(|a| a.1.app_id == app_id)
+            .map(|a| (a.0.to_owned(), a.1))
+            .collect();
 
+        Ok(workers)
+    }
+
+    fn get_workers(&self) -> Result<Vec<(String, Worker)>> {
         let workers = self
             .workers
             .iter()?
@@ -147,17 +227,44 @@ impl AppStateInner {
         Ok(workers)
     }
 
-    fn create_app(&self, app: App) -> Result<Option<String>> {
+    pub(crate) fn create_app(&self, app: App) -> Result<Option<String>> {
         let id = cuid2::cuid();
-        Ok(self.apps.set(&id, &app).map(|_| Some(id))?)
+        self.apps.set(&id, &app)?;
+        self.refresh_routing()?;
+        Ok(Some(id))
     }
 
-    fn get_app(&self, app_id: &str) -> Result<Option<App>> {
+    pub(crate) fn update_app(&self, app_id: &str, app: App) -> Result<()> {
+        self.apps.set(app_id, &app)?;
+        self.reconcile_now.notify_one();
+        self.refresh_routing()
+    }
+
+    pub(crate) async fn delete_app(&self, app_id: &str) -> Result<()> {
+        self.apps.delete(app_id)?;
+        self.delete_secrets(app_id)?;
+        self.delete_releases(app_id)?;
+        self.delete_artifacts(app_id).await?;
+        self.activity.lock().expect("activity lock poisoned").remove(app_id);
+        self.logs.remove(app_id);
+        self.balancer.forget_app(app_id);
+        self.reconcile_now.notify_one();
+        self.refresh_routing()
+    }
+
+    pub(crate) fn get_app(&self, app_id: &str) -> Result<Option<App>> {
         let app = self.apps.get(app_id)?;
         Ok(app)
     }
 
-    fn get_apps(&self) -> Result<HashMap<String, App>> {
+    pub(crate) fn get_app_by_name(&self, project: &str, name: &str) -> Result<Option<(String, App)>> {
+        Ok(self
+            .get_apps()?
+            .into_iter()
+            .find(|(_, app)| app.project == project && app.name == name))
+    }
+
+    pub(crate) fn get_apps(&self) -> Result<HashMap<String, App>> {
         let apps = self
             .apps
             .iter()?
@@ -168,3 +275,118 @@ impl AppStateInner {
         Ok(apps)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use axum::async_trait;
+    use nots_client::models::{
+        App, Artifact, ArtifactKind, WorkerRuntimeOptions, WorkerSettings, WorkerState, WorkerStatus, DEFAULT_PROJECT,
+    };
+
+    use super::{AppState, RocksDbOptimistic, Worker};
+    use crate::backend::{CreateWorker, CreatedWorker, NotsBackend};
+    use color_eyre::eyre::{ContextCompat, Result};
+
+    /// A state with its database and files in a temporary directory, which is removed with the returned guard
+    pub(crate) async fn state(backend: impl NotsBackend + 'static) -> (AppState, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let fs = dir.path().join("fs");
+        std::fs::create_dir_all(&fs).unwrap();
+
+        let db = RocksDbOptimistic::new(dir.path().join("db").to_str().unwrap()).unwrap();
+        let file = super::fs_operator(fs.to_str().unwrap()).unwrap();
+        let state = super::try_new(okv::Env::new(db), file, "a-test-secret-for-key-wrapping", Box::new(backend))
+            .await
+            .unwrap();
+        (state, dir)
+    }
+
+    /// A running worker of the first version of an app
+    pub(crate) fn worker(app_id: &str) -> Worker {
+        Worker {
+            app_id: app_id.to_string(),
+            state: WorkerState {
+                status: WorkerStatus::Running,
+                restart_count: None,
+                oom_killed: false,
+            },
+            updated_at: time::OffsetDateTime::now_utc(),
+            container_id: None,
+            process_id: None,
+            app_version: "v1".to_string(),
+            address: None,
+            release: None,
+        }
+    }
+
+    pub(crate) fn app(name: &str) -> App {
+        App {
+            name: name.to_string(),
+            hostnames: Vec::new(),
+            routes: Vec::new(),
+            route_priority: 0,
+            worker_settings: WorkerSettings::default(),
+            worker_runtime: WorkerRuntimeOptions::Process {},
+            version: None,
+            updated_at: None,
+            needs_restart_since: None,
+            project: DEFAULT_PROJECT.to_string(),
+            git: None,
+            commit: None,
+            idle_timeout: None,
+            pause_timeout: None,
+            health_check: None,
+            replicas: 1,
+        }
+    }
+
+    /// Store a small file as a new artifact of the app
+    pub(crate) async fn artifact(state: &AppState, app_id: &str) -> Artifact {
+        let body = futures::stream::once(async { Ok::<_, std::io::Error>("console.log('hi')".into()) });
+        let kind = ArtifactKind::File {
+            name: "index.js".to_string(),
+        };
+        state.store_artifact(app_id, kind, Box::pin(body)).await.unwrap()
+    }
+
+    /// Backend that only keeps track of the workers it was asked to create, they are running until removed
+    #[derive(Clone, Default)]
+    pub(crate) struct FakeBackend {
+        pub(crate) workers: Arc<Mutex<HashMap<String, WorkerStatus>>>,
+    }
+
+    #[async_trait]
+    impl NotsBackend for FakeBackend {
+        async fn workers_get(&self) -> Result<HashMap<String, WorkerStatus>> {
+            Ok(self.workers.lock().unwrap().clone())
+        }
+
+        async fn worker_create(&self, worker: CreateWorker) -> Result<CreatedWorker> {
+            self.workers
+                .lock()
+                .unwrap()
+                .insert(worker.worker_id, WorkerStatus::Running);
+            Ok(CreatedWorker {
+                address: Some("127.0.0.1:1".to_string()),
+                ..Default::default()
+            })
+        }
+
+        async fn worker_state(&self, id: &str) -> Result<WorkerState> {
+            let status = self.workers.lock().unwrap().get(id).cloned().context("Worker not found")?;
+            Ok(WorkerState {
+                status,
+                restart_count: None,
+                oom_killed: false,
+            })
+        }
+
+        async fn worker_remove(&self, id: &str) -> Result<()> {
+            self.workers.lock().unwrap().remove(id);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::Result;
+use nots_client::models::{App, Match, Project, DEFAULT_PROJECT};
+use tracing::info;
+
+use super::AppStateInner;
+
+impl AppStateInner {
+    /// The default project always exists, apps created without a project end up there
+    pub(super) fn ensure_default_project(&self) -> Result<()> {
+        if self.get_project(DEFAULT_PROJECT)?.is_none() {
+            self.set_project(Project {
+                name: DEFAULT_PROJECT.to_string(),
+                hostnames: Vec::new(),
+                created_at: time::OffsetDateTime::now_utc(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Create or replace a project
+    pub(crate) fn set_project(&self, project: Project) -> Result<()> {
+        self.projects.set(&project.name, &project)?;
+        info!("Stored project {}", project.name);
+        Ok(())
+    }
+
+    /// Projects can only be deleted once they don't have any apps
+    pub(crate) fn delete_project(&self, name: &str) -> Result<()> {
+        self.projects.delete(name)?;
+        info!("Deleted project {}", name);
+        Ok(())
+    }
+
+    pub(crate) fn get_project(&self, name: &str) -> Result<Option<Project>> {
+        Ok(self.projects.get(name)?)
+    }
+
+    pub(crate) fn get_projects(&self) -> Result<Vec<Project>> {
+        let mut projects: Vec<Project> = self
+            .projects
+            .iter()?
+            .filter_map(|res| res.ok())
+            .map(|(_, project)| project)
+            .collect();
+
+        projects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(projects)
+    }
+
+    pub(crate) fn get_project_apps(&self, project: &str) -> Result<HashMap<String, App>> {
+        Ok(self
+            .get_apps()?
+            .into_iter()
+            .filter(|(_, app)| app.project == project)
+            .collect())
+    }
+
+    /// The project owning `hostname`, i.e. the hostname is one of its domains or a subdomain of one
+    pub(crate) fn hostname_owner(&self, hostname: &Match) -> Result<Option<Project>> {
+        let Match::Glob(hostname) = hostname else {
+            return Ok(None);
+        };
+
+        let hostname = hostname.to_ascii_lowercase();
+        Ok(self
+            .get_projects()?
+            .into_iter()
+            .find(|p| p.hostnames.iter().any(|domain| is_within(&hostname, domain))))
+    }
+}
+
+/// Whether `hostname` is `domain` or one of its subdomains, e.g. `*.example.com` is within `example.com`
+pub(crate) fn is_within(hostname: &str, domain: &str) -> bool {
+    let domain = domain.to_ascii_lowercase();
+    hostname == domain || hostname.ends_with(&format!(".{}", domain))
+}
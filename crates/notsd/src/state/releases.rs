@@ -0,0 +1,278 @@
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use nots_client::models::{App, Release};
+use tracing::info;
+
+use super::AppStateInner;
+
+impl AppStateInner {
+    /// Record the current version and settings of an app as its next release
+    pub(super) fn create_release(
+        &self,
+        app_id: &str,
+        app: &App,
+        deployed_by: &str,
+        rollback_of: Option<u64>,
+    ) -> Result<Release> {
+        // the number is taken from the stored releases, so concurrent deploys must not read the same one
+        let _lock = self.release_numbers.lock().expect("release lock poisoned");
+        let number = self.get_releases(app_id)?.last().map_or(1, |r| r.number + 1);
+        let release = Release {
+            number,
+            app_id: app_id.to_string(),
+            artifact_id: app.version.clone().context("App has no deployed version")?,
+            commit: app.commit.clone(),
+            worker_settings: app.worker_settings.clone(),
+            worker_runtime: app.worker_runtime.clone(),
+            deployed_by: deployed_by.to_string(),
+            rollback_of,
+            created_at: time::OffsetDateTime::now_utc(),
+        };
+
+        if !self.releases.set_nx(&release_key(app_id, number), &release)? {
+            bail!("Release {} of app {} already exists", number, app_id);
+        }
+        Ok(release)
+    }
+
+    /// Releases of an app, oldest first
+    pub(crate) fn get_releases(&self, app_id: &str) -> Result<Vec<Release>> {
+        let mut releases: Vec<Release> = self
+            .releases
+            .iter_prefix::<String>(&format!("{}/", app_id))?
+            .filter_map(|res| res.ok())
+            .map(|(_, release)| release)
+            .collect();
+
+        releases.sort_by_key(|r| r.number);
+        Ok(releases)
+    }
+
+    /// Store an app whose workers have to be restarted with new settings, recorded as a release if it was deployed
+    ///
+    /// Otherwise rolling back would bring back the settings of an older release and silently undo the change.
+    pub(crate) fn update_app_settings(&self, app_id: &str, mut app: App, changed_by: &str) -> Result<App> {
+        let now = time::OffsetDateTime::now_utc();
+        app.updated_at = Some(now);
+        app.needs_restart_since = Some(now);
+        self.update_app(app_id, app.clone())?;
+
+        if app.version.is_some() {
+            let release = self.create_release(app_id, &app, changed_by, None)?;
+            info!("Changed settings of app {} as release {}", app_id, release.number);
+        }
+        Ok(app)
+    }
+
+    pub(crate) fn get_release(&self, app_id: &str, number: u64) -> Result<Option<Release>> {
+        Ok(self.releases.get(&release_key(app_id, number))?)
+    }
+
+    /// Number of the current release of an app, if it deployed `version`
+    pub(super) fn release_of(&self, app_id: &str, version: &str) -> Result<Option<u64>> {
+        let current = self.get_releases(app_id)?.pop();
+        Ok(current.filter(|r| r.artifact_id == version).map(|r| r.number))
+    }
+
+    /// Deploy the artifact and settings of an earlier release again, recorded as a new release
+    pub(crate) fn roll_back_to(&self, app_id: &str, release: &Release, deployed_by: &str) -> Result<Release> {
+        let mut app = self.get_app(app_id)?.context("App not found")?;
+        if self.get_artifact(app_id, &release.artifact_id)?.is_none() {
+            bail!(
+                "Artifact {} of release {} not found",
+                release.artifact_id,
+                release.number
+            );
+        }
+
+        let now = time::OffsetDateTime::now_utc();
+        app.version = Some(release.artifact_id.clone());
+        app.commit = release.commit.clone();
+        app.worker_settings = release.worker_settings.clone();
+        app.worker_runtime = release.worker_runtime.clone();
+        app.updated_at = Some(now);
+        app.needs_restart_since = Some(now);
+        self.update_app(app_id, app.clone())?;
+
+        let rollback = self.create_release(app_id, &app, deployed_by, Some(release.number))?;
+        info!(
+            "Rolled back app {} to release {} as release {}",
+            app_id, release.number, rollback.number
+        );
+        Ok(rollback)
+    }
+
+    pub(super) fn delete_releases(&self, app_id: &str) -> Result<()> {
+        for release in self.get_releases(app_id)? {
+            self.releases.delete(&release_key(app_id, release.number))?;
+        }
+        Ok(())
+    }
+}
+
+fn release_key(app_id: &str, number: u64) -> String {
+    format!("{}/{:010}", app_id, number)
+}
+
+/// The release a rollback goes back to if none is given: the one before the current release, or with
+/// `different_version` the last one that deployed another version, skipping releases that only changed settings
+pub(crate) fn previous_release(releases: &[Release], different_version: bool) -> Option<&Release> {
+    let (current, earlier) = releases.split_last()?;
+    match different_version {
+        true => earlier.iter().rev().find(|r| r.artifact_id != current.artifact_id),
+        false => earlier.last(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nots_client::models::{WorkerRuntimeOptions, WorkerSettings};
+
+    use super::*;
+    use crate::state::tests::{app, artifact, state, FakeBackend};
+
+    fn releases(artifacts: &[&str]) -> Vec<Release> {
+        (1..)
+            .zip(artifacts)
+            .map(|(number, artifact)| Release {
+                number,
+                app_id: "app".to_string(),
+                artifact_id: artifact.to_string(),
+                commit: None,
+                worker_settings: WorkerSettings::default(),
+                worker_runtime: WorkerRuntimeOptions::Process {},
+                deployed_by: "test".to_string(),
+                rollback_of: None,
+                created_at: time::OffsetDateTime::UNIX_EPOCH,
+            })
+            .collect()
+    }
+
+    fn previous(artifacts: &[&str], different_version: bool) -> Option<u64> {
+        previous_release(&releases(artifacts), different_version).map(|r| r.number)
+    }
+
+    #[test]
+    fn previous_release_is_the_one_before() {
+        assert_eq!(previous(&["a", "b"], false), Some(1));
+        // settings changes are releases of the same version
+        assert_eq!(previous(&["a", "b", "b", "b"], false), Some(3));
+        assert_eq!(previous(&["a", "a"], false), Some(1));
+    }
+
+    #[test]
+    fn previous_version_skips_same_version() {
+        assert_eq!(previous(&["a", "b"], true), Some(1));
+        assert_eq!(previous(&["a", "b", "b", "b"], true), Some(1));
+        // after a rollback from `c` to `b`, rolling back again returns to `c`
+        assert_eq!(previous(&["a", "b", "c", "b"], true), Some(3));
+        assert_eq!(previous(&["a", "b", "a"], true), Some(2));
+    }
+
+    #[test]
+    fn previous_release_missing() {
+        for different_version in [false, true] {
+            assert_eq!(previous(&[], different_version), None);
+            assert_eq!(previous(&["a"], different_version), None);
+        }
+        assert_eq!(previous(&["a", "a"], true), None);
+    }
+
+    #[tokio::test]
+    async fn rollback_undoes_settings_changes() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+
+        let v1 = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &v1.id, "test").unwrap();
+
+        let mut changed = state.get_app(&app_id).unwrap().unwrap();
+        changed.worker_settings.command = Some("bun run start".to_string());
+        state.update_app_settings(&app_id, changed, "test").unwrap();
+
+        let releases = state.get_releases(&app_id).unwrap();
+        let previous = previous_release(&releases, false).unwrap();
+        assert_eq!(previous.number, 1);
+        assert!(previous_release(&releases, true).is_none());
+
+        let rollback = state.roll_back_to(&app_id, previous, "test").unwrap();
+        assert_eq!(rollback.rollback_of, Some(1));
+        let app = state.get_app(&app_id).unwrap().unwrap();
+        assert_eq!(app.version.as_ref(), Some(&v1.id));
+        assert_eq!(app.worker_settings.command, None);
+    }
+
+    #[tokio::test]
+    async fn rollback_keeps_later_settings_changes() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+
+        let v1 = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &v1.id, "test").unwrap();
+
+        let mut changed = state.get_app(&app_id).unwrap().unwrap();
+        changed.worker_settings.command = Some("bun run start".to_string());
+        state.update_app_settings(&app_id, changed, "test").unwrap();
+
+        let v2 = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &v2.id, "test").unwrap();
+
+        let releases = state.get_releases(&app_id).unwrap();
+        assert_eq!(releases.len(), 3);
+        assert_eq!(previous_release(&releases, false).unwrap().number, 2);
+        let previous = previous_release(&releases, true).unwrap();
+        assert_eq!(previous.number, 2);
+
+        state.roll_back_to(&app_id, previous, "test").unwrap();
+        let app = state.get_app(&app_id).unwrap().unwrap();
+        assert_eq!(app.version.as_ref(), Some(&v1.id));
+        assert_eq!(app.worker_settings.command.as_deref(), Some("bun run start"));
+    }
+
+    #[tokio::test]
+    async fn settings_of_undeployed_apps_are_not_releases() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+
+        let mut changed = state.get_app(&app_id).unwrap().unwrap();
+        changed.worker_settings.command = Some("bun run start".to_string());
+        state.update_app_settings(&app_id, changed, "test").unwrap();
+        assert!(state.get_releases(&app_id).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deploy_with_settings_is_one_release() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+        let v1 = artifact(&state, &app_id).await;
+
+        let mut settings = state.get_app(&app_id).unwrap().unwrap().worker_settings;
+        settings.command = Some("bun run start".to_string());
+        let app = state
+            .deploy_artifact_with_settings(&app_id, &v1.id, Some(settings), None, "test")
+            .unwrap();
+        assert_eq!(app.worker_settings.command.as_deref(), Some("bun run start"));
+
+        let releases = state.get_releases(&app_id).unwrap();
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].artifact_id, v1.id);
+        assert_eq!(releases[0].worker_settings.command.as_deref(), Some("bun run start"));
+    }
+
+    #[tokio::test]
+    async fn concurrent_releases_get_distinct_numbers() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+        let v1 = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &v1.id, "test").unwrap();
+        let app = state.get_app(&app_id).unwrap().unwrap();
+
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| state.create_release(&app_id, &app, "test", None).unwrap());
+            }
+        });
+
+        let numbers: Vec<u64> = state.get_releases(&app_id).unwrap().iter().map(|r| r.number).collect();
+        assert_eq!(numbers, (1..=9).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, Result};
+use nots_client::models::{App, WorkerStatus};
+use tracing::{error, info, warn};
+
+use super::{AppStateInner, Worker};
+
+/// Time the new workers of a rollout have to become ready, on top of the health check's start period
+const ROLLOUT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time old workers get to finish their open requests after traffic was switched away from them
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub(super) struct Rollouts {
+    active: HashSet<String>,              // app ids
+    settled: HashMap<String, Generation>, // app ids whose last rollout failed, their workers are kept as they are
+}
+
+/// The version and settings the workers of an app should run with
+#[derive(PartialEq)]
+struct Generation {
+    version: Option<String>,
+    since: Option<time::OffsetDateTime>,
+}
+
+impl Generation {
+    fn of(app: &App) -> Self {
+        Self {
+            version: app.version.clone(),
+            since: app.needs_restart_since,
+        }
+    }
+}
+
+impl AppStateInner {
+    /// Whether a worker runs an older version or older settings than its app, and has to be replaced
+    pub(super) fn is_outdated(&self, worker: &Worker, app: &App) -> bool {
+        let outdated = app.version.as_deref() != Some(worker.app_version.as_str())
+            || app.needs_restart_since.unwrap_or(time::OffsetDateTime::UNIX_EPOCH) > worker.updated_at;
+
+        let rollouts = self.rollouts.lock().expect("rollouts lock poisoned");
+        outdated && rollouts.settled.get(&worker.app_id) != Some(&Generation::of(app))
+    }
+
+    /// Whether the workers of an app are being replaced, the scheduler leaves them alone in the meantime
+    pub(super) fn is_rolling_out(&self, app_id: &str) -> bool {
+        let rollouts = self.rollouts.lock().expect("rollouts lock poisoned");
+        rollouts.active.contains(app_id)
+    }
+
+    /// Replace the workers `old` of an app with new ones in the background
+    ///
+    /// The new workers only get requests once all of them are ready, then the old ones are drained and
+    /// removed. If they don't become ready in time, they are removed and the app goes back to the release
+    /// the old workers were started from.
+    pub(super) fn start_rollout(self: &Arc<Self>, app_id: &str, app: App, old: Vec<String>) {
+        {
+            let mut rollouts = self.rollouts.lock().expect("rollouts lock poisoned");
+            rollouts.active.insert(app_id.to_string());
+            rollouts.settled.remove(app_id);
+        }
+
+        let previous = old.iter().find_map(|id| self.workers.get(id).ok().flatten());
+        let (state, app_id) = (self.clone(), app_id.to_string());
+        tokio::spawn(async move {
+            if let Err(e) = state.roll_out(&app_id, &app, &old, previous).await {
+                error!("Rollout of app {}: {:?}", app.name, e);
+            }
+
+            state
+                .rollouts
+                .lock()
+                .expect("rollouts lock poisoned")
+                .active
+                .remove(&app_id);
+            state.reconcile_now.notify_one();
+        });
+    }
+
+    async fn roll_out(&self, app_id: &str, app: &App, old: &[String], previous: Option<Worker>) -> Result<()> {
+        let version = app.version.clone().unwrap_or_default();
+        info!(
+            "Rolling out version {} of app {} to {} workers",
+            version, app.name, app.replicas
+        );
+
+        let start = Instant::now();
+        let mut new = Vec::new();
+        if let Err(e) = self.start_new_workers(app_id, app, &mut new).await {
+            warn!("Rollout of app {} failed: {:#}", app.name, e);
+            self.roll_back(app_id, app, previous, &new).await?;
+            return Ok(());
+        }
+
+        self.balancer.switch(&new, old);
+        info!(
+            "Switched app {} to version {} in {}ms",
+            app.name,
+            version,
+            start.elapsed().as_millis()
+        );
+
+        let drain_start = Instant::now();
+        while self.balancer.in_flight(old) > 0 {
+            if drain_start.elapsed() > DRAIN_TIMEOUT {
+                warn!("Old workers of app {} still had open requests after draining", app.name);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        info!("Removing the old workers of app {}", app.name);
+        self.remove_workers(old).await
+    }
+
+    /// Start the new workers of a rollout and wait until all of them are ready, `new` collects their ids
+    async fn start_new_workers(&self, app_id: &str, app: &App, new: &mut Vec<String>) -> Result<()> {
+        for _ in 0..app.replicas {
+            let worker = self.prepare_worker(app_id, app)?;
+            self.balancer.hide(&worker.worker_id);
+            new.push(worker.worker_id.clone());
+            self.start_worker(worker, &app.name).await?;
+        }
+
+        let start_period = app.health_check.as_ref().map_or(0, |check| check.start_period);
+        let timeout = ROLLOUT_TIMEOUT + Duration::from_secs(start_period);
+        let start = Instant::now();
+        let mut ready = HashSet::new();
+        while ready.len() < new.len() {
+            if start.elapsed() > timeout {
+                bail!("Workers did not become ready within {}s", timeout.as_secs());
+            }
+
+            for id in new.iter() {
+                if !ready.contains(id) && self.is_ready(id, app).await? {
+                    ready.insert(id.clone());
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+
+    /// Whether a new worker passed its health checks, or accepts connections if the app has none
+    async fn is_ready(&self, id: &str, app: &App) -> Result<bool> {
+        match self.processes.worker_state(id).await?.status {
+            WorkerStatus::Running => {}
+            WorkerStatus::Exited | WorkerStatus::Dead => bail!("Worker {} exited", id),
+            _ => return Ok(false),
+        }
+
+        if app.health_check.is_some() {
+            if self.health_failed(id) {
+                bail!("Worker {} failed its health checks", id);
+            }
+            return Ok(self.is_healthy(id, app));
+        }
+
+        let Some(address) = self.workers.get(id)?.and_then(|w| w.address) else {
+            return Ok(false);
+        };
+        Ok(tokio::net::TcpStream::connect(address).await.is_ok())
+    }
+
+    /// Remove the new workers of a failed rollout and deploy the release the `previous` workers run again
+    async fn roll_back(&self, app_id: &str, app: &App, previous: Option<Worker>, new: &[String]) -> Result<()> {
+        self.remove_workers(new).await?;
+
+        let Some(current) = self.get_app(app_id)? else {
+            return Ok(());
+        };
+
+        // the app might have been changed again in the meantime, the next rollout takes care of it
+        if current.version != app.version || current.needs_restart_since != app.needs_restart_since {
+            return Ok(());
+        }
+
+        let latest = self.get_releases(app_id)?.pop().map(|r| r.number);
+        let release = match previous.as_ref().and_then(|w| w.release) {
+            Some(number) if Some(number) != latest => self.get_release(app_id, number)?,
+            _ => None,
+        };
+        let old_version = previous
+            .filter(|w| w.release.is_none())
+            .map(|w| w.app_version)
+            .filter(|version| current.version.as_ref() != Some(version));
+
+        let current = if let Some(release) = release {
+            warn!("Rolling back app {} to release {}", app.name, release.number);
+            self.roll_back_to(app_id, &release, "automatic rollback")?;
+            self.get_app(app_id)?.unwrap_or(current)
+        } else if let Some(old_version) = old_version {
+            // started before releases were recorded
+            warn!("Rolling back app {} to version {}", app.name, old_version);
+            self.deploy_artifact(app_id, &old_version, "automatic rollback")?
+        } else {
+            warn!(
+                "Keeping the old workers of app {} with their previous settings",
+                app.name
+            );
+            current
+        };
+
+        let mut rollouts = self.rollouts.lock().expect("rollouts lock poisoned");
+        rollouts.settled.insert(app_id.to_string(), Generation::of(&current));
+        Ok(())
+    }
+
+    async fn remove_workers(&self, ids: &[String]) -> Result<()> {
+        let running = self.processes.workers_get().await?;
+        for id in ids {
+            if let Err(e) = self.remove_worker(id, running.contains_key(id)).await {
+                error!("{:?}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use nots_client::models::{App, Match};
+use regex::bytes::Regex;
+use tracing::warn;
+
+struct Route {
+    app_id: String,
+    app_name: String,
+    hostnames: Vec<Regex>,
+    routes: Vec<Regex>,
+    priority: i16,
+    any_host: bool, // no hostnames configured, invalid ones don't count
+    any_path: bool, // no routes configured, invalid ones don't count
+}
+
+/// Compiled hostnames and routes of all apps, rebuilt whenever an app changes
+#[derive(Default)]
+pub struct RoutingTable {
+    routes: Vec<Route>, // sorted by priority, highest first
+}
+
+impl RoutingTable {
+    pub fn new(apps: &HashMap<String, App>) -> Self {
+        let mut routes: Vec<Route> = apps
+            .iter()
+            .map(|(id, app)| Route {
+                app_id: id.clone(),
+                app_name: app.name.clone(),
+                hostnames: compile(&app.hostnames, &app.name),
+                routes: compile(&app.routes, &app.name),
+                priority: app.route_priority,
+                any_host: app.hostnames.is_empty(),
+                any_path: app.routes.is_empty(),
+            })
+            .collect();
+
+        routes.sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.app_name.cmp(&b.app_name)));
+        Self { routes }
+    }
+
+    /// Find the app responsible for a request
+    ///
+    /// Apps with a matching hostname take precedence over apps without hostnames.
+    /// Apps without routes match every path.
+    pub fn find(&self, host: &str, path: &str) -> Option<&str> {
+        let matches_path = |r: &&Route| r.any_path || r.routes.iter().any(|re| re.is_match(path.as_bytes()));
+
+        let by_host = self
+            .routes
+            .iter()
+            .filter(|r| r.hostnames.iter().any(|re| re.is_match(host.as_bytes())))
+            .find(matches_path);
+
+        by_host
+            .or_else(|| self.routes.iter().filter(|r| r.any_host).find(matches_path))
+            .map(|r| r.app_id.as_str())
+    }
+}
+
+fn compile(matches: &[Match], app_name: &str) -> Vec<Regex> {
+    matches
+        .iter()
+        .filter_map(|m| match m.clone().regex().map(|re| Regex::new(&re)) {
+            Ok(Ok(re)) => Some(re),
+            _ => {
+                warn!("Ignoring invalid pattern {} of app {}", m, app_name);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use nots_client::models::{WorkerRuntimeOptions, WorkerSettings, DEFAULT_PROJECT};
+
+    use super::*;
+
+    fn app(name: &str, hostnames: &[&str], routes: &[&str], route_priority: i16) -> App {
+        let matches = |patterns: &[&str]| patterns.iter().map(|p| p.parse().unwrap()).collect();
+        App {
+            name: name.to_string(),
+            hostnames: matches(hostnames),
+            routes: matches(routes),
+            route_priority,
+            worker_settings: WorkerSettings::default(),
+            worker_runtime: WorkerRuntimeOptions::Process {},
+            version: None,
+            updated_at: None,
+            needs_restart_since: None,
+            project: DEFAULT_PROJECT.to_string(),
+            git: None,
+            commit: None,
+            idle_timeout: None,
+            pause_timeout: None,
+            health_check: None,
+            replicas: 1,
+        }
+    }
+
+    fn table(apps: Vec<App>) -> RoutingTable {
+        RoutingTable::new(&apps.into_iter().map(|app| (app.name.clone(), app)).collect())
+    }
+
+    #[test]
+    fn find_by_host_and_path() {
+        let table = table(vec![
+            app("web", &["example.com"], &[], 0),
+            app("api", &["example.com"], &["/api/*"], 1),
+            app(
+                "admin",
+                &["admin.example.com", "regex:^admin\\d+\\.example\\.com$"],
+                &[],
+                0,
+            ),
+        ]);
+
+        assert_eq!(table.find("example.com", "/"), Some("web"));
+        assert_eq!(table.find("example.com", "/api/users"), Some("api"));
+        // globs are case insensitive
+        assert_eq!(table.find("EXAMPLE.com", "/API/users"), Some("api"));
+        assert_eq!(table.find("admin.example.com", "/api/users"), Some("admin"));
+        assert_eq!(table.find("admin2.example.com", "/"), Some("admin"));
+        assert_eq!(table.find("other.com", "/"), None);
+    }
+
+    #[test]
+    fn hostnames_take_precedence() {
+        let table = table(vec![
+            app("fallback", &[], &[], 10),
+            app("web", &["example.com"], &["/app/*"], 0),
+        ]);
+
+        assert_eq!(table.find("example.com", "/app/index.html"), Some("web"));
+        // apps without hostnames match any host, but only if no app for the host matches the path
+        assert_eq!(table.find("example.com", "/other"), Some("fallback"));
+        assert_eq!(table.find("other.com", "/app/index.html"), Some("fallback"));
+    }
+
+    #[test]
+    fn priority_and_name_order() {
+        let table = table(vec![
+            app("b", &["example.com"], &["/*"], 0),
+            app("a", &["example.com"], &["/*"], 0),
+            app("c", &["example.com"], &["/c/*"], -1),
+        ]);
+
+        // same priority, so the name decides
+        assert_eq!(table.find("example.com", "/c/x"), Some("a"));
+
+        let table = self::table(vec![
+            app("b", &["example.com"], &[], 1),
+            app("a", &["example.com"], &[], 0),
+        ]);
+        assert_eq!(table.find("example.com", "/"), Some("b"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_ignored() {
+        let table = table(vec![
+            app("web", &["regex:("], &["/*"], 0),
+            app("api", &[], &["regex:(", "/api/*"], 0),
+        ]);
+
+        // an app with only invalid hostnames must not turn into a catch-all
+        assert_eq!(table.find("example.com", "/"), None);
+        assert_eq!(table.find("example.com", "/api/users"), Some("api"));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::{bail, eyre, Result};
+use nots_client::models::WorkerStatus;
+use tracing::info;
+
+use super::{balancer::Target, AppStateInner, Worker};
+
+/// Time a cold started worker has to accept connections
+const COLD_START_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(super) type WakeLock = Arc<tokio::sync::Mutex<()>>;
+
+impl AppStateInner {
+    /// Remember that an app is in use, apps with an idle timeout are stopped after a while without requests
+    pub(crate) fn record_request(&self, app_id: &str) {
+        let mut activity = self.activity.lock().expect("activity lock poisoned");
+        activity.insert(app_id.to_string(), time::OffsetDateTime::now_utc());
+    }
+
+    /// Whether the worker's app hasn't received a request for longer than `timeout` seconds
+    pub(super) fn is_idle(&self, app_id: &str, worker: &Worker, timeout: Option<u64>) -> bool {
+        let Some(timeout) = timeout else {
+            return false;
+        };
+
+        let activity = self.activity.lock().expect("activity lock poisoned");
+        let last_request = activity.get(app_id).copied().unwrap_or(worker.updated_at);
+        let idle_since = last_request.max(worker.updated_at);
+        time::OffsetDateTime::now_utc() - idle_since > time::Duration::seconds(timeout as i64)
+    }
+
+    /// Resume the paused workers of an app, or start one if the app was scaled to zero
+    ///
+    /// Concurrent requests for the same app wait for the same worker. Returns the id and address of the
+    /// worker the request should go to, or `Ok(None)` if the app has neither. The workers of apps without
+    /// an idle timeout are started by the scheduler, which also starts the remaining replicas.
+    pub(crate) async fn wake(&self, app_id: &str) -> Result<Option<Target>> {
+        let lock = {
+            let mut wake_locks = self.wake_locks.lock().expect("wake lock poisoned");
+            wake_locks.entry(app_id.to_string()).or_default().clone()
+        };
+        let _guard = lock.lock().await;
+
+        // another request might have started a worker while this one was waiting
+        if let Some(target) = self.pick_worker(app_id)? {
+            return Ok(Some(target));
+        }
+
+        let Some(app) = self.get_app(app_id)? else {
+            return Ok(None);
+        };
+
+        let start = std::time::Instant::now();
+        let paused: Vec<_> = self
+            .get_app_workers(app_id)?
+            .into_iter()
+            .filter(|(_, w)| matches!(w.state.status, WorkerStatus::Paused))
+            .collect();
+
+        if !paused.is_empty() {
+            for (id, mut worker) in paused {
+                // the scheduler might have stored an outdated status after the worker was resumed
+                if matches!(self.processes.worker_state(&id).await?.status, WorkerStatus::Paused) {
+                    self.processes.worker_resume(&id).await?;
+                }
+                worker.state.status = WorkerStatus::Running;
+                self.set_worker(&id, worker)?;
+
+                info!(
+                    "Resumed worker {} of app {} in {}ms",
+                    id,
+                    app.name,
+                    start.elapsed().as_millis()
+                );
+            }
+            return self.pick_worker(app_id);
+        }
+
+        if app.idle_timeout.is_none() || app.version.is_none() {
+            return Ok(None);
+        }
+
+        let worker = self.prepare_worker(app_id, &app)?;
+        let (id, worker) = self.start_worker(worker, &app.name).await?;
+
+        let address = worker
+            .address
+            .ok_or_else(|| eyre!("Worker of app {} has no address", app.name))?;
+        match &app.health_check {
+            Some(check) => {
+                let healthy = self.wait_until_healthy(&id, &address, check);
+                if tokio::time::timeout(COLD_START_TIMEOUT, healthy).await.is_err() {
+                    bail!("Worker at {} did not become healthy in time", address);
+                }
+            }
+            None => wait_until_ready(&address).await?,
+        }
+
+        info!("Cold started app {} in {}ms", app.name, start.elapsed().as_millis());
+        if app.replicas > 1 {
+            self.reconcile_now.notify_one();
+        }
+        Ok(Some((self.connect_to(&id), address)))
+    }
+
+    /// Whether a worker of the app is being woken up, a cold started worker's address is known before it is ready
+    pub(crate) fn is_waking(&self, app_id: &str) -> bool {
+        let wake_locks = self.wake_locks.lock().expect("wake lock poisoned");
+        wake_locks.get(app_id).is_some_and(|lock| lock.try_lock().is_err())
+    }
+}
+
+/// Wait until the worker accepts connections, starting the process doesn't mean it is listening yet
+async fn wait_until_ready(address: &str) -> Result<()> {
+    let ready = async {
+        while tokio::net::TcpStream::connect(address).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    };
+
+    if tokio::time::timeout(COLD_START_TIMEOUT, ready).await.is_err() {
+        bail!("Worker at {} did not accept connections in time", address);
+    }
+    Ok(())
+}
@@ -0,0 +1,399 @@
+use std::{collections::HashMap, sync::Arc};
+
+use color_eyre::eyre::{Context, Result};
+use nots_client::models::{App, WorkerState, WorkerStatus};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use super::{artifacts::code_dir, AppStateInner, Worker};
+use crate::{backend::CreateWorker, utils::AwaitAll};
+
+/// What the scheduler decided to do with a worker
+enum Action {
+    Remove(&'static str),
+    Restart(&'static str),
+    RollOut,
+    Pause,
+    Update(WorkerState),
+}
+
+impl AppStateInner {
+    /// Bring the workers in the backend in line with the apps and workers in the database
+    pub(super) async fn reconcile(self: &Arc<Self>) -> Result<()> {
+        let apps = self.get_apps()?;
+        let workers = self.get_workers()?;
+        let running = self
+            .processes
+            .workers_get()
+            .await
+            .wrap_err("Could not get workers from backend")?;
+
+        let mut joinset = JoinSet::new();
+        let mut replicas: HashMap<String, u32> = HashMap::new(); // workers that are kept per app id
+
+        for (id, w) in workers.iter() {
+            // workers of apps that are being rolled out are replaced by the rollout
+            if self.is_rolling_out(&w.app_id) {
+                continue;
+            }
+
+            let mut action = match (apps.get(&w.app_id), running.get(id)) {
+                (None, _) => Action::Remove("app was deleted"),
+                (Some(app), _) if app.version.is_none() => Action::Remove("app has no deployed version"),
+                (Some(app), _) if self.is_idle(&w.app_id, w, app.idle_timeout) => Action::Remove("app is idle"),
+                (Some(app), _) if self.is_outdated(w, app) => Action::RollOut,
+                // scaled to zero apps are started again by their next request
+                (Some(app), None | Some(WorkerStatus::Exited | WorkerStatus::Dead)) if app.idle_timeout.is_some() => {
+                    Action::Remove("worker stopped")
+                }
+                (Some(_), None) => Action::Restart("worker is missing in the backend"),
+                (Some(_), Some(WorkerStatus::Exited | WorkerStatus::Dead)) => {
+                    match self.processes.worker_state(id).await {
+                        Ok(state) if state.oom_killed => Action::Restart("worker ran out of memory"),
+                        _ => Action::Restart("worker exited"),
+                    }
+                }
+                (Some(_), Some(WorkerStatus::Running)) if self.health_failed(id) => {
+                    Action::Restart("health check failed")
+                }
+                (Some(app), Some(WorkerStatus::Running))
+                    if self.processes.can_pause() && self.is_idle(&w.app_id, w, app.pause_timeout) =>
+                {
+                    Action::Pause
+                }
+                (Some(_), Some(_)) => match self.processes.worker_state(id).await {
+                    Ok(state) => Action::Update(state),
+                    Err(e) => {
+                        warn!("Could not get state of worker {}: {}", id, e);
+                        continue;
+                    }
+                },
+            };
+
+            // workers beyond the number of replicas are removed, e.g. after the app was scaled down
+            if !matches!(action, Action::Remove(_) | Action::RollOut) {
+                let kept = replicas.entry(w.app_id.clone()).or_default();
+                match apps.get(&w.app_id) {
+                    Some(app) if *kept >= app.replicas => action = Action::Remove("app has too many replicas"),
+                    _ => *kept += 1,
+                }
+            }
+
+            let (id, w, state) = (id.clone(), w.clone(), self.clone());
+            let exists = running.contains_key(&id);
+            match action {
+                Action::Remove(reason) => {
+                    info!("Removing worker {} of app {}: {}", id, w.app_id, reason);
+                    joinset.spawn(async move { state.remove_worker(&id, exists).await });
+                }
+                Action::Restart(reason) => {
+                    let app = apps[&w.app_id].clone();
+                    info!("Restarting worker {} of app {}: {}", id, app.name, reason);
+
+                    // keep the old worker if the new one can't be started anyway
+                    let worker = match self.prepare_worker(&w.app_id, &app) {
+                        Ok(worker) => worker,
+                        Err(e) => {
+                            error!("Not restarting worker {} of app {}: {}", id, app.name, e);
+                            continue;
+                        }
+                    };
+
+                    joinset.spawn(async move {
+                        state.remove_worker(&id, exists).await?;
+                        state.start_worker(worker, &app.name).await.map(|_| ())
+                    });
+                }
+                Action::RollOut => {
+                    let old = workers
+                        .iter()
+                        .filter(|(_, o)| o.app_id == w.app_id)
+                        .map(|(id, _)| id.clone());
+                    self.start_rollout(&w.app_id, apps[&w.app_id].clone(), old.collect());
+                }
+                Action::Pause => {
+                    info!("Pausing worker {} of app {}: app is idle", id, w.app_id);
+                    joinset.spawn(async move { state.pause_worker(&id, w).await });
+                }
+                Action::Update(worker_state) => {
+                    debug!("Worker {} of app {} is {:?}", id, w.app_id, worker_state.status);
+                    self.set_worker(
+                        &id,
+                        Worker {
+                            state: worker_state,
+                            ..w
+                        },
+                    )?;
+                }
+            }
+        }
+
+        // workers the backend knows about but that aren't tracked anymore, e.g. after a crash of notsd
+        for id in running.keys().filter(|id| !workers.iter().any(|(w, _)| w == *id)) {
+            let (id, state) = (id.clone(), self.clone());
+            joinset.spawn(async move {
+                // a cold start might have created and stored the worker since the lists were read
+                if !state.is_orphaned(&id)? {
+                    return Ok(());
+                }
+
+                info!("Removing orphaned worker {}", id);
+                state.remove_worker(&id, true).await
+            });
+        }
+
+        // apps that have been deployed but don't have enough workers yet, scaled to zero apps are started on demand
+        for (app_id, app) in apps.into_iter() {
+            let kept = replicas.get(&app_id).copied().unwrap_or_default();
+            if app.version.is_none() || (app.idle_timeout.is_some() && kept == 0) || self.is_rolling_out(&app_id) {
+                continue;
+            }
+
+            for _ in kept..app.replicas {
+                info!("Starting worker for app {}", app.name);
+                let worker = match self.prepare_worker(&app_id, &app) {
+                    Ok(worker) => worker,
+                    Err(e) => {
+                        error!("{:?}", e);
+                        break;
+                    }
+                };
+
+                let (state, name) = (self.clone(), app.name.clone());
+                joinset.spawn(async move { state.start_worker(worker, &name).await.map(|_| ()) });
+            }
+        }
+
+        joinset.await_all("scheduler").await
+    }
+
+    /// Resolve everything a new worker needs, fails if a referenced secret is missing
+    pub(super) fn prepare_worker(&self, app_id: &str, app: &App) -> Result<CreateWorker> {
+        let app_version = app.version.clone().unwrap_or_default();
+        let env = self
+            .resolve_env(app_id, &app.worker_settings.env)
+            .wrap_err_with(|| format!("Could not start a worker for app {}", app.name))?;
+
+        Ok(CreateWorker {
+            worker_id: cuid2::cuid(),
+            app_id: app_id.to_string(),
+            code_path: self.file.local_path(&code_dir(app_id, &app_version)),
+            app_version,
+            runtime_options: app.worker_runtime.clone(),
+            settings: app.worker_settings.clone(),
+            env,
+            logs: self.logs.sender(app_id),
+        })
+    }
+
+    /// Create a worker in the backend and store it, returns its id and the stored worker
+    pub(super) async fn start_worker(&self, worker: CreateWorker, app_name: &str) -> Result<(String, Worker)> {
+        let (worker_id, app_id, app_version) = (
+            worker.worker_id.clone(),
+            worker.app_id.clone(),
+            worker.app_version.clone(),
+        );
+
+        // cold starts run alongside the scheduler, which must not mistake the new worker for an orphan
+        let _starting = Starting::new(self, &worker_id);
+        let created = self
+            .processes
+            .worker_create(worker)
+            .await
+            .wrap_err_with(|| format!("Could not create worker for app {}", app_name))?;
+
+        let state = match self.processes.worker_state(&worker_id).await {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("Could not get state of worker {}: {}", worker_id, e);
+                WorkerState {
+                    status: WorkerStatus::Created,
+                    restart_count: None,
+                    oom_killed: false,
+                }
+            }
+        };
+
+        info!("Started worker {} of app {} ({:?})", worker_id, app_name, state.status);
+        let release = self.release_of(&app_id, &app_version)?;
+        let worker = Worker {
+            app_id,
+            state,
+            updated_at: time::OffsetDateTime::now_utc(),
+            container_id: created.container_id,
+            process_id: created.process_id,
+            app_version,
+            address: created.address,
+            release,
+        };
+        self.set_worker(&worker_id, worker.clone())?;
+        Ok((worker_id, worker))
+    }
+
+    /// Whether a worker that exists in the backend is neither stored nor being created
+    fn is_orphaned(&self, id: &str) -> Result<bool> {
+        // workers leave `starting` only after they are stored, so it has to be checked first
+        if self.starting.lock().expect("starting lock poisoned").contains(id) {
+            return Ok(false);
+        }
+        Ok(self.workers.get(id)?.is_none())
+    }
+
+    async fn pause_worker(&self, id: &str, mut worker: Worker) -> Result<()> {
+        self.processes
+            .worker_pause(id)
+            .await
+            .wrap_err_with(|| format!("Could not pause worker {}", id))?;
+
+        worker.state.status = WorkerStatus::Paused;
+        self.set_worker(id, worker)
+    }
+
+    /// Remove a worker from the backend (if it still exists there) and the database
+    pub(super) async fn remove_worker(&self, id: &str, in_backend: bool) -> Result<()> {
+        // the proxy only uses running workers, so requests don't reach a worker that is being stopped
+        if let Some(mut worker) = self.workers.get(id)? {
+            worker.state.status = WorkerStatus::Removing;
+            self.set_worker(id, worker)?;
+        }
+
+        if in_backend {
+            self.processes
+                .worker_remove(id)
+                .await
+                .wrap_err_with(|| format!("Could not remove worker {}", id))?;
+        }
+
+        self.forget_health(id);
+        self.balancer.forget(id);
+        self.delete_worker(id)
+    }
+}
+
+/// Marks a worker as being created until it is stored in the database
+struct Starting<'a>(&'a AppStateInner, String);
+
+impl<'a> Starting<'a> {
+    fn new(state: &'a AppStateInner, worker_id: &str) -> Self {
+        state
+            .starting
+            .lock()
+            .expect("starting lock poisoned")
+            .insert(worker_id.to_string());
+        Self(state, worker_id.to_string())
+    }
+}
+
+impl Drop for Starting<'_> {
+    fn drop(&mut self) {
+        self.0.starting.lock().expect("starting lock poisoned").remove(&self.1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::{app, artifact, state, worker, FakeBackend};
+
+    fn worker_ids(state: &AppStateInner, app_id: &str) -> Vec<String> {
+        let mut ids: Vec<String> = state.get_app_workers(app_id).unwrap().into_iter().map(|(id, _)| id).collect();
+        ids.sort();
+        ids
+    }
+
+    fn backend_ids(backend: &FakeBackend) -> Vec<String> {
+        let mut ids: Vec<String> = backend.workers.lock().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn deployed_apps_get_their_replicas() {
+        let backend = FakeBackend::default();
+        let (state, _dir) = state(backend.clone()).await;
+        let app_id = state.create_app(App { replicas: 2, ..app("web") }).unwrap().unwrap();
+
+        state.reconcile().await.unwrap();
+        assert!(worker_ids(&state, &app_id).is_empty());
+
+        let v1 = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &v1.id, "test").unwrap();
+        state.reconcile().await.unwrap();
+        assert_eq!(worker_ids(&state, &app_id).len(), 2);
+        assert_eq!(worker_ids(&state, &app_id), backend_ids(&backend));
+
+        // nothing changes once the app has its workers
+        let before = worker_ids(&state, &app_id);
+        state.reconcile().await.unwrap();
+        assert_eq!(worker_ids(&state, &app_id), before);
+
+        let mut app = state.get_app(&app_id).unwrap().unwrap();
+        app.replicas = 1;
+        state.update_app(&app_id, app).unwrap();
+        state.reconcile().await.unwrap();
+        assert_eq!(worker_ids(&state, &app_id).len(), 1);
+        assert_eq!(worker_ids(&state, &app_id), backend_ids(&backend));
+    }
+
+    #[tokio::test]
+    async fn exited_workers_are_restarted() {
+        let backend = FakeBackend::default();
+        let (state, _dir) = state(backend.clone()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+        let v1 = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &v1.id, "test").unwrap();
+        state.reconcile().await.unwrap();
+        let old = worker_ids(&state, &app_id);
+
+        backend.workers.lock().unwrap().insert(old[0].clone(), WorkerStatus::Exited);
+        state.reconcile().await.unwrap();
+        let new = worker_ids(&state, &app_id);
+        assert_eq!(new.len(), 1);
+        assert_ne!(new, old);
+        assert_eq!(new, backend_ids(&backend));
+    }
+
+    #[tokio::test]
+    async fn workers_of_deleted_apps_are_removed() {
+        let backend = FakeBackend::default();
+        let (state, _dir) = state(backend.clone()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+        let v1 = artifact(&state, &app_id).await;
+        state.deploy_artifact(&app_id, &v1.id, "test").unwrap();
+        state.reconcile().await.unwrap();
+        assert_eq!(backend_ids(&backend).len(), 1);
+
+        state.delete_app(&app_id).await.unwrap();
+        state.reconcile().await.unwrap();
+        assert!(backend_ids(&backend).is_empty());
+        assert!(worker_ids(&state, &app_id).is_empty());
+    }
+
+    #[tokio::test]
+    async fn workers_being_started_are_not_orphaned() {
+        let backend = FakeBackend::default();
+        let (state, _dir) = state(backend.clone()).await;
+        backend.workers.lock().unwrap().insert("new".to_string(), WorkerStatus::Running);
+
+        // created in the backend, but not stored yet
+        let starting = Starting::new(&state, "new");
+        assert!(!state.is_orphaned("new").unwrap());
+
+        state.set_worker("new", worker("app")).unwrap();
+        drop(starting);
+        assert!(!state.is_orphaned("new").unwrap());
+
+        state.delete_worker("new").unwrap();
+        assert!(state.is_orphaned("new").unwrap());
+    }
+
+    #[tokio::test]
+    async fn orphaned_workers_are_removed() {
+        let backend = FakeBackend::default();
+        let (state, _dir) = state(backend.clone()).await;
+        backend.workers.lock().unwrap().insert("orphan".to_string(), WorkerStatus::Running);
+
+        state.reconcile().await.unwrap();
+        assert!(backend.workers.lock().unwrap().is_empty());
+    }
+}
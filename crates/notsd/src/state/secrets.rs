@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::{bail, ContextCompat, Result};
+use nots_client::{models::SecretInfo, EncryptedBytes};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use zeroize::Zeroizing;
+
+use super::AppStateInner;
+
+/// Prefix of env vars that reference a secret, e.g. `secrets://db-password`
+const SECRET_PREFIX: &str = "secrets://";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredSecret {
+    pub value: EncryptedBytes, // encrypted with `kw_secret`, the key is used as the salt
+    pub created_at: time::OffsetDateTime,
+    pub updated_at: time::OffsetDateTime,
+}
+
+impl AppStateInner {
+    /// Create or replace a secret
+    pub(crate) fn set_secret(&self, app_id: &str, name: &str, value: Zeroizing<Vec<u8>>) -> Result<SecretInfo> {
+        let key = secret_key(app_id, name);
+        let now = time::OffsetDateTime::now_utc();
+        let created_at = self.secrets.get(&key)?.map(|s| s.created_at).unwrap_or(now);
+
+        let secret = StoredSecret {
+            value: self.kw_secret.encrypt(value, &key)?,
+            created_at,
+            updated_at: now,
+        };
+
+        self.secrets.set(&key, &secret)?;
+        info!("Stored secret {} for app {}", name, app_id);
+        Ok(secret_info(name, &secret))
+    }
+
+    /// Decrypt a secret, `None` if it doesn't exist
+    pub(crate) fn get_secret(&self, app_id: &str, name: &str) -> Result<Option<Zeroizing<Vec<u8>>>> {
+        let key = secret_key(app_id, name);
+        match self.secrets.get(&key)? {
+            Some(secret) => Ok(Some(self.kw_secret.decrypt(&secret.value, &key)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// All secrets of an app, sorted by name
+    pub(crate) fn get_secrets(&self, app_id: &str) -> Result<Vec<SecretInfo>> {
+        let prefix = format!("{}/", app_id);
+        let mut secrets: Vec<SecretInfo> = self
+            .secrets
+            .iter_prefix::<String>(&prefix)?
+            .filter_map(|res| res.ok())
+            .map(|(key, secret)| secret_info(key.trim_start_matches(&prefix), &secret))
+            .collect();
+
+        secrets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(secrets)
+    }
+
+    /// Returns `false` if the secret didn't exist
+    pub(crate) fn delete_secret(&self, app_id: &str, name: &str) -> Result<bool> {
+        let key = secret_key(app_id, name);
+        if self.secrets.get(&key)?.is_none() {
+            return Ok(false);
+        }
+
+        self.secrets.delete(&key)?;
+        info!("Deleted secret {} of app {}", name, app_id);
+        Ok(true)
+    }
+
+    /// Secrets referenced in `env` that don't exist, sorted by name
+    pub(crate) fn missing_secrets(&self, app_id: &str, env: &HashMap<String, String>) -> Result<Vec<String>> {
+        let mut missing = Vec::new();
+        for name in env.values().filter_map(|v| v.strip_prefix(SECRET_PREFIX)) {
+            if self.secrets.get(&secret_key(app_id, name))?.is_none() {
+                missing.push(name.to_string());
+            }
+        }
+
+        missing.sort();
+        missing.dedup();
+        Ok(missing)
+    }
+
+    /// Replace `secrets://<name>` references in `env` with the decrypted secrets
+    pub(crate) fn resolve_env(
+        &self,
+        app_id: &str,
+        env: &HashMap<String, String>,
+    ) -> Result<Vec<(String, Zeroizing<String>)>> {
+        let missing = self.missing_secrets(app_id, env)?;
+        if !missing.is_empty() {
+            bail!("Missing secrets: {}", missing.join(", "));
+        }
+
+        let mut resolved = Vec::with_capacity(env.len());
+        for (key, value) in env {
+            let value = match value.strip_prefix(SECRET_PREFIX) {
+                Some(name) => {
+                    let mut secret = self.get_secret(app_id, name)?.context("Secret was deleted")?;
+                    // validate first, `secret` is zeroized when dropped on error
+                    if std::str::from_utf8(&secret).is_err() {
+                        bail!("Secret {} is not valid UTF-8", name);
+                    }
+
+                    // move the bytes out instead of copying them so only one buffer holds the plaintext
+                    let bytes = std::mem::take(&mut *secret);
+                    Zeroizing::new(String::from_utf8(bytes).expect("validated above"))
+                }
+                None => Zeroizing::new(value.clone()),
+            };
+            resolved.push((key.clone(), value));
+        }
+
+        Ok(resolved)
+    }
+
+    pub(crate) fn delete_secrets(&self, app_id: &str) -> Result<()> {
+        for secret in self.get_secrets(app_id)? {
+            self.secrets.delete(&secret_key(app_id, &secret.name))?;
+        }
+        Ok(())
+    }
+}
+
+fn secret_info(name: &str, secret: &StoredSecret) -> SecretInfo {
+    SecretInfo {
+        name: name.to_string(),
+        created_at: secret.created_at,
+        updated_at: secret.updated_at,
+    }
+}
+
+fn secret_key(app_id: &str, name: &str) -> String {
+    format!("{}/{}", app_id, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::{app, state, FakeBackend};
+
+    #[tokio::test]
+    async fn secrets_round_trip() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+
+        state.set_secret(&app_id, "db-password", Zeroizing::new(b"hunter2".to_vec())).unwrap();
+        let value = state.get_secret(&app_id, "db-password").unwrap().unwrap();
+        assert_eq!(value.as_slice(), b"hunter2");
+        // stored encrypted
+        let stored = state.secrets.get(&secret_key(&app_id, "db-password")).unwrap().unwrap();
+        assert!(!stored.value.0.windows(7).any(|w| w == b"hunter2"));
+
+        let env = HashMap::from([
+            ("DB_PASSWORD".to_string(), "secrets://db-password".to_string()),
+            ("PLAIN".to_string(), "value".to_string()),
+        ]);
+        let mut resolved: Vec<(String, String)> = state
+            .resolve_env(&app_id, &env)
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k, v.to_string()))
+            .collect();
+        resolved.sort();
+        assert_eq!(
+            resolved,
+            [("DB_PASSWORD".to_string(), "hunter2".to_string()), ("PLAIN".to_string(), "value".to_string())]
+        );
+
+        assert!(state.delete_secret(&app_id, "db-password").unwrap());
+        assert!(state.get_secret(&app_id, "db-password").unwrap().is_none());
+        assert_eq!(state.missing_secrets(&app_id, &env).unwrap(), ["db-password"]);
+        assert!(state.resolve_env(&app_id, &env).is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_secrets_are_not_resolved() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let app_id = state.create_app(app("web")).unwrap().unwrap();
+
+        state.set_secret(&app_id, "binary", Zeroizing::new(vec![0xff, 0xfe])).unwrap();
+        let env = HashMap::from([("KEY".to_string(), "secrets://binary".to_string())]);
+        assert!(state.resolve_env(&app_id, &env).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+use blake2::{Blake2s256, Digest};
+use color_eyre::eyre::{eyre, Context, Result};
+use nots_client::models::{TokenInfo, TokenScope};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+use super::AppStateInner;
+
+/// Prefix of all API tokens, makes them easy to recognize (and to scan for in leaked code)
+const TOKEN_PREFIX: &str = "nots_";
+
+/// File the initial admin token is written to, relative to the file store
+const INITIAL_TOKEN_FILE: &str = "initial-token";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredToken {
+    pub name: String,
+    pub hash: Vec<u8>, // blake2s of the token secret, tokens are random so a slow hash isn't needed
+    pub scope: TokenScope,
+    pub project: Option<String>,
+    pub created_at: time::OffsetDateTime,
+}
+
+impl AppStateInner {
+    /// Without any tokens the API would be unusable, so an admin token is created and written to a private file once
+    pub(super) fn ensure_initial_token(&self) -> Result<()> {
+        if self.tokens.iter()?.next().is_some() {
+            return Ok(());
+        }
+
+        let (token, _) = self.create_token("initial", TokenScope::Admin, None)?;
+        let path = self.file.local_path(INITIAL_TOKEN_FILE);
+        let _ = std::fs::remove_file(&path);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .wrap_err("Could not write initial token")?;
+        file.write_all(token.as_bytes())?;
+
+        warn!(
+            "No API tokens found, created an admin token in {}. Delete the file once the token is stored elsewhere",
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Create a new token, the returned secret token is not stored anywhere
+    pub(crate) fn create_token(
+        &self,
+        name: &str,
+        scope: TokenScope,
+        project: Option<String>,
+    ) -> Result<(Zeroizing<String>, TokenInfo)> {
+        let id = cuid2::cuid();
+        let mut secret = Zeroizing::new([0u8; 32]);
+        getrandom::getrandom(&mut *secret).map_err(|e| eyre!("Could not generate token: {}", e))?;
+        let secret = Zeroizing::new(hex::encode(*secret));
+
+        let token = StoredToken {
+            name: name.to_string(),
+            hash: hash(&secret),
+            scope,
+            project,
+            created_at: time::OffsetDateTime::now_utc(),
+        };
+
+        self.tokens.set(&id, &token)?;
+        info!("Created {} token {} ({})", scope, name, id);
+        let info = token_info(&id, token);
+        Ok((
+            Zeroizing::new(format!("{}{}_{}", TOKEN_PREFIX, id, secret.as_str())),
+            info,
+        ))
+    }
+
+    /// Check a bearer token, `None` if it is invalid or was revoked
+    pub(crate) fn verify_token(&self, token: &str) -> Result<Option<TokenInfo>> {
+        let Some((id, secret)) = token.strip_prefix(TOKEN_PREFIX).and_then(|t| t.split_once('_')) else {
+            return Ok(None);
+        };
+
+        let Some(stored) = self.tokens.get(id)? else {
+            return Ok(None);
+        };
+
+        match bool::from(hash(secret).ct_eq(&stored.hash)) {
+            true => Ok(Some(token_info(id, stored))),
+            false => Ok(None),
+        }
+    }
+
+    /// All tokens, sorted by creation time
+    pub(crate) fn get_tokens(&self) -> Result<Vec<TokenInfo>> {
+        let mut tokens: Vec<TokenInfo> = self
+            .tokens
+            .iter()?
+            .filter_map(|res| res.ok())
+            .map(|(id, token)| token_info(&id, token))
+            .collect();
+
+        tokens.sort_by_key(|t| t.created_at);
+        Ok(tokens)
+    }
+
+    /// Returns `false` if the token didn't exist
+    pub(crate) fn revoke_token(&self, id: &str) -> Result<bool> {
+        if self.tokens.get(id)?.is_none() {
+            return Ok(false);
+        }
+
+        self.tokens.delete(id)?;
+        info!("Revoked token {}", id);
+        Ok(true)
+    }
+}
+
+fn hash(secret: &str) -> Vec<u8> {
+    Blake2s256::digest(secret.as_bytes()).to_vec()
+}
+
+fn token_info(id: &str, token: StoredToken) -> TokenInfo {
+    TokenInfo {
+        id: id.to_string(),
+        name: token.name,
+        scope: token.scope,
+        project: token.project,
+        created_at: token.created_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::state::tests::{state, FakeBackend};
+
+    #[tokio::test]
+    async fn initial_token_is_only_readable_by_the_owner() {
+        let (state, _dir) = state(FakeBackend::default()).await;
+        let path = state.file.local_path(INITIAL_TOKEN_FILE);
+
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let token = std::fs::read_to_string(&path).unwrap();
+        let info = state.verify_token(&token).unwrap().unwrap();
+        assert_eq!(info.scope, TokenScope::Admin);
+    }
+}
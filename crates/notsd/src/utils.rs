@@ -15,8 +15,15 @@ use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
 pub(crate) struct UdsConnectInfo {
-    peer_addr: Arc<tokio::net::unix::SocketAddr>,
-    peer_cred: UCred,
+    pub(crate) peer_addr: Arc<tokio::net::unix::SocketAddr>,
+    pub(crate) peer_cred: UCred,
+}
+
+/// Ownership of the API socket, callers with this uid or in this group are trusted
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SocketOwner {
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
 }
 
 impl connect_info::Connected<&UnixStream> for UdsConnectInfo {